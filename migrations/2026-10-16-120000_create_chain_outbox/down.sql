@@ -0,0 +1 @@
+DROP TABLE chain_outbox;
\ No newline at end of file
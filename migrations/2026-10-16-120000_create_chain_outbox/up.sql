@@ -0,0 +1,16 @@
+CREATE TABLE chain_outbox (
+  id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+  idempotency_key TEXT UNIQUE NOT NULL,
+  session_id uuid NOT NULL,
+  FOREIGN KEY(session_id)
+    REFERENCES sessions,
+  operation JSONB NOT NULL,
+  status VARCHAR NOT NULL DEFAULT 'pending', --pending, running, done or dead
+  attempts INTEGER NOT NULL DEFAULT 0,
+  last_error TEXT,
+  next_attempt_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  completed_at TIMESTAMP
+);
+
+CREATE INDEX chain_outbox_due ON chain_outbox (status, next_attempt_at);
@@ -0,0 +1,3 @@
+ALTER TABLE sessions DROP COLUMN snapshot_seq;
+
+DROP TABLE session_events;
\ No newline at end of file
@@ -0,0 +1,13 @@
+CREATE TABLE session_events (
+  session_id uuid NOT NULL,
+  FOREIGN KEY(session_id)
+    REFERENCES sessions,
+  seq BIGINT NOT NULL,
+  PRIMARY KEY(session_id, seq),
+  kind VARCHAR NOT NULL,
+  event JSONB NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+--last event folded into sessions.state, later events are replayed on restore
+ALTER TABLE sessions ADD COLUMN snapshot_seq BIGINT NOT NULL DEFAULT 0;
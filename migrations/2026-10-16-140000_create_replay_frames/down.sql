@@ -0,0 +1 @@
+DROP TABLE replay_frames;
\ No newline at end of file
@@ -0,0 +1,11 @@
+CREATE TABLE replay_frames (
+  id BIGSERIAL PRIMARY KEY,
+  session_id uuid NOT NULL,
+  FOREIGN KEY(session_id)
+    REFERENCES sessions,
+  tick BIGINT NOT NULL,
+  frame JSONB NOT NULL,
+  recorded_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX replay_frames_session ON replay_frames (session_id, recorded_at);
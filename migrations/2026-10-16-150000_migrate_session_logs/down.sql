@@ -0,0 +1,16 @@
+ALTER TABLE sessions ADD COLUMN logs JSONB NOT NULL DEFAULT '{}';
+
+UPDATE sessions SET logs = history.logs
+FROM (
+  SELECT session_id, jsonb_object_agg(to_char(created_at, 'YYYY-MM-DD"T"HH24:MI:SS.US'), event) AS logs
+  FROM session_events
+  WHERE kind NOT IN ('spawned', 'updated', 'killed', 'stats', 'scene')
+  GROUP BY session_id
+) history
+WHERE sessions.id = history.session_id;
+
+DELETE FROM session_events WHERE seq <= 0;
+
+DROP INDEX session_events_user_id;
+
+ALTER TABLE session_events DROP COLUMN user_id;
@@ -0,0 +1,49 @@
+ALTER TABLE session_events ADD COLUMN user_id VARCHAR(50);
+
+UPDATE session_events SET user_id = coalesce(event->>'user_id', event->>'sender');
+
+CREATE INDEX session_events_user_id ON session_events (session_id, user_id);
+
+--old log entries are numbered at or below zero so they are never replayed on restore
+WITH logged AS (
+  SELECT
+    sessions.id AS session_id,
+    1 - row_number() OVER (PARTITION BY sessions.id ORDER BY entry.key::timestamp DESC) AS seq,
+    entry.key::timestamp AS created_at,
+    entry.value AS value,
+    CASE WHEN jsonb_typeof(entry.value) = 'string' THEN entry.value #>> '{}' END AS line
+  FROM sessions, jsonb_each(sessions.logs) AS entry
+),
+typed AS (
+  SELECT
+    session_id,
+    seq,
+    created_at,
+    CASE
+      WHEN value->>'msg_type' = 'message' THEN jsonb_build_object(
+        'kind', 'chat',
+        'sender', value->'content'->'sender',
+        'msg', value->'content'->'msg'
+      )
+      WHEN value->>'msg_type' = 'left' THEN jsonb_build_object(
+        'kind', 'left',
+        'user_id', value->'content'->'user_id'
+      )
+      WHEN line LIKE '% joined.' THEN jsonb_build_object(
+        'kind', 'joined',
+        'user_id', substring(line FROM '^(.*) joined\.$')
+      )
+      WHEN line LIKE '% was kicked by %.' THEN jsonb_build_object(
+        'kind', 'kicked',
+        'user_id', substring(line FROM '^(.*) was kicked by '),
+        'by', substring(line FROM ' was kicked by (.*)\.$')
+      )
+      ELSE jsonb_build_object('kind', 'legacy', 'entry', value)
+    END AS event
+  FROM logged
+)
+INSERT INTO session_events (session_id, seq, kind, event, created_at, user_id)
+SELECT session_id, seq, event->>'kind', event, created_at, coalesce(event->>'user_id', event->>'sender')
+FROM typed;
+
+ALTER TABLE sessions DROP COLUMN logs;
@@ -0,0 +1,2 @@
+ALTER TABLE chain_outbox DROP COLUMN tx_hash;
+ALTER TABLE chain_outbox DROP COLUMN tx_signer;
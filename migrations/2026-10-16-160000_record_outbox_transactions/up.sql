@@ -0,0 +1,3 @@
+--the last transaction signed for an entry, recorded before it is broadcast
+ALTER TABLE chain_outbox ADD COLUMN tx_signer TEXT;
+ALTER TABLE chain_outbox ADD COLUMN tx_hash TEXT;
@@ -0,0 +1,2 @@
+DELETE FROM chain_outbox WHERE session_id IS NULL;
+ALTER TABLE chain_outbox ALTER COLUMN session_id SET NOT NULL;
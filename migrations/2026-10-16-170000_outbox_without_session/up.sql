@@ -0,0 +1,2 @@
+--pools whose session failed to insert are still deactivated through the outbox
+ALTER TABLE chain_outbox ALTER COLUMN session_id DROP NOT NULL;
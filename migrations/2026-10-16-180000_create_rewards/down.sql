@@ -0,0 +1,12 @@
+ALTER TABLE accounts ADD COLUMN rewards JSONB NOT NULL DEFAULT '{}';
+
+--rewards logged within the same instant collapse into one entry
+UPDATE accounts SET rewards = logged.rewards
+FROM (
+  SELECT account_id, jsonb_object_agg(replace(created_at::text, ' ', 'T'), reward) AS rewards
+  FROM rewards
+  GROUP BY account_id
+) AS logged
+WHERE accounts.account_id = logged.account_id;
+
+DROP TABLE rewards;
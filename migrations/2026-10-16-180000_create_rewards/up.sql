@@ -0,0 +1,18 @@
+CREATE TABLE rewards (
+  id BIGSERIAL PRIMARY KEY,
+  account_id VARCHAR NOT NULL,
+  FOREIGN KEY(account_id)
+    REFERENCES accounts,
+  reward JSONB NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX rewards_account ON rewards (account_id, id);
+
+--logged rewards are inserted oldest first so ids keep their order
+INSERT INTO rewards (account_id, reward, created_at)
+SELECT accounts.account_id, entry.value, entry.key::timestamp
+FROM accounts, jsonb_each(accounts.rewards) AS entry
+ORDER BY entry.key::timestamp;
+
+ALTER TABLE accounts DROP COLUMN rewards;
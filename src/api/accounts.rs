@@ -0,0 +1,229 @@
+use std::io::ErrorKind;
+
+use actix_web::{web, HttpResponse};
+use diesel::{insert_into, prelude::*};
+use near_crypto::{PublicKey, Signature};
+use near_primitives::types::AccountId;
+use serde::Deserialize;
+
+use crate::{
+    auth::{issue_challenge, redeem_challenge, verify_signature},
+    db::{
+        connection,
+        models::{Account, NewAccount},
+        schema, Identity,
+    },
+    handlers::{
+        chain::chain,
+        messages::ServerError,
+        wallet::{reward_history, REWARDS_PAGE},
+    },
+    types::UserId,
+};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChallengeRequest {
+    pub account_id: AccountId,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RewardsQuery {
+    #[serde(default)]
+    pub account_id: Option<AccountId>,
+    #[serde(default)]
+    pub offset: usize,
+    #[serde(default = "RewardsQuery::default_limit")]
+    pub limit: usize,
+}
+
+impl RewardsQuery {
+    fn default_limit() -> usize {
+        REWARDS_PAGE
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LinkAccount {
+    pub account_id: AccountId,
+    pub public_key: PublicKey,
+    pub signature: Signature,
+}
+
+pub async fn list_accounts(identity: web::ReqData<Identity>) -> Result<HttpResponse, ServerError> {
+    let mut conn = connection()?;
+
+    use schema::accounts::dsl::{accounts, last_active, user_id};
+
+    match accounts
+        .filter(user_id.eq(&identity.user_id))
+        .order(last_active.desc().nulls_last())
+        .load::<Account>(&mut conn)
+    {
+        Ok(linked) => Ok(HttpResponse::Ok().json(linked)),
+
+        Err(e) => Err(ServerError::Database(e)),
+    }
+}
+
+pub async fn list_rewards(
+    identity: web::ReqData<Identity>,
+    query: web::Query<RewardsQuery>,
+) -> Result<HttpResponse, ServerError> {
+    let RewardsQuery {
+        account_id,
+        offset,
+        limit,
+    } = query.into_inner();
+
+    let mut conn = connection()?;
+
+    let history = reward_history(&identity.user_id, account_id, offset, limit, &mut conn)?;
+
+    Ok(HttpResponse::Ok().json(history))
+}
+
+pub async fn challenge(
+    identity: web::ReqData<Identity>,
+    body: web::Json<ChallengeRequest>,
+) -> Result<HttpResponse, ServerError> {
+    let (message, lifetime) = issue_challenge(&identity.user_id, &body.account_id);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "challenge": message,
+        "expires_in": lifetime.as_secs(),
+    })))
+}
+
+//the challenge has to be signed with a full access key the account holds on chain
+pub async fn verify_link(
+    uid: &UserId,
+    account_id: &AccountId,
+    public_key: PublicKey,
+    signature: &Signature,
+) -> Result<(), ServerError> {
+    let message = match redeem_challenge(uid, account_id) {
+        Some(message) => message,
+
+        None => {
+            return Err(ServerError::new(
+                ErrorKind::PermissionDenied,
+                &format!("No pending challenge for {}", account_id),
+            ))
+        }
+    };
+
+    if !verify_signature(&message, &public_key, signature) {
+        return Err(ServerError::new(
+            ErrorKind::PermissionDenied,
+            &format!("Invalid signature for {}", account_id),
+        ));
+    }
+
+    chain()
+        .verify_access_key(account_id.to_owned(), public_key)
+        .await
+}
+
+pub async fn link_account(
+    identity: web::ReqData<Identity>,
+    body: web::Json<LinkAccount>,
+) -> Result<HttpResponse, ServerError> {
+    let LinkAccount {
+        account_id,
+        public_key,
+        signature,
+    } = body.into_inner();
+
+    verify_link(&identity.user_id, &account_id, public_key, &signature).await?;
+
+    let mut conn = connection()?;
+
+    use schema::accounts::dsl::{account_id as aid, accounts};
+
+    match accounts
+        .filter(aid.eq(account_id.as_str()))
+        .get_result::<Account>(&mut conn)
+    {
+        Ok(account) if account.user_id == identity.user_id => {
+            return Ok(HttpResponse::Ok().json(account))
+        }
+
+        Ok(_) => {
+            return Err(ServerError::new(
+                ErrorKind::AlreadyExists,
+                &format!("{} is linked to another user", &account_id),
+            ))
+        }
+
+        Err(diesel::result::Error::NotFound) => {}
+
+        Err(e) => return Err(ServerError::Database(e)),
+    }
+
+    match insert_into(accounts)
+        .values(&NewAccount {
+            account_id: account_id.to_string(),
+            user_id: identity.user_id.to_owned(),
+        })
+        .get_result::<Account>(&mut conn)
+    {
+        Ok(account) => Ok(HttpResponse::Created().json(account)),
+
+        Err(e) => Err(ServerError::Database(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use near_crypto::{KeyType, SecretKey};
+    use near_primitives::types::AccountId;
+
+    use crate::{
+        auth::issue_challenge,
+        handlers::memory_chain::{install, MemoryChain},
+    };
+
+    use super::verify_link;
+
+    #[actix::test]
+    async fn links_need_a_signed_challenge_and_a_full_access_key() {
+        let uid = "linker".to_string();
+
+        let account_id = AccountId::from_str("linker.testnet").unwrap();
+
+        let key = SecretKey::from_random(KeyType::ED25519);
+
+        let stranger = SecretKey::from_random(KeyType::ED25519);
+
+        let chain = MemoryChain::new().with_access_key(account_id.to_owned(), key.public_key());
+
+        let _installed = install(&chain).await;
+
+        let (message, _) = issue_challenge(&uid, &account_id);
+
+        let signature = stranger.sign(message.as_bytes());
+
+        assert!(
+            verify_link(&uid, &account_id, stranger.public_key(), &signature)
+                .await
+                .is_err()
+        );
+
+        //the failed attempt used up the challenge
+        let signature = key.sign(message.as_bytes());
+
+        assert!(verify_link(&uid, &account_id, key.public_key(), &signature)
+            .await
+            .is_err());
+
+        let (message, _) = issue_challenge(&uid, &account_id);
+
+        let signature = key.sign(message.as_bytes());
+
+        assert!(verify_link(&uid, &account_id, key.public_key(), &signature)
+            .await
+            .is_ok());
+    }
+}
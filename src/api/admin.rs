@@ -0,0 +1,107 @@
+use std::io::ErrorKind;
+
+use actix_web::{web, HttpResponse};
+use chrono::Local;
+use diesel::{prelude::*, update};
+use serde::Deserialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::{
+    auth::{require, Permission},
+    db::{connection, models::OutboxEntry, schema, Identity},
+    handlers::{
+        chain::chain,
+        messages::ServerError,
+        outbox::{ProcessOutbox, DEAD, PENDING},
+        OUTBOX,
+    },
+};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OutboxQuery {
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub session_id: Option<Uuid>,
+}
+
+pub async fn set_attributes(
+    identity: web::ReqData<Identity>,
+    body: web::Json<Value>,
+) -> Result<HttpResponse, ServerError> {
+    require(&identity.roles, Permission::SetAttributes)?;
+
+    chain().set_default_attributes(body.into_inner()).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub async fn list_outbox(
+    identity: web::ReqData<Identity>,
+    query: web::Query<OutboxQuery>,
+) -> Result<HttpResponse, ServerError> {
+    require(&identity.roles, Permission::ManageOutbox)?;
+
+    let OutboxQuery {
+        status: filter_status,
+        session_id: filter_session,
+    } = query.into_inner();
+
+    let mut conn = connection()?;
+
+    use schema::chain_outbox::dsl::{chain_outbox, created_at, session_id, status};
+
+    let mut entries = chain_outbox.order(created_at.desc()).into_boxed();
+
+    if let Some(s) = filter_status {
+        entries = entries.filter(status.eq(s));
+    }
+
+    if let Some(sid) = filter_session {
+        entries = entries.filter(session_id.eq(sid));
+    }
+
+    match entries.load::<OutboxEntry>(&mut conn) {
+        Ok(entries) => Ok(HttpResponse::Ok().json(entries)),
+
+        Err(e) => Err(ServerError::Database(e)),
+    }
+}
+
+//dead entries start over with a fresh attempt count
+pub async fn replay_outbox(
+    identity: web::ReqData<Identity>,
+    path: web::Path<Uuid>,
+) -> Result<HttpResponse, ServerError> {
+    require(&identity.roles, Permission::ManageOutbox)?;
+
+    let entry_id = path.into_inner();
+
+    let mut conn = connection()?;
+
+    use schema::chain_outbox::dsl::{attempts, chain_outbox, id, next_attempt_at, status};
+
+    match update(chain_outbox)
+        .filter(id.eq(&entry_id).and(status.eq(DEAD)))
+        .set((
+            status.eq(PENDING),
+            attempts.eq(0),
+            next_attempt_at.eq(Local::now().naive_local()),
+        ))
+        .get_result::<OutboxEntry>(&mut conn)
+    {
+        Ok(entry) => {
+            OUTBOX.do_send(ProcessOutbox);
+
+            Ok(HttpResponse::Ok().json(entry))
+        }
+
+        Err(diesel::result::Error::NotFound) => Err(ServerError::new(
+            ErrorKind::NotFound,
+            &format!("No dead chain operation {}", &entry_id),
+        )),
+
+        Err(e) => Err(ServerError::Database(e)),
+    }
+}
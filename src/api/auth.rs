@@ -0,0 +1,155 @@
+use std::io::ErrorKind;
+
+use actix_web::{web, HttpResponse};
+use chrono::{Local, NaiveDateTime};
+use diesel::{insert_into, prelude::*, update};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::{generate_token, hash_password, issue_ticket, verify_password},
+    config::auth,
+    db::{
+        connection,
+        models::{NewUser, NewUserSession, User},
+        schema, Identity,
+    },
+    handlers::messages::ServerError,
+    types::UserId,
+};
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Register {
+    pub id: UserId,
+    pub email: String,
+    pub password: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Login {
+    //user id or email
+    pub login: String,
+    pub password: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IssuedToken {
+    pub auth_token: String,
+    pub user_id: UserId,
+    pub expires_at: NaiveDateTime,
+}
+
+pub async fn register(body: web::Json<Register>) -> Result<HttpResponse, ServerError> {
+    let Register {
+        id,
+        email,
+        password,
+    } = body.into_inner();
+
+    if password.len() < MIN_PASSWORD_LENGTH {
+        return Err(ServerError::new(
+            ErrorKind::InvalidInput,
+            &format!(
+                "Password must be at least {} characters",
+                MIN_PASSWORD_LENGTH
+            ),
+        ));
+    }
+
+    let mut conn = connection()?;
+
+    use schema::users::dsl::users;
+
+    match insert_into(users)
+        .values(&NewUser {
+            id,
+            email,
+            password: hash_password(&password)?,
+        })
+        .get_result::<User>(&mut conn)
+    {
+        Ok(User { id, email, .. }) => Ok(HttpResponse::Created().json(serde_json::json!({
+            "id": id,
+            "email": email,
+        }))),
+
+        Err(e) => Err(ServerError::Database(e)),
+    }
+}
+
+pub async fn login(body: web::Json<Login>) -> Result<HttpResponse, ServerError> {
+    let Login { login, password } = body.into_inner();
+
+    let mut conn = connection()?;
+
+    use schema::users::dsl::{email, id, last_login, users};
+
+    let user = match users
+        .filter(id.eq(&login).or(email.eq(&login)))
+        .get_result::<User>(&mut conn)
+    {
+        Ok(user) if verify_password(&password, &user.password) => user,
+
+        Ok(_) | Err(diesel::result::Error::NotFound) => {
+            return Err(ServerError::new(
+                ErrorKind::PermissionDenied,
+                "Invalid login or password",
+            ))
+        }
+
+        Err(e) => return Err(ServerError::Database(e)),
+    };
+
+    let token = generate_token();
+
+    let now = Local::now().naive_local();
+
+    use schema::user_sessions::dsl::user_sessions;
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        insert_into(user_sessions)
+            .values(&NewUserSession {
+                auth_token: token.to_owned(),
+                user_id: user.id.to_owned(),
+            })
+            .execute(conn)?;
+
+        update(users)
+            .filter(id.eq(&user.id))
+            .set(last_login.eq(now))
+            .execute(conn)
+    })
+    .map_err(ServerError::Database)?;
+
+    Ok(HttpResponse::Ok().json(IssuedToken {
+        auth_token: token,
+        user_id: user.id,
+        expires_at: now + auth().token_lifetime,
+    }))
+}
+
+pub async fn logout(identity: web::ReqData<Identity>) -> Result<HttpResponse, ServerError> {
+    let mut conn = connection()?;
+
+    use schema::user_sessions::dsl::{auth_token, ended_at, user_sessions};
+
+    match update(user_sessions)
+        .filter(auth_token.eq(&identity.auth_token))
+        .set(ended_at.eq(Local::now().naive_local()))
+        .execute(&mut conn)
+    {
+        Ok(_) => Ok(HttpResponse::NoContent().finish()),
+
+        Err(e) => Err(ServerError::Database(e)),
+    }
+}
+
+pub async fn ticket(identity: web::ReqData<Identity>) -> Result<HttpResponse, ServerError> {
+    let (ticket, lifetime) = issue_ticket(&identity);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "ticket": ticket,
+        "expires_in": lifetime.as_secs(),
+    })))
+}
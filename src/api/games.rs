@@ -0,0 +1,50 @@
+use actix_web::{web, HttpResponse};
+use chrono::NaiveDateTime;
+use diesel::{insert_into, prelude::*};
+use serde::Deserialize;
+
+use crate::{
+    auth::{require, Permission},
+    db::{
+        connection,
+        models::{Game, NewGame},
+        schema, Identity,
+    },
+    handlers::messages::ServerError,
+    types::{GameConfig, GameId},
+};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateGame {
+    pub id: GameId,
+    pub config: GameConfig,
+    #[serde(default)]
+    pub expiry: Option<NaiveDateTime>,
+}
+
+pub async fn create_game(
+    identity: web::ReqData<Identity>,
+    body: web::Json<CreateGame>,
+) -> Result<HttpResponse, ServerError> {
+    require(&identity.roles, Permission::CreateGame)?;
+
+    let CreateGame { id, config, expiry } = body.into_inner();
+
+    let mut conn = connection()?;
+
+    use schema::games::dsl::games;
+
+    match insert_into(games)
+        .values(&NewGame {
+            id,
+            creator: identity.user_id.to_owned(),
+            config,
+            expiry,
+        })
+        .get_result::<Game>(&mut conn)
+    {
+        Ok(game) => Ok(HttpResponse::Created().json(game)),
+
+        Err(e) => Err(ServerError::Database(e)),
+    }
+}
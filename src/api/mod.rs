@@ -0,0 +1,46 @@
+use actix_web::web;
+
+pub mod accounts;
+pub mod admin;
+pub mod auth;
+pub mod games;
+pub mod sessions;
+
+//routes reachable without an auth token
+pub fn public(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/register").route(web::post().to(auth::register)))
+        .service(web::resource("/login").route(web::post().to(auth::login)));
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/logout").route(web::post().to(auth::logout)))
+        .service(web::resource("/ticket").route(web::post().to(auth::ticket)))
+        .service(
+            web::resource("/accounts")
+                .route(web::get().to(accounts::list_accounts))
+                .route(web::post().to(accounts::link_account)),
+        )
+        .service(web::resource("/accounts/challenge").route(web::post().to(accounts::challenge)))
+        .service(web::resource("/accounts/rewards").route(web::get().to(accounts::list_rewards)))
+        .service(web::resource("/games").route(web::post().to(games::create_game)))
+        .service(
+            web::resource("/sessions")
+                .route(web::get().to(sessions::list_sessions))
+                .route(web::post().to(sessions::open_session)),
+        )
+        .service(
+            web::resource("/sessions/{session_id}/players")
+                .route(web::post().to(sessions::register_player))
+                .route(web::patch().to(sessions::select_account)),
+        )
+        .service(
+            web::resource("/sessions/{session_id}/history")
+                .route(web::get().to(sessions::list_history)),
+        )
+        .service(web::resource("/admin/attributes").route(web::post().to(admin::set_attributes)))
+        .service(web::resource("/admin/outbox").route(web::get().to(admin::list_outbox)))
+        .service(
+            web::resource("/admin/outbox/{entry_id}/replay")
+                .route(web::post().to(admin::replay_outbox)),
+        );
+}
@@ -0,0 +1,477 @@
+use std::{collections::HashSet, io::ErrorKind};
+
+use actix_web::{web, HttpResponse};
+use chrono::{Local, NaiveDateTime};
+use diesel::{insert_into, prelude::*, update};
+use near_primitives::types::AccountId;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    auth::{hash_password, permitted, require, Permission},
+    db::{
+        connection, joinable_sessions,
+        models::{Game, NewPlayerSession, NewPoolRef, NewSession, PoolRef, Session, Whitelist},
+        player_counts, schema, session_history, HistoryFilter, Identity, HISTORY_PAGE,
+    },
+    handlers::{
+        chain::chain,
+        messages::{LobbyEvent, LobbyUpdate, ServerError},
+        outbox::enqueue,
+        wallet::owned_account,
+        GLOBAL,
+    },
+    types::{ChainOperation, GameId, PlayerInfo, SessionInfo, SessionState, UserId},
+};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OpenSession {
+    pub game_id: GameId,
+    #[serde(default)]
+    pub pool_id: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub private: bool,
+    //creates a pool for the session instead of using an existing pool_id
+    #[serde(default)]
+    pub pool: Option<SessionPool>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SessionPool {
+    //players the pool can resolve to, through their most recently active linked account
+    pub participants: HashSet<UserId>,
+    #[serde(default)]
+    pub required_xp: u128,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RegisterPlayer {
+    #[serde(default)]
+    pub account_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HistoryQuery {
+    #[serde(default)]
+    pub user_id: Option<UserId>,
+    //comma separated event kinds
+    #[serde(default)]
+    pub kind: Option<String>,
+    #[serde(default)]
+    pub from: Option<NaiveDateTime>,
+    #[serde(default)]
+    pub to: Option<NaiveDateTime>,
+    #[serde(default)]
+    pub offset: usize,
+    #[serde(default = "HistoryQuery::default_limit")]
+    pub limit: usize,
+}
+
+impl HistoryQuery {
+    fn default_limit() -> usize {
+        HISTORY_PAGE
+    }
+}
+
+pub async fn open_session(
+    identity: web::ReqData<Identity>,
+    body: web::Json<OpenSession>,
+) -> Result<HttpResponse, ServerError> {
+    let OpenSession {
+        game_id,
+        pool_id,
+        password,
+        private,
+        pool,
+    } = body.into_inner();
+
+    let mut conn = connection()?;
+
+    use schema::games::dsl::{games, id as gid};
+
+    let game = games
+        .filter(gid.eq(&game_id))
+        .get_result::<Game>(&mut conn)
+        .map_err(ServerError::Database)?;
+
+    let now = Local::now().naive_local();
+
+    if game.ended_at.is_some() || game.expiry.map_or(false, |expiry| expiry < now) {
+        return Err(ServerError::new(
+            ErrorKind::InvalidInput,
+            &format!("Game {} is no longer accepting sessions", &game_id),
+        ));
+    }
+
+    if let Some(pool) = &pool_id {
+        use schema::pools::dsl::{id as pid, pools};
+
+        match pools.filter(pid.eq(pool)).get_result::<PoolRef>(&mut conn) {
+            Ok(PoolRef {
+                resolved_at: None, ..
+            }) => {}
+
+            Ok(_) => {
+                return Err(ServerError::new(
+                    ErrorKind::InvalidInput,
+                    &format!("Pool {} has already been resolved", pool),
+                ))
+            }
+
+            Err(e) => return Err(ServerError::Database(e)),
+        }
+    }
+
+    let (pool_id, created, participants) = match (pool_id, pool) {
+        (Some(_), Some(_)) => {
+            return Err(ServerError::new(
+                ErrorKind::InvalidInput,
+                "Provide either pool_id or pool, not both",
+            ))
+        }
+
+        (
+            None,
+            Some(SessionPool {
+                participants,
+                required_xp,
+            }),
+        ) => {
+            require(&identity.roles, Permission::CreatePool)?;
+
+            if participants.is_empty() {
+                return Err(ServerError::new(
+                    ErrorKind::InvalidInput,
+                    "A session pool needs at least one participant",
+                ));
+            }
+
+            let results = participants
+                .iter()
+                .map(|uid| owned_account(uid, None, &mut conn))
+                .collect::<Result<HashSet<AccountId>, ServerError>>()?;
+
+            let pool_id = Uuid::new_v4().to_string();
+
+            chain()
+                .create_pool(pool_id.to_owned(), results, required_xp)
+                .await?;
+
+            (Some(pool_id.to_owned()), Some(pool_id), participants)
+        }
+
+        (pool_id, None) => (pool_id, None, HashSet::new()),
+    };
+
+    let password = match password {
+        Some(password) => Some(hash_password(&password)?),
+
+        None => None,
+    };
+
+    //a private session without its creator and participants on the whitelist could never be joined by them
+    let res = conn.transaction::<_, ServerError, _>(|conn| {
+        if let Some(created) = &created {
+            use schema::pools::dsl::pools;
+
+            insert_into(pools)
+                .values(&NewPoolRef {
+                    id: created.to_owned(),
+                })
+                .execute(conn)
+                .map_err(ServerError::Database)?;
+        }
+
+        use schema::sessions::dsl::sessions;
+
+        let session = insert_into(sessions)
+            .values(&NewSession {
+                game_id,
+                pool_id,
+                creator: identity.user_id.to_owned(),
+                password,
+                private,
+                state: SessionState::default(),
+            })
+            .get_result::<Session>(conn)
+            .map_err(ServerError::Database)?;
+
+        if session.private {
+            use schema::whitelist::dsl::whitelist;
+
+            let mut allowed = participants.to_owned();
+
+            allowed.insert(session.creator.to_owned());
+
+            insert_into(whitelist)
+                .values(
+                    &allowed
+                        .into_iter()
+                        .map(|user_id| Whitelist {
+                            session_id: session.id.to_owned(),
+                            user_id,
+                        })
+                        .collect::<Vec<Whitelist>>(),
+                )
+                .execute(conn)
+                .map_err(ServerError::Database)?;
+        }
+
+        Ok(session)
+    });
+
+    let session = match (res, created) {
+        (Ok(session), _) => session,
+
+        //the pool exists on chain without a session to play it, nobody may stake on it
+        (Err(e), Some(created)) => {
+            if let Err(e) = enqueue(
+                ChainOperation::DeactivatePool {
+                    session_id: None,
+                    pool_id: created.to_owned(),
+                },
+                &mut conn,
+            ) {
+                println!(
+                    "[Server] DB Error Deactivating Pool - {}: {}",
+                    &created,
+                    e.to_string()
+                )
+            }
+
+            return Err(e);
+        }
+
+        (Err(e), None) => return Err(e),
+    };
+
+    let info = SessionInfo::new(&session, &game.config, 0);
+
+    if !info.private {
+        GLOBAL.do_send(LobbyEvent(LobbyUpdate::Opened(info.to_owned())));
+    }
+
+    Ok(HttpResponse::Created().json(info))
+}
+
+pub async fn list_sessions(_: web::ReqData<Identity>) -> Result<HttpResponse, ServerError> {
+    let mut conn = connection()?;
+
+    let listed = joinable_sessions(&mut conn)?;
+
+    Ok(HttpResponse::Ok().json(listed))
+}
+
+fn check_linked(account: &str, uid: &UserId, conn: &mut PgConnection) -> Result<(), ServerError> {
+    use schema::accounts::dsl::{account_id as aid, accounts, user_id};
+
+    match accounts
+        .filter(aid.eq(account).and(user_id.eq(uid)))
+        .count()
+        .get_result::<i64>(conn)
+    {
+        Ok(0) => Err(ServerError::new(
+            ErrorKind::PermissionDenied,
+            &format!("{} is not linked to {}", account, uid),
+        )),
+
+        Ok(_) => Ok(()),
+
+        Err(e) => Err(ServerError::Database(e)),
+    }
+}
+
+pub async fn register_player(
+    identity: web::ReqData<Identity>,
+    path: web::Path<Uuid>,
+    body: web::Json<RegisterPlayer>,
+) -> Result<HttpResponse, ServerError> {
+    let session_id = path.into_inner();
+
+    let RegisterPlayer { account_id } = body.into_inner();
+
+    let mut conn = connection()?;
+
+    //the session row stays locked until the insert, concurrent registrations wait their turn
+    let (session, game, players) = conn.transaction::<_, ServerError, _>(|conn| {
+        use schema::games::dsl::games;
+        use schema::sessions::dsl::{id, sessions};
+
+        let (session, game) = sessions
+            .inner_join(games)
+            .filter(id.eq(&session_id))
+            .for_update()
+            .get_result::<(Session, Game)>(conn)
+            .map_err(ServerError::Database)?;
+
+        if session.ended_at.is_some() {
+            return Err(ServerError::new(
+                ErrorKind::InvalidInput,
+                &format!("Session {} has ended", &session_id),
+            ));
+        }
+
+        let players = player_counts(&vec![session_id], conn)?
+            .get(&session_id)
+            .copied()
+            .unwrap_or(0);
+
+        if players >= game.config.player_limit as i64 {
+            return Err(ServerError::new(
+                ErrorKind::InvalidInput,
+                &format!("Session {} is full", &session_id),
+            ));
+        }
+
+        if let Some(account) = &account_id {
+            check_linked(account, &identity.user_id, conn)?;
+        }
+
+        use schema::player_sessions::dsl::player_sessions;
+
+        insert_into(player_sessions)
+            .values(&NewPlayerSession {
+                session_id,
+                user_id: identity.user_id.to_owned(),
+                account_id,
+                info: PlayerInfo::default(),
+            })
+            .execute(conn)
+            .map_err(ServerError::Database)?;
+
+        Ok((session, game, players + 1))
+    })?;
+
+    let info = SessionInfo::new(&session, &game.config, players);
+
+    if !info.private {
+        GLOBAL.do_send(LobbyEvent(LobbyUpdate::Players {
+            session_id,
+            players: info.players,
+            full: info.players >= info.player_limit as i64,
+        }));
+    }
+
+    Ok(HttpResponse::Created().json(info))
+}
+
+//the account rewarded for a session can be changed until it starts
+pub async fn select_account(
+    identity: web::ReqData<Identity>,
+    path: web::Path<Uuid>,
+    body: web::Json<RegisterPlayer>,
+) -> Result<HttpResponse, ServerError> {
+    let session_id = path.into_inner();
+
+    let RegisterPlayer { account_id } = body.into_inner();
+
+    let mut conn = connection()?;
+
+    use schema::sessions::dsl::{id, sessions};
+
+    let session = sessions
+        .filter(id.eq(&session_id))
+        .get_result::<Session>(&mut conn)
+        .map_err(ServerError::Database)?;
+
+    if session.started_at.is_some() {
+        return Err(ServerError::new(
+            ErrorKind::InvalidInput,
+            &format!("Session {} has already started", &session_id),
+        ));
+    }
+
+    if let Some(account) = &account_id {
+        check_linked(account, &identity.user_id, &mut conn)?;
+    }
+
+    use schema::player_sessions::dsl::{
+        account_id as aid, player_sessions, session_id as sid, user_id,
+    };
+
+    match update(player_sessions)
+        .filter(sid.eq(&session_id).and(user_id.eq(&identity.user_id)))
+        .set(aid.eq(&account_id))
+        .execute(&mut conn)
+    {
+        Ok(0) => Err(ServerError::new(
+            ErrorKind::NotFound,
+            &format!(
+                "{} is not registered for session {}",
+                &identity.user_id, &session_id
+            ),
+        )),
+
+        Ok(_) => Ok(HttpResponse::NoContent().finish()),
+
+        Err(e) => Err(ServerError::Database(e)),
+    }
+}
+
+//players and the creator can read a session's history, moderators any session's
+pub async fn list_history(
+    identity: web::ReqData<Identity>,
+    path: web::Path<Uuid>,
+    query: web::Query<HistoryQuery>,
+) -> Result<HttpResponse, ServerError> {
+    let session_id = path.into_inner();
+
+    let HistoryQuery {
+        user_id: player,
+        kind,
+        from,
+        to,
+        offset,
+        limit,
+    } = query.into_inner();
+
+    let mut conn = connection()?;
+
+    use schema::sessions::dsl::{id, sessions};
+
+    let session = sessions
+        .filter(id.eq(&session_id))
+        .get_result::<Session>(&mut conn)
+        .map_err(ServerError::Database)?;
+
+    if session.creator != identity.user_id && !permitted(&identity.roles, Permission::ViewHistory) {
+        use schema::player_sessions::dsl::{player_sessions, session_id as sid, user_id};
+
+        let registered = player_sessions
+            .filter(sid.eq(&session_id).and(user_id.eq(&identity.user_id)))
+            .count()
+            .get_result::<i64>(&mut conn)
+            .map_err(ServerError::Database)?;
+
+        if registered == 0 {
+            return Err(ServerError::new(
+                ErrorKind::PermissionDenied,
+                &format!(
+                    "{} did not play in session {}",
+                    &identity.user_id, &session_id
+                ),
+            ));
+        }
+    }
+
+    let filter = HistoryFilter {
+        user_id: player,
+        kinds: kind
+            .map(|kinds| {
+                kinds
+                    .split(',')
+                    .map(|k| k.trim().to_string())
+                    .filter(|k| !k.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default(),
+        from,
+        to,
+    };
+
+    let history = session_history(&session_id, &filter, offset, limit, &mut conn)?;
+
+    Ok(HttpResponse::Ok().json(history))
+}
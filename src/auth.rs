@@ -0,0 +1,244 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::ErrorKind,
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use actix_web::{dev::Payload, http::header::Header, FromRequest, HttpRequest};
+use actix_web_httpauth::headers::authorization::{Authorization, Basic, Bearer};
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use futures::future::{ready, Ready};
+use near_crypto::{PublicKey, Signature};
+use near_primitives::types::AccountId;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::{db::Identity, handlers::messages::ServerError, types::UserId};
+
+const TICKET_LIFETIME: Duration = Duration::from_secs(30);
+const TICKET_PROTOCOL_PREFIX: &str = "ticket.";
+const CHALLENGE_LIFETIME: Duration = Duration::from_secs(5 * 60);
+
+lazy_static::lazy_static! {
+    static ref TICKETS: Mutex<HashMap<String, (Identity, Instant)>> = Mutex::new(HashMap::new());
+
+    //one outstanding account link challenge per user and account
+    static ref CHALLENGES: Mutex<HashMap<(UserId, AccountId), (String, Instant)>> =
+        Mutex::new(HashMap::new());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UserRole {
+    Admin,
+    Moderator,
+    GameCreator,
+}
+
+impl FromStr for UserRole {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "admin" => Ok(Self::Admin),
+            "moderator" => Ok(Self::Moderator),
+            "game_creator" => Ok(Self::GameCreator),
+            _ => Err(format!("Unknown role {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Permission {
+    CreateGame,
+    //pools are created by the admin signer, stakes move real tokens
+    CreatePool,
+    EndAnySession,
+    KickPlayer,
+    ManageOutbox,
+    ResolvePool,
+    SetAttributes,
+    ViewHistory,
+    WatchReplay,
+}
+
+impl UserRole {
+    pub fn grants(&self, permission: Permission) -> bool {
+        match self {
+            Self::Admin => true,
+
+            Self::Moderator => matches!(
+                permission,
+                Permission::EndAnySession
+                    | Permission::KickPlayer
+                    | Permission::ViewHistory
+                    | Permission::WatchReplay
+            ),
+
+            Self::GameCreator => matches!(permission, Permission::CreateGame),
+        }
+    }
+}
+
+pub fn permitted(roles: &HashSet<UserRole>, permission: Permission) -> bool {
+    roles.iter().any(|role| role.grants(permission))
+}
+
+pub fn require(roles: &HashSet<UserRole>, permission: Permission) -> Result<(), ServerError> {
+    if permitted(roles, permission) {
+        Ok(())
+    } else {
+        Err(ServerError::new(
+            ErrorKind::PermissionDenied,
+            &format!("Missing permission {:?}", permission),
+        ))
+    }
+}
+
+//browsers cannot set headers on websocket upgrades, so a ticket can be passed instead,
+//as ?ticket= or a ticket.<ticket> protocol offered next to json or msgpack
+pub enum Credentials {
+    Token(String),
+    Ticket(String),
+    Missing,
+}
+
+impl Credentials {
+    fn parse(req: &HttpRequest) -> Self {
+        if let Ok(auth) = Authorization::<Bearer>::parse(req) {
+            return Self::Token(auth.into_scheme().token().to_string());
+        }
+
+        if let Ok(auth) = Authorization::<Basic>::parse(req) {
+            return Self::Token(auth.into_scheme().user_id().to_string());
+        }
+
+        let query_ticket = req.query_string().split('&').find_map(|pair| {
+            match pair.split_once('=') {
+                Some(("ticket", value)) => Some(value.to_owned()),
+                _ => None,
+            }
+        });
+
+        let protocol_ticket = req
+            .headers()
+            .get("Sec-WebSocket-Protocol")
+            .and_then(|header| header.to_str().ok())
+            .and_then(|header| {
+                header
+                    .split(',')
+                    .find_map(|p| p.trim().strip_prefix(TICKET_PROTOCOL_PREFIX))
+                    .map(|ticket| ticket.to_owned())
+            });
+
+        match query_ticket.or(protocol_ticket) {
+            Some(ticket) => Self::Ticket(ticket),
+
+            None => Self::Missing,
+        }
+    }
+}
+
+impl FromRequest for Credentials {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(Self::parse(req)))
+    }
+}
+
+pub fn issue_ticket(identity: &Identity) -> (String, Duration) {
+    let ticket = generate_token();
+
+    let mut tickets = TICKETS.lock().unwrap();
+
+    tickets.retain(|_, (_, issued_at)| issued_at.elapsed() < TICKET_LIFETIME);
+
+    tickets.insert(ticket.to_owned(), (identity.to_owned(), Instant::now()));
+
+    (ticket, TICKET_LIFETIME)
+}
+
+//tickets are single use, redeeming removes it even when expired
+pub fn redeem_ticket(ticket: &str) -> Option<Identity> {
+    match TICKETS.lock().unwrap().remove(ticket) {
+        Some((identity, issued_at)) if issued_at.elapsed() < TICKET_LIFETIME => Some(identity),
+
+        _ => None,
+    }
+}
+
+//the message the account owner signs, a new challenge replaces the previous one
+pub fn issue_challenge(uid: &UserId, account_id: &AccountId) -> (String, Duration) {
+    let message = format!(
+        "Link NEAR account {} to user {}\nNonce: {}",
+        account_id,
+        uid,
+        generate_token()
+    );
+
+    let mut challenges = CHALLENGES.lock().unwrap();
+
+    challenges.retain(|_, (_, issued_at)| issued_at.elapsed() < CHALLENGE_LIFETIME);
+
+    challenges.insert(
+        (uid.to_owned(), account_id.to_owned()),
+        (message.to_owned(), Instant::now()),
+    );
+
+    (message, CHALLENGE_LIFETIME)
+}
+
+//challenges are single use, a failed verification needs a new one
+pub fn redeem_challenge(uid: &UserId, account_id: &AccountId) -> Option<String> {
+    match CHALLENGES
+        .lock()
+        .unwrap()
+        .remove(&(uid.to_owned(), account_id.to_owned()))
+    {
+        Some((message, issued_at)) if issued_at.elapsed() < CHALLENGE_LIFETIME => Some(message),
+
+        _ => None,
+    }
+}
+
+//signatures are over the raw utf8 bytes of the challenge
+pub fn verify_signature(message: &str, public_key: &PublicKey, signature: &Signature) -> bool {
+    signature.verify(message.as_bytes(), public_key)
+}
+
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+
+    rand::thread_rng().fill_bytes(&mut bytes);
+
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+pub fn hash_password(password: &str) -> Result<String, ServerError> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    match Argon2::default().hash_password(password.as_bytes(), &salt) {
+        Ok(hash) => Ok(hash.to_string()),
+
+        Err(e) => Err(ServerError::new(ErrorKind::InvalidInput, &e.to_string())),
+    }
+}
+
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(parsed) => Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok(),
+
+        Err(_) => false,
+    }
+}
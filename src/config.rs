@@ -0,0 +1,339 @@
+use std::{env, fmt, fs, path::PathBuf, str::FromStr, sync::OnceLock};
+
+use near_crypto::{InMemorySigner, SecretKey};
+use near_jsonrpc_client::{NEAR_MAINNET_RPC_URL, NEAR_TESTNET_RPC_URL};
+use near_primitives::types::AccountId;
+use serde::Deserialize;
+
+const LOCALNET_RPC_URL: &str = "http://127.0.0.1:3030";
+
+//tokens last a week unless AUTH_TOKEN_LIFETIME says otherwise
+const DEFAULT_TOKEN_LIFETIME: i64 = 7 * 24 * 60 * 60;
+
+static NEAR: OnceLock<NearConfig> = OnceLock::new();
+static AUTH: OnceLock<AuthConfig> = OnceLock::new();
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Missing(&'static str),
+    Invalid { key: &'static str, reason: String },
+    File { path: PathBuf, reason: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Missing(key) => write!(f, "Missing config value {}", key),
+
+            ConfigError::Invalid { key, reason } => {
+                write!(f, "Invalid config value {}: {}", key, reason)
+            }
+
+            ConfigError::File { path, reason } => {
+                write!(
+                    f,
+                    "Error reading config file {}: {}",
+                    path.display(),
+                    reason
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Network {
+    Mainnet,
+    Testnet,
+    //local node or near-sandbox
+    Localnet,
+}
+
+impl Network {
+    fn rpc_url(&self) -> &'static str {
+        match self {
+            Network::Mainnet => NEAR_MAINNET_RPC_URL,
+            Network::Testnet => NEAR_TESTNET_RPC_URL,
+            Network::Localnet => LOCALNET_RPC_URL,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Network::Mainnet => "mainnet",
+            Network::Testnet => "testnet",
+            Network::Localnet => "localnet",
+        }
+    }
+}
+
+impl FromStr for Network {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mainnet" => Ok(Network::Mainnet),
+            "testnet" => Ok(Network::Testnet),
+            "localnet" | "sandbox" => Ok(Network::Localnet),
+            _ => Err(format!("unknown network {}", s)),
+        }
+    }
+}
+
+//values read from NEAR_CONFIG_FILE, env variables take precedence
+#[derive(Debug, Default, Deserialize)]
+struct RawNearConfig {
+    network: Option<String>,
+    rpc_url: Option<String>,
+    signer_account_id: Option<String>,
+    signer_secret_key: Option<String>,
+    signer_file: Option<PathBuf>,
+    #[serde(default)]
+    signer_extra_keys: Vec<String>,
+    deltd_account_id: Option<String>,
+    deltft_account_id: Option<String>,
+    deltmt_account_id: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Contracts {
+    pub deltd: AccountId,
+    pub deltft: AccountId,
+    pub deltmt: AccountId,
+}
+
+#[derive(Debug, Clone)]
+pub struct NearConfig {
+    pub network: Network,
+    pub rpc_url: String,
+    pub signer: InMemorySigner,
+    //additional access keys of the signer account, each gets its own nonce
+    pub extra_keys: Vec<SecretKey>,
+    pub contracts: Contracts,
+}
+
+fn value(key: &'static str, file: Option<String>) -> Option<String> {
+    env::var(key).ok().filter(|v| !v.is_empty()).or(file)
+}
+
+fn parse<T>(key: &'static str, value: &str) -> Result<T, ConfigError>
+where
+    T: FromStr,
+    T::Err: ToString,
+{
+    value.parse::<T>().map_err(|e| ConfigError::Invalid {
+        key,
+        reason: e.to_string(),
+    })
+}
+
+impl NearConfig {
+    pub fn load() -> Result<Self, ConfigError> {
+        let raw = match env::var("NEAR_CONFIG_FILE") {
+            Ok(path) => {
+                let path = PathBuf::from(path);
+
+                let file = fs::read_to_string(&path).map_err(|e| ConfigError::File {
+                    path: path.to_owned(),
+                    reason: e.to_string(),
+                })?;
+
+                serde_json::from_str::<RawNearConfig>(&file).map_err(|e| ConfigError::File {
+                    path,
+                    reason: e.to_string(),
+                })?
+            }
+
+            Err(_) => RawNearConfig::default(),
+        };
+
+        let network = match value("NEAR_NETWORK", raw.network) {
+            Some(network) => parse::<Network>("NEAR_NETWORK", &network)?,
+
+            None => Network::Testnet,
+        };
+
+        let rpc_url =
+            value("NEAR_RPC_URL", raw.rpc_url).unwrap_or_else(|| network.rpc_url().to_string());
+
+        let signer = Self::load_signer(
+            network,
+            value("NEAR_SIGNER_ACCOUNT_ID", raw.signer_account_id),
+            value("NEAR_SIGNER_SECRET_KEY", raw.signer_secret_key),
+            env::var("NEAR_SIGNER_FILE")
+                .ok()
+                .map(PathBuf::from)
+                .or(raw.signer_file),
+        )?;
+
+        let extra_keys = match env::var("NEAR_SIGNER_EXTRA_KEYS") {
+            Ok(keys) => keys
+                .split(',')
+                .map(|key| key.trim())
+                .filter(|key| !key.is_empty())
+                .map(|key| parse::<SecretKey>("NEAR_SIGNER_EXTRA_KEYS", key))
+                .collect::<Result<Vec<SecretKey>, ConfigError>>()?,
+
+            Err(_) => raw
+                .signer_extra_keys
+                .iter()
+                .map(|key| parse::<SecretKey>("NEAR_SIGNER_EXTRA_KEYS", key))
+                .collect::<Result<Vec<SecretKey>, ConfigError>>()?,
+        };
+
+        //testnet falls back to the deployed delt contracts
+        let contract =
+            |key: &'static str, file: Option<String>, testnet: &str| match value(key, file) {
+                Some(id) => parse::<AccountId>(key, &id),
+
+                None if network == Network::Testnet => parse::<AccountId>(key, testnet),
+
+                None => Err(ConfigError::Missing(key)),
+            };
+
+        let contracts = Contracts {
+            deltd: contract(
+                "NEAR_DELTD_ACCOUNT_ID",
+                raw.deltd_account_id,
+                "delt-d.delt.testnet",
+            )?,
+            deltft: contract(
+                "NEAR_DELTFT_ACCOUNT_ID",
+                raw.deltft_account_id,
+                "delt-ft.delt.testnet",
+            )?,
+            deltmt: contract(
+                "NEAR_DELTMT_ACCOUNT_ID",
+                raw.deltmt_account_id,
+                "delt-mt.delt.testnet",
+            )?,
+        };
+
+        Ok(Self {
+            network,
+            rpc_url,
+            signer,
+            extra_keys,
+            contracts,
+        })
+    }
+
+    pub fn signers(&self) -> Vec<InMemorySigner> {
+        let mut signers = vec![self.signer.to_owned()];
+
+        for key in self.extra_keys.iter() {
+            signers.push(InMemorySigner::from_secret_key(
+                self.signer.account_id.to_owned(),
+                key.to_owned(),
+            ));
+        }
+
+        signers
+    }
+
+    //a secret key wins over a credentials file, which defaults to ~/.near-credentials/<network>/<account>.json
+    fn load_signer(
+        network: Network,
+        account_id: Option<String>,
+        secret_key: Option<String>,
+        file: Option<PathBuf>,
+    ) -> Result<InMemorySigner, ConfigError> {
+        let account_id = match account_id {
+            Some(id) => Some(parse::<AccountId>("NEAR_SIGNER_ACCOUNT_ID", &id)?),
+
+            None => None,
+        };
+
+        if let Some(secret_key) = secret_key {
+            let account_id = account_id.ok_or(ConfigError::Missing("NEAR_SIGNER_ACCOUNT_ID"))?;
+
+            let secret_key = parse::<SecretKey>("NEAR_SIGNER_SECRET_KEY", &secret_key)?;
+
+            return Ok(InMemorySigner::from_secret_key(account_id, secret_key));
+        }
+
+        let path = match (file, &account_id) {
+            (Some(path), _) => path,
+
+            (None, Some(account_id)) => {
+                let home = env::var("HOME")
+                    .or(env::var("USERPROFILE"))
+                    .map_err(|_| ConfigError::Missing("NEAR_SIGNER_FILE"))?;
+
+                PathBuf::from(home)
+                    .join(".near-credentials")
+                    .join(network.name())
+                    .join(format!("{}.json", account_id))
+            }
+
+            (None, None) => return Err(ConfigError::Missing("NEAR_SIGNER_ACCOUNT_ID")),
+        };
+
+        let signer = InMemorySigner::from_file(&path).map_err(|e| ConfigError::File {
+            path: path.to_owned(),
+            reason: e.to_string(),
+        })?;
+
+        match account_id {
+            Some(account_id) if account_id != signer.account_id => Err(ConfigError::Invalid {
+                key: "NEAR_SIGNER_FILE",
+                reason: format!(
+                    "{} holds a key for {}, not {}",
+                    path.display(),
+                    signer.account_id,
+                    account_id
+                ),
+            }),
+
+            _ => Ok(signer),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthConfig {
+    pub token_lifetime: chrono::Duration,
+}
+
+impl AuthConfig {
+    pub fn load() -> Result<Self, ConfigError> {
+        let secs = match value("AUTH_TOKEN_LIFETIME", None) {
+            Some(secs) => parse::<i64>("AUTH_TOKEN_LIFETIME", &secs)?,
+
+            None => DEFAULT_TOKEN_LIFETIME,
+        };
+
+        if secs <= 0 {
+            return Err(ConfigError::Invalid {
+                key: "AUTH_TOKEN_LIFETIME",
+                reason: "must be a positive number of seconds".to_string(),
+            });
+        }
+
+        Ok(Self {
+            token_lifetime: chrono::Duration::seconds(secs),
+        })
+    }
+}
+
+pub fn init() -> Result<&'static NearConfig, ConfigError> {
+    let auth = AuthConfig::load()?;
+
+    AUTH.get_or_init(|| auth);
+
+    let config = NearConfig::load()?;
+
+    Ok(NEAR.get_or_init(|| config))
+}
+
+//only valid after init, which main runs before starting the server
+pub fn near() -> &'static NearConfig {
+    NEAR.get().expect("near config accessed before init")
+}
+
+pub fn auth() -> &'static AuthConfig {
+    AUTH.get().expect("auth config accessed before init")
+}
@@ -0,0 +1,724 @@
+use std::{collections::HashSet, io::ErrorKind};
+
+use actix::{Actor, Handler, Message, SyncContext};
+use chrono::{Local, NaiveDateTime};
+use diesel::{delete, insert_into, prelude::*, sql_types::Jsonb, update};
+use near_primitives::{hash::CryptoHash, types::AccountId};
+use uuid::Uuid;
+
+use crate::{
+    config::auth,
+    handlers::{
+        client::{check_access, check_attempts},
+        contract_methods::Success,
+        messages::ServerError,
+        outbox,
+        wallet::{owned_account, reward_history},
+    },
+    types::{
+        ChainOperation, Content, GameConfig, PlayerInfo, RewardEntry, SessionEvent, SessionInfo,
+        SessionState, UserId,
+    },
+};
+
+use super::{
+    connection, joinable_sessions, load_roles,
+    models::{
+        Game, NewReplayFrame, NewSessionEvent, OutboxEntry, PlayerSession, PoolRef, ReplayFrameRow,
+        Session, SessionEventRow, UserSession, Whitelist,
+    },
+    schema, session_events, Identity, DATABASE,
+};
+
+//runs diesel queries on its own threads so a slow database never blocks the event loop
+pub struct DbActor;
+
+impl Actor for DbActor {
+    type Context = SyncContext<Self>;
+}
+
+//sends a query to the database threads, a dropped mailbox is reported like a lost connection
+pub async fn run<M, T>(msg: M) -> Result<T, ServerError>
+where
+    M: Message<Result = Result<T, ServerError>> + Send + 'static,
+    T: Send + 'static,
+    DbActor: Handler<M>,
+{
+    match DATABASE.send(msg).await {
+        Ok(res) => res,
+
+        Err(e) => Err(ServerError::new(ErrorKind::NotConnected, &e.to_string())),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum PlayerProgress {
+    Playing(PlayerInfo),
+    Ended(NaiveDateTime),
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<Option<Identity>, ServerError>")]
+pub struct Authenticate {
+    pub token: String,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<(), ServerError>")]
+pub struct SaveSession {
+    pub session_id: Uuid,
+    pub state: SessionState,
+    pub started_at: Option<NaiveDateTime>,
+    pub players: Vec<(UserId, PlayerProgress)>,
+    //last event folded into state
+    pub snapshot_seq: i64,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<(), ServerError>")]
+pub struct AppendEvents {
+    pub session_id: Uuid,
+    pub events: Vec<(i64, NaiveDateTime, SessionEvent)>,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<(), ServerError>")]
+pub struct AppendReplay(pub Vec<NewReplayFrame>);
+
+//every frame recorded for a session, in the order it was recorded
+#[derive(Message)]
+#[rtype(result = "Result<Vec<ReplayFrameRow>, ServerError>")]
+pub struct LoadReplay(pub Uuid);
+
+//saves first so players that just ended are seen as ended
+#[derive(Message)]
+#[rtype(result = "Result<(Vec<PlayerSession>, Option<PoolRef>), ServerError>")]
+pub struct EndSession {
+    pub save: SaveSession,
+    pub ended_at: NaiveDateTime,
+    pub pool_id: Option<String>,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<(), ServerError>")]
+pub struct EndPlayerSession {
+    pub session_id: Uuid,
+    pub user_id: UserId,
+    pub info: PlayerInfo,
+}
+
+//access and attempt limits are checked before the player session is returned
+#[derive(Message)]
+#[rtype(result = "Result<(PlayerSession, Session, GameConfig, Vec<SessionEventRow>), ServerError>")]
+pub struct PrepareJoin {
+    pub user_id: UserId,
+    pub session_id: Uuid,
+    pub password: Option<String>,
+}
+
+//spectators need the same access as players but no player session
+#[derive(Message)]
+#[rtype(result = "Result<(), ServerError>")]
+pub struct CheckAccess {
+    pub user_id: UserId,
+    pub session_id: Uuid,
+    pub password: Option<String>,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<Option<Uuid>, ServerError>")]
+pub struct RejoinableSession(pub UserId);
+
+#[derive(Message)]
+#[rtype(result = "Result<(Session, GameConfig, Vec<SessionEventRow>), ServerError>")]
+pub struct LoadSession(pub Uuid);
+
+#[derive(Message)]
+#[rtype(result = "Result<Vec<(Session, GameConfig, Vec<SessionEventRow>)>, ServerError>")]
+pub struct UnresolvedSessions;
+
+#[derive(Message)]
+#[rtype(result = "Result<Vec<SessionInfo>, ServerError>")]
+pub struct JoinableSessions;
+
+#[derive(Message)]
+#[rtype(result = "Result<(), ServerError>")]
+pub struct UpdateWhitelist {
+    pub session_id: Uuid,
+    pub add: Vec<UserId>,
+    pub remove: Vec<UserId>,
+}
+
+//enqueued together or not at all
+#[derive(Message)]
+#[rtype(result = "Result<(), ServerError>")]
+pub struct Enqueue(pub Vec<ChainOperation>);
+
+#[derive(Message)]
+#[rtype(result = "Result<usize, ServerError>")]
+pub struct RequeueOperations;
+
+#[derive(Message)]
+#[rtype(result = "Result<Vec<OutboxEntry>, ServerError>")]
+pub struct ClaimOperations;
+
+//recorded before the transaction is broadcast
+#[derive(Message)]
+#[rtype(result = "Result<(), ServerError>")]
+pub struct RecordTransaction {
+    pub id: Uuid,
+    pub signer_id: AccountId,
+    pub tx_hash: CryptoHash,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<(), ServerError>")]
+pub struct CompleteOperation {
+    pub entry: OutboxEntry,
+    pub success: Success,
+    pub record: Option<Content>,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<(), ServerError>")]
+pub struct FailOperation {
+    pub entry: OutboxEntry,
+    pub error: String,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<AccountId, ServerError>")]
+pub struct OwnedAccount {
+    pub user_id: UserId,
+    pub account_id: Option<AccountId>,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<Vec<RewardEntry>, ServerError>")]
+pub struct RewardHistory {
+    pub user_id: UserId,
+    pub account_id: Option<AccountId>,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+fn save_session(
+    SaveSession {
+        session_id: sid,
+        state: session_state,
+        started_at: session_start,
+        players,
+        snapshot_seq: seq,
+    }: &SaveSession,
+    conn: &mut PgConnection,
+) -> Result<(), ServerError> {
+    use schema::sessions::dsl::{id, last_update, sessions, snapshot_seq, started_at, state};
+
+    //a slower save of an older snapshot must not overwrite a newer one
+    update(sessions)
+        .filter(id.eq(sid).and(snapshot_seq.le(seq)))
+        .set((
+            state.eq(session_state.as_sql::<Jsonb>()),
+            last_update.eq(Local::now().naive_local()),
+            started_at.eq(session_start),
+            snapshot_seq.eq(seq),
+        ))
+        .execute(conn)?;
+
+    use schema::player_sessions::dsl::{ended_at, info, player_sessions, session_id, user_id};
+
+    for (uid, progress) in players.iter() {
+        let req = update(player_sessions).filter(
+            session_id
+                .eq(sid)
+                .and(user_id.eq(uid))
+                .and(ended_at.is_null()),
+        );
+
+        match progress {
+            PlayerProgress::Playing(player_info) => req.set(info.eq(player_info)).execute(conn)?,
+
+            PlayerProgress::Ended(t) => req.set(ended_at.eq(t)).execute(conn)?,
+        };
+    }
+
+    Ok(())
+}
+
+impl Handler<Authenticate> for DbActor {
+    type Result = Result<Option<Identity>, ServerError>;
+
+    fn handle(
+        &mut self,
+        Authenticate { token }: Authenticate,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        use schema::user_sessions::dsl::{auth_token, ended_at, started_at, user_sessions};
+
+        let mut conn = connection()?;
+
+        let issued_after = Local::now().naive_local() - auth().token_lifetime;
+
+        match user_sessions
+            .filter(
+                auth_token
+                    .eq(&token)
+                    .and(ended_at.is_null())
+                    .and(started_at.gt(issued_after)),
+            )
+            .get_result::<UserSession>(&mut conn)
+            .optional()?
+        {
+            Some(UserSession {
+                user_id,
+                auth_token: token,
+                ..
+            }) => Ok(Some(Identity {
+                roles: load_roles(&user_id, &mut conn),
+                user_id,
+                auth_token: token,
+            })),
+
+            None => Ok(None),
+        }
+    }
+}
+
+impl Handler<SaveSession> for DbActor {
+    type Result = Result<(), ServerError>;
+
+    fn handle(&mut self, msg: SaveSession, _: &mut Self::Context) -> Self::Result {
+        save_session(&msg, &mut connection()?)
+    }
+}
+
+impl Handler<AppendEvents> for DbActor {
+    type Result = Result<(), ServerError>;
+
+    fn handle(
+        &mut self,
+        AppendEvents {
+            session_id: sid,
+            events,
+        }: AppendEvents,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        use schema::session_events::dsl::session_events;
+
+        let rows: Vec<NewSessionEvent> = events
+            .into_iter()
+            .map(|(seq, at, event)| NewSessionEvent {
+                session_id: sid.to_owned(),
+                seq,
+                kind: event.kind().to_string(),
+                user_id: event.player().cloned(),
+                created_at: at,
+                event,
+            })
+            .collect();
+
+        insert_into(session_events)
+            .values(&rows)
+            .on_conflict_do_nothing()
+            .execute(&mut connection()?)?;
+
+        Ok(())
+    }
+}
+
+impl Handler<AppendReplay> for DbActor {
+    type Result = Result<(), ServerError>;
+
+    fn handle(
+        &mut self,
+        AppendReplay(frames): AppendReplay,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        use schema::replay_frames::dsl::replay_frames;
+
+        insert_into(replay_frames)
+            .values(&frames)
+            .execute(&mut connection()?)?;
+
+        Ok(())
+    }
+}
+
+impl Handler<LoadReplay> for DbActor {
+    type Result = Result<Vec<ReplayFrameRow>, ServerError>;
+
+    fn handle(&mut self, LoadReplay(sid): LoadReplay, _: &mut Self::Context) -> Self::Result {
+        use schema::replay_frames::dsl::{id, recorded_at, replay_frames, session_id};
+
+        Ok(replay_frames
+            .filter(session_id.eq(&sid))
+            .order((recorded_at.asc(), id.asc()))
+            .get_results::<ReplayFrameRow>(&mut connection()?)?)
+    }
+}
+
+impl Handler<EndSession> for DbActor {
+    type Result = Result<(Vec<PlayerSession>, Option<PoolRef>), ServerError>;
+
+    fn handle(
+        &mut self,
+        EndSession {
+            save,
+            ended_at: end,
+            pool_id,
+        }: EndSession,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        let mut conn = connection()?;
+
+        save_session(&save, &mut conn)?;
+
+        use schema::sessions::dsl::{ended_at, id, sessions};
+
+        update(sessions)
+            .filter(id.eq(&save.session_id))
+            .set(ended_at.eq(end))
+            .execute(&mut conn)?;
+
+        use schema::player_sessions::dsl::{player_sessions, session_id};
+
+        let players = player_sessions
+            .filter(session_id.eq(&save.session_id))
+            .get_results::<PlayerSession>(&mut conn)?;
+
+        let pool = match pool_id {
+            Some(pool_id) => {
+                use schema::pools::dsl::{id as pid, pools};
+
+                pools
+                    .filter(pid.eq(&pool_id))
+                    .get_result::<PoolRef>(&mut conn)
+                    .optional()?
+            }
+
+            None => None,
+        };
+
+        Ok((players, pool))
+    }
+}
+
+impl Handler<EndPlayerSession> for DbActor {
+    type Result = Result<(), ServerError>;
+
+    fn handle(
+        &mut self,
+        EndPlayerSession {
+            session_id: sid,
+            user_id: uid,
+            info: player_info,
+        }: EndPlayerSession,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        use schema::player_sessions::dsl::{ended_at, info, player_sessions, session_id, user_id};
+
+        update(player_sessions)
+            .filter(user_id.eq(&uid).and(session_id.eq(sid)))
+            .set((
+                ended_at.eq(Local::now().naive_local()),
+                info.eq(&player_info),
+            ))
+            .execute(&mut connection()?)?;
+
+        Ok(())
+    }
+}
+
+impl Handler<PrepareJoin> for DbActor {
+    type Result = Result<(PlayerSession, Session, GameConfig, Vec<SessionEventRow>), ServerError>;
+
+    fn handle(
+        &mut self,
+        PrepareJoin {
+            user_id: uid,
+            session_id,
+            password,
+        }: PrepareJoin,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        use schema::games::dsl::games;
+        use schema::player_sessions::dsl::{player_sessions, user_id};
+        use schema::sessions::dsl::{id, sessions};
+
+        let mut conn = connection()?;
+
+        let (player_session, (session, Game { config, .. })) = player_sessions
+            .inner_join(sessions.inner_join(games))
+            .filter(id.eq(&session_id).and(user_id.eq(&uid)))
+            .get_result::<(PlayerSession, (Session, Game))>(&mut conn)?;
+
+        check_access(&uid, &session, password, &mut conn)?;
+
+        check_attempts(&uid, &session, &config, &mut conn)?;
+
+        let events = session_events(&session, &mut conn)?;
+
+        Ok((player_session, session, config, events))
+    }
+}
+
+impl Handler<CheckAccess> for DbActor {
+    type Result = Result<(), ServerError>;
+
+    fn handle(
+        &mut self,
+        CheckAccess {
+            user_id,
+            session_id,
+            password,
+        }: CheckAccess,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        use schema::sessions::dsl::sessions;
+
+        let mut conn = connection()?;
+
+        let session = sessions
+            .find(&session_id)
+            .get_result::<Session>(&mut conn)?;
+
+        check_access(&user_id, &session, password, &mut conn)
+    }
+}
+
+impl Handler<RejoinableSession> for DbActor {
+    type Result = Result<Option<Uuid>, ServerError>;
+
+    fn handle(
+        &mut self,
+        RejoinableSession(uid): RejoinableSession,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        use schema::player_sessions::dsl::{ended_at, player_sessions, user_id};
+        use schema::sessions::dsl::{ended_at as session_ended_at, id, password, sessions};
+
+        //password protected sessions have to be rejoined explicitly
+        Ok(player_sessions
+            .inner_join(sessions)
+            .filter(
+                user_id
+                    .eq(&uid)
+                    .and(session_ended_at.is_not_null())
+                    .and(ended_at.is_not_null())
+                    .and(password.is_null()),
+            )
+            .select(id)
+            .get_result::<Uuid>(&mut connection()?)
+            .optional()?)
+    }
+}
+
+impl Handler<LoadSession> for DbActor {
+    type Result = Result<(Session, GameConfig, Vec<SessionEventRow>), ServerError>;
+
+    fn handle(
+        &mut self,
+        LoadSession(session_id): LoadSession,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        use schema::games::dsl::games;
+        use schema::sessions::dsl::{id, sessions};
+
+        let mut conn = connection()?;
+
+        let (session, game) = sessions
+            .inner_join(games)
+            .filter(id.eq(&session_id))
+            .get_result::<(Session, Game)>(&mut conn)?;
+
+        let events = session_events(&session, &mut conn)?;
+
+        Ok((session, game.config, events))
+    }
+}
+
+impl Handler<UnresolvedSessions> for DbActor {
+    type Result = Result<Vec<(Session, GameConfig, Vec<SessionEventRow>)>, ServerError>;
+
+    fn handle(&mut self, _: UnresolvedSessions, _: &mut Self::Context) -> Self::Result {
+        use schema::games::dsl::games;
+        use schema::player_sessions::dsl::{player_sessions, resolved_at};
+        use schema::pools::dsl::{pools, resolved_at as pool_resolved_at};
+        use schema::sessions::dsl::{ended_at, sessions};
+
+        let mut conn = connection()?;
+
+        let unresolved = sessions
+            .inner_join(pools)
+            .inner_join(player_sessions)
+            .inner_join(games)
+            .filter(
+                ended_at
+                    .is_not_null()
+                    .and(pool_resolved_at.is_null().or(resolved_at.is_null())),
+            )
+            .get_results::<(Session, PoolRef, PlayerSession, Game)>(&mut conn)?;
+
+        let mut seen = HashSet::new();
+
+        unresolved
+            .into_iter()
+            .filter(|(session, ..)| seen.insert(session.id))
+            .map(|(session, _, _, game)| {
+                let events = session_events(&session, &mut conn)?;
+
+                Ok((session, game.config, events))
+            })
+            .collect()
+    }
+}
+
+impl Handler<JoinableSessions> for DbActor {
+    type Result = Result<Vec<SessionInfo>, ServerError>;
+
+    fn handle(&mut self, _: JoinableSessions, _: &mut Self::Context) -> Self::Result {
+        joinable_sessions(&mut connection()?)
+    }
+}
+
+impl Handler<UpdateWhitelist> for DbActor {
+    type Result = Result<(), ServerError>;
+
+    fn handle(
+        &mut self,
+        UpdateWhitelist {
+            session_id: sid,
+            add,
+            remove,
+        }: UpdateWhitelist,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        use schema::whitelist::dsl::{session_id, user_id, whitelist};
+
+        let entries: Vec<Whitelist> = add
+            .iter()
+            .map(|uid| Whitelist {
+                session_id: sid.to_owned(),
+                user_id: uid.to_owned(),
+            })
+            .collect();
+
+        connection()?.transaction::<_, ServerError, _>(|conn| {
+            insert_into(whitelist)
+                .values(&entries)
+                .on_conflict_do_nothing()
+                .execute(conn)?;
+
+            delete(whitelist.filter(session_id.eq(&sid).and(user_id.eq_any(&remove))))
+                .execute(conn)?;
+
+            Ok(())
+        })
+    }
+}
+
+impl Handler<Enqueue> for DbActor {
+    type Result = Result<(), ServerError>;
+
+    fn handle(&mut self, Enqueue(operations): Enqueue, _: &mut Self::Context) -> Self::Result {
+        connection()?.transaction::<_, ServerError, _>(|conn| {
+            for operation in operations {
+                outbox::enqueue(operation, conn)?;
+            }
+
+            Ok(())
+        })
+    }
+}
+
+impl Handler<RequeueOperations> for DbActor {
+    type Result = Result<usize, ServerError>;
+
+    fn handle(&mut self, _: RequeueOperations, _: &mut Self::Context) -> Self::Result {
+        outbox::requeue(&mut connection()?)
+    }
+}
+
+impl Handler<ClaimOperations> for DbActor {
+    type Result = Result<Vec<OutboxEntry>, ServerError>;
+
+    fn handle(&mut self, _: ClaimOperations, _: &mut Self::Context) -> Self::Result {
+        outbox::claim(&mut connection()?)
+    }
+}
+
+impl Handler<RecordTransaction> for DbActor {
+    type Result = Result<(), ServerError>;
+
+    fn handle(
+        &mut self,
+        RecordTransaction {
+            id,
+            signer_id,
+            tx_hash,
+        }: RecordTransaction,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        outbox::record_transaction(&id, &signer_id, &tx_hash, &mut connection()?)
+    }
+}
+
+impl Handler<CompleteOperation> for DbActor {
+    type Result = Result<(), ServerError>;
+
+    fn handle(
+        &mut self,
+        CompleteOperation {
+            entry,
+            success,
+            record,
+        }: CompleteOperation,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        outbox::complete(&entry, success, record, &mut connection()?)
+    }
+}
+
+impl Handler<FailOperation> for DbActor {
+    type Result = Result<(), ServerError>;
+
+    fn handle(
+        &mut self,
+        FailOperation { entry, error }: FailOperation,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        outbox::fail(&entry, &error, &mut connection()?)
+    }
+}
+
+impl Handler<OwnedAccount> for DbActor {
+    type Result = Result<AccountId, ServerError>;
+
+    fn handle(
+        &mut self,
+        OwnedAccount {
+            user_id,
+            account_id,
+        }: OwnedAccount,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        owned_account(&user_id, account_id, &mut connection()?)
+    }
+}
+
+impl Handler<RewardHistory> for DbActor {
+    type Result = Result<Vec<RewardEntry>, ServerError>;
+
+    fn handle(
+        &mut self,
+        RewardHistory {
+            user_id,
+            account_id,
+            offset,
+            limit,
+        }: RewardHistory,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        reward_history(&user_id, account_id, offset, limit, &mut connection()?)
+    }
+}
@@ -1,32 +1,48 @@
 use std::{
+    collections::{HashMap, HashSet},
     env,
     io::{Error, ErrorKind},
 };
 
+use actix::{Addr, SyncArbiter};
 use actix_http::HttpMessage;
-use actix_web_httpauth::extractors::{
-    basic::{BasicAuth, Config},
-    AuthenticationError,
-};
+use actix_web_httpauth::extractors::{basic::Config, AuthenticationError};
+
+use actix_web::{self, dev::ServiceRequest, error::ErrorBadRequest};
 
-use actix_web::{self, dev::ServiceRequest};
+use chrono::NaiveDateTime;
 
 use diesel::{
+    insert_into,
     prelude::*,
-    r2d2::{ConnectionManager, Pool},
+    r2d2::{ConnectionManager, Pool, PooledConnection},
     PgConnection,
 };
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 
+use serde_json::to_value;
+use uuid::Uuid;
+
 use crate::{
-    db::models::UserSession,
-    handlers::{client::ClientActor, CLIENTS},
+    auth::{redeem_ticket, Credentials, UserRole},
+    db::{
+        actor::{run, Authenticate, DbActor},
+        models::{Game, NewReward, Session, SessionEventRow},
+    },
+    handlers::messages::{Encoding, ServerError},
+    types::{Reward, SessionInfo, UserId, STATE_KINDS},
 };
 
+pub mod actor;
 pub mod models;
 pub mod schema;
 
 const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
+//threads running actor queries, each holds at most one pooled connection
+const DB_THREADS: usize = 4;
+
+pub const HISTORY_PAGE: usize = 100;
+const MAX_HISTORY_PAGE: usize = 500;
 
 lazy_static::lazy_static! {
     pub static ref DB_URL: String = {
@@ -40,6 +56,194 @@ lazy_static::lazy_static! {
           .build(manager)
           .expect("Error building a connection pool")
       };
+
+    pub static ref DATABASE: Addr<DbActor> = SyncArbiter::start(DB_THREADS, || DbActor);
+}
+
+//authenticated caller, inserted into request extensions by the validator
+#[derive(Debug, Clone)]
+pub struct Identity {
+    pub user_id: UserId,
+    pub auth_token: String,
+    pub roles: HashSet<UserRole>,
+}
+
+pub fn load_roles(uid: &UserId, conn: &mut PgConnection) -> HashSet<UserRole> {
+    use schema::roles::dsl::{role, roles, user_id};
+
+    match roles
+        .filter(user_id.eq(uid))
+        .select(role)
+        .load::<String>(conn)
+    {
+        Ok(granted) => granted
+            .iter()
+            .filter_map(|r| r.parse::<UserRole>().ok())
+            .collect(),
+
+        Err(e) => {
+            println!("[Server] DB Error loading roles for {}: {}", uid, e);
+
+            HashSet::new()
+        }
+    }
+}
+
+pub fn connection() -> Result<PooledConnection<ConnectionManager<PgConnection>>, ServerError> {
+    DB.get()
+        .map_err(|e| ServerError::new(ErrorKind::NotConnected, &e.to_string()))
+}
+
+pub fn player_counts(
+    session_ids: &Vec<Uuid>,
+    conn: &mut PgConnection,
+) -> Result<HashMap<Uuid, i64>, ServerError> {
+    use schema::player_sessions::dsl::{player_sessions, session_id};
+
+    let mut counts = HashMap::new();
+
+    match player_sessions
+        .filter(session_id.eq_any(session_ids))
+        .select(session_id)
+        .load::<Uuid>(conn)
+    {
+        Ok(registered) => {
+            for id in registered {
+                *counts.entry(id).or_insert(0) += 1;
+            }
+
+            Ok(counts)
+        }
+
+        Err(e) => Err(ServerError::Database(e)),
+    }
+}
+
+//public sessions that have not started and still have room
+pub fn joinable_sessions(conn: &mut PgConnection) -> Result<Vec<SessionInfo>, ServerError> {
+    use schema::games::dsl::{ended_at as game_ended_at, games};
+    use schema::sessions::dsl::{created_at, ended_at, private, sessions, started_at};
+
+    let joinable = sessions
+        .inner_join(games)
+        .filter(
+            ended_at
+                .is_null()
+                .and(started_at.is_null())
+                .and(private.eq(false))
+                .and(game_ended_at.is_null()),
+        )
+        .order(created_at.desc())
+        .get_results::<(Session, Game)>(conn)
+        .map_err(ServerError::Database)?;
+
+    let counts = player_counts(
+        &joinable.iter().map(|(session, _)| session.id).collect(),
+        conn,
+    )?;
+
+    Ok(joinable
+        .iter()
+        .map(|(session, game)| {
+            SessionInfo::new(
+                session,
+                &game.config,
+                counts.get(&session.id).copied().unwrap_or(0),
+            )
+        })
+        .filter(|info| info.players < info.player_limit as i64)
+        .collect())
+}
+
+//events appended after the session's last snapshot, oldest first
+pub fn session_events(
+    session: &Session,
+    conn: &mut PgConnection,
+) -> Result<Vec<SessionEventRow>, ServerError> {
+    use schema::session_events::dsl::{seq, session_events, session_id};
+
+    session_events
+        .filter(session_id.eq(&session.id).and(seq.gt(session.snapshot_seq)))
+        .order(seq.asc())
+        .get_results::<SessionEventRow>(conn)
+        .map_err(ServerError::Database)
+}
+
+//which of a session's events to return, unset fields match everything
+#[derive(Debug, Clone, Default)]
+pub struct HistoryFilter {
+    pub user_id: Option<UserId>,
+    //state changes are left out unless asked for by kind
+    pub kinds: Vec<String>,
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+}
+
+//a page of the session's event log, oldest first
+pub fn session_history(
+    sid: &Uuid,
+    filter: &HistoryFilter,
+    offset: usize,
+    limit: usize,
+    conn: &mut PgConnection,
+) -> Result<Vec<SessionEventRow>, ServerError> {
+    use schema::session_events::dsl::{created_at, kind, seq, session_events, session_id, user_id};
+
+    let mut query = session_events.filter(session_id.eq(sid)).into_boxed();
+
+    if let Some(uid) = &filter.user_id {
+        query = query.filter(user_id.eq(uid));
+    }
+
+    query = match filter.kinds.is_empty() {
+        true => query.filter(kind.ne_all(STATE_KINDS)),
+
+        false => query.filter(kind.eq_any(&filter.kinds)),
+    };
+
+    if let Some(from) = filter.from {
+        query = query.filter(created_at.ge(from));
+    }
+
+    if let Some(to) = filter.to {
+        query = query.filter(created_at.lt(to));
+    }
+
+    query
+        .order(seq.asc())
+        .offset(offset as i64)
+        .limit(limit.min(MAX_HISTORY_PAGE) as i64)
+        .get_results::<SessionEventRow>(conn)
+        .map_err(ServerError::Database)
+}
+
+//accounts that were never linked to a user have nowhere to keep a history
+pub fn record_reward(
+    account: &str,
+    reward: &Reward,
+    conn: &mut PgConnection,
+) -> Result<(), ServerError> {
+    use schema::accounts::dsl::{account_id, accounts};
+    use schema::rewards::dsl::rewards;
+
+    match accounts
+        .filter(account_id.eq(account))
+        .count()
+        .get_result::<i64>(conn)
+    {
+        Ok(0) => Ok(()),
+
+        Ok(_) => insert_into(rewards)
+            .values(&NewReward {
+                account_id: account.to_string(),
+                reward: to_value(reward).map_err(ServerError::Serde)?,
+            })
+            .execute(conn)
+            .map(|_| ())
+            .map_err(ServerError::Database),
+
+        Err(e) => Err(ServerError::Database(e)),
+    }
 }
 
 pub fn run_migrations() {
@@ -56,37 +260,44 @@ pub fn run_migrations() {
 
 pub async fn validator(
     req: ServiceRequest,
-    credentials: BasicAuth,
+    credentials: Credentials,
 ) -> Result<ServiceRequest, (actix_web::Error, ServiceRequest)> {
     let config = req.app_data::<Config>().cloned().unwrap_or_default();
 
-    use schema::user_sessions::dsl::{auth_token, user_sessions};
-
-    match DB.get().as_mut() {
-        Ok(conn) => match user_sessions
-            .filter(auth_token.eq(credentials.user_id()))
-            .get_result::<UserSession>(conn)
-        {
-            Ok(UserSession { user_id, .. }) if CLIENTS.lock().unwrap().get(&user_id).is_some() => {
-                Err((
-                    actix_web::Error::from(Error::new(
-                        ErrorKind::AddrInUse,
-                        format!("{} already connected", &user_id),
-                    )),
-                    req,
-                ))
+    let token = match credentials {
+        Credentials::Token(token) => token,
+
+        //tickets are only accepted for the websocket upgrade
+        Credentials::Ticket(ticket) if req.path() == "/" => {
+            //checked before redeeming so a rejected handshake does not use up the ticket
+            if let Err(reason) = Encoding::check_offered(req.request()) {
+                return Err((ErrorBadRequest(reason), req));
             }
 
-            Ok(UserSession { user_id, .. }) => {
-                let act = ClientActor::new(user_id);
+            match redeem_ticket(&ticket) {
+                Some(identity) => {
+                    req.extensions_mut().insert(identity);
 
-                req.extensions_mut().insert(act);
+                    return Ok(req);
+                }
 
-                Ok(req)
+                None => return Err((AuthenticationError::from(config).into(), req)),
             }
+        }
+
+        Credentials::Ticket(_) | Credentials::Missing => {
+            return Err((AuthenticationError::from(config).into(), req))
+        }
+    };
+
+    match run(Authenticate { token }).await {
+        Ok(Some(identity)) => {
+            req.extensions_mut().insert(identity);
+
+            Ok(req)
+        }
 
-            Err(e) => Err((AuthenticationError::from(config).into(), req)),
-        },
+        Ok(None) => Err((AuthenticationError::from(config).into(), req)),
 
         Err(e) => Err((
             actix_web::Error::from(Error::new(ErrorKind::NotFound, e.to_string())),
@@ -1,7 +1,11 @@
-use crate::types::{Content, GameConfig, GameId, Logs, PlayerInfo, SessionState, UserId};
+use crate::types::{
+    ChainOperation, Content, GameConfig, GameId, PlayerInfo, ReplayFrame, SessionEvent,
+    SessionState, UserId,
+};
 use chrono::NaiveDateTime;
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use uuid::Uuid;
 
 use super::schema;
@@ -62,6 +66,7 @@ pub struct User {
     pub email: String,
     pub created_at: NaiveDateTime,
     pub last_login: Option<NaiveDateTime>,
+    pub settings: Content,
 }
 
 #[derive(Debug, Clone, Insertable, Serialize, Deserialize)]
@@ -85,7 +90,13 @@ pub struct Account {
     pub account_id: String,
     pub user_id: UserId,
     pub last_active: Option<NaiveDateTime>,
-    pub rewards: Logs,
+}
+
+#[derive(Debug, Clone, Insertable, Serialize, Deserialize)]
+#[diesel(table_name = schema::accounts)]
+pub struct NewAccount {
+    pub account_id: String,
+    pub user_id: UserId,
 }
 
 #[derive(Debug, Clone, Queryable, Serialize, Deserialize, PartialEq, QueryableByName)]
@@ -101,8 +112,8 @@ pub struct Session {
     pub started_at: Option<NaiveDateTime>,
     pub ended_at: Option<NaiveDateTime>,
     pub last_update: Option<NaiveDateTime>,
-    pub logs: Logs,
     pub state: SessionState,
+    pub snapshot_seq: i64,
 }
 
 #[derive(Debug, Clone, Insertable, Serialize, Deserialize)]
@@ -125,6 +136,13 @@ pub struct UserSession {
     pub ended_at: Option<NaiveDateTime>,
 }
 
+#[derive(Debug, Clone, Insertable, Serialize, Deserialize)]
+#[diesel(table_name = schema::user_sessions)]
+pub struct NewUserSession {
+    pub auth_token: String,
+    pub user_id: UserId,
+}
+
 #[derive(Debug, Clone, Queryable, Serialize, Deserialize, Insertable, QueryableByName)]
 #[diesel(table_name = schema::player_sessions)]
 pub struct PlayerSession {
@@ -142,5 +160,88 @@ pub struct PlayerSession {
 pub struct NewPlayerSession {
     pub session_id: Uuid,
     pub user_id: String,
+    pub account_id: Option<String>,
     pub info: PlayerInfo,
 }
+
+#[derive(Debug, Clone, Queryable, Serialize, Deserialize)]
+#[diesel(table_name = schema::chain_outbox)]
+pub struct OutboxEntry {
+    pub id: Uuid,
+    pub idempotency_key: String,
+    pub session_id: Option<Uuid>,
+    pub operation: ChainOperation,
+    pub status: String,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub next_attempt_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+    pub completed_at: Option<NaiveDateTime>,
+    pub tx_signer: Option<String>,
+    pub tx_hash: Option<String>,
+}
+
+#[derive(Debug, Clone, Insertable, Serialize, Deserialize)]
+#[diesel(table_name = schema::chain_outbox)]
+pub struct NewOutboxEntry {
+    pub idempotency_key: String,
+    pub session_id: Option<Uuid>,
+    pub operation: ChainOperation,
+}
+
+#[derive(Debug, Clone, Queryable, Serialize, Deserialize)]
+#[diesel(table_name = schema::session_events)]
+pub struct SessionEventRow {
+    pub session_id: Uuid,
+    pub seq: i64,
+    pub kind: String,
+    pub event: SessionEvent,
+    pub created_at: NaiveDateTime,
+    pub user_id: Option<UserId>,
+}
+
+#[derive(Debug, Clone, Insertable, Serialize, Deserialize)]
+#[diesel(table_name = schema::session_events)]
+pub struct NewSessionEvent {
+    pub session_id: Uuid,
+    pub seq: i64,
+    pub kind: String,
+    pub event: SessionEvent,
+    pub created_at: NaiveDateTime,
+    pub user_id: Option<UserId>,
+}
+
+#[derive(Debug, Clone, Queryable, Serialize, Deserialize)]
+#[diesel(table_name = schema::replay_frames)]
+pub struct ReplayFrameRow {
+    pub id: i64,
+    pub session_id: Uuid,
+    pub tick: i64,
+    pub frame: ReplayFrame,
+    pub recorded_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Insertable, Serialize, Deserialize)]
+#[diesel(table_name = schema::replay_frames)]
+pub struct NewReplayFrame {
+    pub session_id: Uuid,
+    pub tick: i64,
+    pub frame: ReplayFrame,
+    pub recorded_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Queryable, Serialize, Deserialize)]
+#[diesel(table_name = schema::rewards)]
+pub struct RewardRow {
+    pub id: i64,
+    pub account_id: String,
+    pub reward: Value,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Insertable, Serialize, Deserialize)]
+#[diesel(table_name = schema::rewards)]
+pub struct NewReward {
+    pub account_id: String,
+    pub reward: Value,
+}
@@ -6,7 +6,23 @@ diesel::table! {
         #[max_length = 50]
         user_id -> Varchar,
         last_active -> Nullable<Timestamp>,
-        rewards -> Jsonb,
+    }
+}
+
+diesel::table! {
+    chain_outbox (id) {
+        id -> Uuid,
+        idempotency_key -> Text,
+        session_id -> Nullable<Uuid>,
+        operation -> Jsonb,
+        status -> Varchar,
+        attempts -> Int4,
+        last_error -> Nullable<Text>,
+        next_attempt_at -> Timestamp,
+        created_at -> Timestamp,
+        completed_at -> Nullable<Timestamp>,
+        tx_signer -> Nullable<Text>,
+        tx_hash -> Nullable<Text>,
     }
 }
 
@@ -44,6 +60,25 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    replay_frames (id) {
+        id -> Int8,
+        session_id -> Uuid,
+        tick -> Int8,
+        frame -> Jsonb,
+        recorded_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    rewards (id) {
+        id -> Int8,
+        account_id -> Varchar,
+        reward -> Jsonb,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     roles (user_id, role) {
         #[max_length = 50]
@@ -53,6 +88,18 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    session_events (session_id, seq) {
+        session_id -> Uuid,
+        seq -> Int8,
+        kind -> Varchar,
+        event -> Jsonb,
+        created_at -> Timestamp,
+        #[max_length = 50]
+        user_id -> Nullable<Varchar>,
+    }
+}
+
 diesel::table! {
     sessions (id) {
         id -> Uuid,
@@ -65,8 +112,8 @@ diesel::table! {
         started_at -> Nullable<Timestamp>,
         ended_at -> Nullable<Timestamp>,
         last_update -> Nullable<Timestamp>,
-        logs -> Jsonb,
         state -> Jsonb,
+        snapshot_seq -> Int8,
     }
 }
 
@@ -101,11 +148,15 @@ diesel::table! {
 }
 
 diesel::joinable!(accounts -> users (user_id));
+diesel::joinable!(chain_outbox -> sessions (session_id));
 diesel::joinable!(games -> users (creator));
 diesel::joinable!(player_sessions -> accounts (account_id));
 diesel::joinable!(player_sessions -> sessions (session_id));
 diesel::joinable!(player_sessions -> users (user_id));
+diesel::joinable!(replay_frames -> sessions (session_id));
+diesel::joinable!(rewards -> accounts (account_id));
 diesel::joinable!(roles -> users (user_id));
+diesel::joinable!(session_events -> sessions (session_id));
 diesel::joinable!(sessions -> games (game_id));
 diesel::joinable!(sessions -> pools (pool_id));
 diesel::joinable!(sessions -> users (creator));
@@ -115,10 +166,14 @@ diesel::joinable!(whitelist -> users (user_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     accounts,
+    chain_outbox,
     games,
     player_sessions,
     pools,
+    replay_frames,
+    rewards,
     roles,
+    session_events,
     sessions,
     user_sessions,
     users,
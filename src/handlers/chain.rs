@@ -0,0 +1,216 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, RwLock},
+};
+
+use delt_d::{
+    character::Character,
+    staking::{Pool, PoolId, StakeId},
+};
+use futures::future::{BoxFuture, FutureExt};
+use near_crypto::PublicKey;
+use near_primitives::{
+    hash::CryptoHash,
+    types::{AccountId, Balance},
+};
+use serde_json::Value;
+
+use crate::types::PlayerOutcome;
+
+use super::{contract_methods, contract_methods::Success, messages::ServerError, signer::OnSigned};
+
+lazy_static::lazy_static! {
+    static ref CHAIN: RwLock<Arc<dyn Chain>> = RwLock::new(Arc::new(NearChain));
+}
+
+//the chain used by session resolution and join checks
+pub fn chain() -> Arc<dyn Chain> {
+    CHAIN.read().unwrap().to_owned()
+}
+
+//tests install a MemoryChain in its place
+#[cfg(test)]
+pub fn set_chain(chain: Arc<dyn Chain>) {
+    *CHAIN.write().unwrap() = chain;
+}
+
+pub type ChainResult<T> = BoxFuture<'static, Result<T, ServerError>>;
+
+pub trait Chain: Send + Sync {
+    fn get_pools(&self, owner: Option<AccountId>) -> ChainResult<HashMap<PoolId, Pool>>;
+
+    fn create_pool(
+        &self,
+        pool_id: PoolId,
+        pool_results: HashSet<AccountId>,
+        required_xp: u128,
+    ) -> ChainResult<Pool>;
+
+    fn register_stake(&self, stake_id: StakeId, staker_id: AccountId) -> ChainResult<Success>;
+
+    fn unregister_stake(
+        &self,
+        stake_id: StakeId,
+        staker_id: AccountId,
+        reregister: Option<AccountId>,
+    ) -> ChainResult<Success>;
+
+    fn transfer_stake(
+        &self,
+        stake_id: StakeId,
+        receiver_id: AccountId,
+        amount: Option<u128>,
+    ) -> ChainResult<Success>;
+
+    fn get_stakes(&self, staker_id: AccountId) -> ChainResult<Vec<(StakeId, Option<PoolId>)>>;
+
+    fn verify_stake(&self, stake_id: StakeId, check_id: Option<AccountId>) -> ChainResult<String>;
+
+    fn toggle_pool_active(
+        &self,
+        pool_id: PoolId,
+        toggle: bool,
+        on_signed: OnSigned,
+    ) -> ChainResult<Success>;
+
+    fn assert_pool_result(
+        &self,
+        pool_id: PoolId,
+        pool_result: Option<AccountId>,
+    ) -> ChainResult<Success>;
+
+    fn distribute_stakes(&self, pool_id: PoolId, on_signed: OnSigned) -> ChainResult<Success>;
+
+    fn get_character(&self, account_id: AccountId) -> ChainResult<Character>;
+
+    fn get_ft_balance(&self, account_id: AccountId) -> ChainResult<Balance>;
+
+    fn set_default_attributes(&self, attributes: Value) -> ChainResult<Success>;
+
+    fn give_xp(&self, account_id: AccountId, xp: u128, on_signed: OnSigned)
+        -> ChainResult<Success>;
+
+    fn kill_character(&self, account_id: AccountId, on_signed: OnSigned) -> ChainResult<Success>;
+
+    fn settle_players(
+        &self,
+        outcomes: Vec<PlayerOutcome>,
+        on_signed: OnSigned,
+    ) -> ChainResult<Success>;
+
+    //the outcome of a transaction recorded through OnSigned, None if it never landed
+    fn tx_status(&self, signer_id: AccountId, tx_hash: CryptoHash) -> ChainResult<Option<Success>>;
+
+    fn verify_access_key(&self, account_id: AccountId, public_key: PublicKey) -> ChainResult<()>;
+}
+
+//calls the delt contracts through the configured rpc
+pub struct NearChain;
+
+impl Chain for NearChain {
+    fn get_pools(&self, owner: Option<AccountId>) -> ChainResult<HashMap<PoolId, Pool>> {
+        contract_methods::get_pools(owner).boxed()
+    }
+
+    fn create_pool(
+        &self,
+        pool_id: PoolId,
+        pool_results: HashSet<AccountId>,
+        required_xp: u128,
+    ) -> ChainResult<Pool> {
+        async move { contract_methods::create_pool(&pool_id, &pool_results, &required_xp).await }
+            .boxed()
+    }
+
+    fn register_stake(&self, stake_id: StakeId, staker_id: AccountId) -> ChainResult<Success> {
+        contract_methods::register_stake(stake_id, staker_id).boxed()
+    }
+
+    fn unregister_stake(
+        &self,
+        stake_id: StakeId,
+        staker_id: AccountId,
+        reregister: Option<AccountId>,
+    ) -> ChainResult<Success> {
+        contract_methods::unregister_stake(stake_id, staker_id, reregister).boxed()
+    }
+
+    fn transfer_stake(
+        &self,
+        stake_id: StakeId,
+        receiver_id: AccountId,
+        amount: Option<u128>,
+    ) -> ChainResult<Success> {
+        contract_methods::transfer_stake(stake_id, receiver_id, amount).boxed()
+    }
+
+    fn get_stakes(&self, staker_id: AccountId) -> ChainResult<Vec<(StakeId, Option<PoolId>)>> {
+        contract_methods::get_stakes(staker_id).boxed()
+    }
+
+    fn verify_stake(&self, stake_id: StakeId, check_id: Option<AccountId>) -> ChainResult<String> {
+        contract_methods::verify_stake(stake_id, check_id).boxed()
+    }
+
+    fn toggle_pool_active(
+        &self,
+        pool_id: PoolId,
+        toggle: bool,
+        on_signed: OnSigned,
+    ) -> ChainResult<Success> {
+        contract_methods::toggle_pool_active(pool_id, toggle, on_signed).boxed()
+    }
+
+    fn assert_pool_result(
+        &self,
+        pool_id: PoolId,
+        pool_result: Option<AccountId>,
+    ) -> ChainResult<Success> {
+        contract_methods::assert_pool_result(pool_id, pool_result).boxed()
+    }
+
+    fn distribute_stakes(&self, pool_id: PoolId, on_signed: OnSigned) -> ChainResult<Success> {
+        contract_methods::distribute_stakes(pool_id, on_signed).boxed()
+    }
+
+    fn get_character(&self, account_id: AccountId) -> ChainResult<Character> {
+        async move { contract_methods::get_character(&account_id).await }.boxed()
+    }
+
+    fn get_ft_balance(&self, account_id: AccountId) -> ChainResult<Balance> {
+        async move { contract_methods::get_ft_balance(&account_id).await }.boxed()
+    }
+
+    fn set_default_attributes(&self, attributes: Value) -> ChainResult<Success> {
+        async move { contract_methods::set_default_attributes(&attributes).await }.boxed()
+    }
+
+    fn give_xp(
+        &self,
+        account_id: AccountId,
+        xp: u128,
+        on_signed: OnSigned,
+    ) -> ChainResult<Success> {
+        async move { contract_methods::give_xp(&account_id, &xp, on_signed).await }.boxed()
+    }
+
+    fn kill_character(&self, account_id: AccountId, on_signed: OnSigned) -> ChainResult<Success> {
+        async move { contract_methods::kill_character(&account_id, on_signed).await }.boxed()
+    }
+
+    fn settle_players(
+        &self,
+        outcomes: Vec<PlayerOutcome>,
+        on_signed: OnSigned,
+    ) -> ChainResult<Success> {
+        async move { contract_methods::settle_players(&outcomes, on_signed).await }.boxed()
+    }
+
+    fn tx_status(&self, signer_id: AccountId, tx_hash: CryptoHash) -> ChainResult<Option<Success>> {
+        async move { contract_methods::tx_status(&signer_id, &tx_hash).await }.boxed()
+    }
+
+    fn verify_access_key(&self, account_id: AccountId, public_key: PublicKey) -> ChainResult<()> {
+        async move { contract_methods::verify_access_key(&account_id, &public_key).await }.boxed()
+    }
+}
@@ -1,36 +1,210 @@
 use crate::{
+    auth::{permitted, require, verify_password, Permission, UserRole},
     db::{
-        models::{PlayerSession, Session},
-        schema, DB,
+        actor::{
+            run, CheckAccess, EndPlayerSession, LoadSession, OwnedAccount, PrepareJoin,
+            RejoinableSession, RewardHistory,
+        },
+        models::{PlayerSession, Session, SessionEventRow},
+        schema,
     },
-    handlers::{CLIENTS, SESSIONS},
-    types::UserId,
+    handlers::{CLIENTS, GLOBAL, SESSIONS},
+    types::{Content, GameConfig, Lvl, PlayerInfo, UserId},
 };
 use actix::{
     Actor, ActorContext, ActorFutureExt, Addr, AsyncContext, Handler, SpawnHandle, StreamHandler,
     WrapFuture,
 };
 use actix_web_actors::ws;
-use chrono::Local;
 
-use diesel::{prelude::*, update};
+use diesel::prelude::*;
 use near_primitives::types::AccountId;
 use serde_json::from_str;
 use std::{
+    collections::HashSet,
     str::FromStr,
     time::{Duration, Instant},
 };
 use uuid::Uuid;
 
-use super::{messages::*, session::SessionActor};
+use super::{
+    chain::chain,
+    messages::*,
+    replay::{check_speed, ReplayActor},
+    session::SessionActor,
+    wallet::{self, WalletQuery, REWARDS_PAGE},
+};
 
 const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
 const TIMEOUT: Duration = Duration::from_secs(10);
 
+pub fn check_access(
+    uid: &UserId,
+    session: &Session,
+    password: Option<String>,
+    conn: &mut PgConnection,
+) -> Result<(), ServerError> {
+    if let Some(hash) = &session.password {
+        match password {
+            Some(password) if verify_password(&password, hash) => {}
+
+            _ => {
+                return Err(ServerError::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    &format!("Incorrect password for session {}", &session.id),
+                ))
+            }
+        }
+    }
+
+    if session.private && &session.creator != uid {
+        use schema::whitelist::dsl::{session_id, user_id, whitelist};
+
+        let listed = whitelist
+            .filter(session_id.eq(&session.id).and(user_id.eq(uid)))
+            .count()
+            .get_result::<i64>(conn)
+            .map_err(ServerError::Database)?;
+
+        if listed == 0 {
+            return Err(ServerError::new(
+                std::io::ErrorKind::PermissionDenied,
+                &format!("{} is not whitelisted for session {}", uid, &session.id),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn check_attempts(
+    uid: &UserId,
+    session: &Session,
+    config: &GameConfig,
+    conn: &mut PgConnection,
+) -> Result<(), ServerError> {
+    use schema::player_sessions::dsl::{ended_at, player_sessions, session_id, user_id};
+    use schema::sessions::dsl::{game_id, sessions, started_at};
+
+    if let Some(limit) = config.player_attempts {
+        let used = player_sessions
+            .inner_join(sessions)
+            .filter(
+                user_id
+                    .eq(uid)
+                    .and(game_id.eq(&session.game_id))
+                    .and(session_id.ne(&session.id))
+                    .and(ended_at.is_not_null()),
+            )
+            .count()
+            .get_result::<i64>(conn)
+            .map_err(ServerError::Database)?;
+
+        if used >= limit {
+            return Err(ServerError::new(
+                std::io::ErrorKind::PermissionDenied,
+                &format!("{} has used all {} attempts at {}", uid, limit, &session.game_id),
+            ));
+        }
+    }
+
+    if let Some(limit) = config.session_attempts {
+        if session.started_at.is_none() {
+            let started = sessions
+                .filter(game_id.eq(&session.game_id).and(started_at.is_not_null()))
+                .count()
+                .get_result::<i64>(conn)
+                .map_err(ServerError::Database)?;
+
+            if started >= limit {
+                return Err(ServerError::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    &format!("{} has no session attempts left", &session.game_id),
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn check_lvl(account_id: Option<AccountId>, lvl_required: Lvl) -> Result<(), ServerError> {
+    if lvl_required <= Lvl::default() {
+        return Ok(());
+    }
+
+    match account_id {
+        Some(account_id) => {
+            chain().get_character(account_id.to_owned()).await?;
+
+            let lvl = Lvl::from_xp(chain().get_ft_balance(account_id.to_owned()).await?);
+
+            if lvl < lvl_required {
+                Err(ServerError::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    &format!(
+                        "Level {} required, {} is level {}",
+                        lvl_required.0, &account_id, lvl.0
+                    ),
+                ))
+            } else {
+                Ok(())
+            }
+        }
+
+        None => Err(ServerError::new(
+            std::io::ErrorKind::PermissionDenied,
+            &format!(
+                "Level {} required, no account registered for this session",
+                lvl_required.0
+            ),
+        )),
+    }
+}
+
+//sessions with a pool only admit accounts holding a stake in it
+async fn check_stake(account_id: Option<AccountId>, pool_id: String) -> Result<(), ServerError> {
+    let account_id = match account_id {
+        Some(account_id) => account_id,
+
+        None => {
+            return Err(ServerError::new(
+                std::io::ErrorKind::PermissionDenied,
+                &format!(
+                    "A stake in pool {} is required, no account registered for this session",
+                    &pool_id
+                ),
+            ))
+        }
+    };
+
+    let stake = chain()
+        .get_stakes(account_id.to_owned())
+        .await?
+        .into_iter()
+        .find(|(_, staked)| staked.as_ref() == Some(&pool_id));
+
+    match stake {
+        Some((stake_id, _)) => chain()
+            .verify_stake(stake_id, Some(account_id))
+            .await
+            .map(|_| ()),
+
+        None => Err(ServerError::new(
+            std::io::ErrorKind::PermissionDenied,
+            &format!("{} has no stake in pool {}", &account_id, &pool_id),
+        )),
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ClientActor {
     pub id: UserId,
+    pub roles: HashSet<UserRole>,
     pub session: Option<Addr<SessionActor>>,
+    pub spectating: Option<Addr<SessionActor>>,
+    pub replay: Option<Addr<ReplayActor>>,
+    pub encoding: Encoding,
     hb: Instant,
     hb_handle: Option<SpawnHandle>,
 }
@@ -39,7 +213,27 @@ impl Handler<ServerMessage> for ClientActor {
     type Result = ();
 
     fn handle(&mut self, msg: ServerMessage, ctx: &mut Self::Context) {
-        ctx.text(msg.to_message());
+        match self.encoding {
+            Encoding::Json => ctx.text(msg.to_message()),
+
+            Encoding::MsgPack => ctx.binary(msg.to_bytes()),
+        }
+    }
+}
+
+impl Handler<Kicked> for ClientActor {
+    type Result = ();
+
+    fn handle(&mut self, Kicked { session_id, by }: Kicked, ctx: &mut Self::Context) {
+        let mut notif = Content::new();
+
+        notif
+            .insert("message", &format!("Kicked from {} by {}", &session_id, &by))
+            .insert("id", &self.id);
+
+        ctx.notify(ServerMessage::Notification(notif));
+
+        self.leave(ctx);
     }
 }
 
@@ -47,7 +241,11 @@ impl Handler<ServerError> for ClientActor {
     type Result = ();
 
     fn handle(&mut self, msg: ServerError, ctx: &mut Self::Context) {
-        ctx.text(msg.to_message());
+        match self.encoding {
+            Encoding::Json => ctx.text(msg.to_message()),
+
+            Encoding::MsgPack => ctx.binary(msg.to_bytes()),
+        }
     }
 }
 
@@ -62,110 +260,560 @@ fn heartbeat(ctx: &mut ws::WebsocketContext<ClientActor>) -> SpawnHandle {
 }
 
 impl ClientActor {
-    pub fn new(id: UserId) -> Self {
+    pub fn new(id: UserId, roles: HashSet<UserRole>) -> Self {
         Self {
             id,
+            roles,
             session: None,
+            spectating: None,
+            replay: None,
+            encoding: Encoding::Json,
             hb: Instant::now(),
             hb_handle: None,
         }
     }
 
     fn leave(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        self.stop_spectating();
+
         if let Some(session) = self.session.take() {
             let msg = Leave(self.id.to_owned());
 
+            let user_id = self.id.to_owned();
+
             ctx.spawn(
-                async move { session.send(msg).await.unwrap() }
-                    .into_actor(self)
-                    .map(|res, act, ctx| match res {
-                        Some((id, player_info)) => {
-                            use schema::player_sessions::dsl::{
-                                ended_at, info, player_sessions, session_id, user_id,
-                            };
-
-                            let mut db = DB.get();
-
-                            let conn = db.as_mut().unwrap();
-
-                            match update(player_sessions)
-                                .filter(user_id.eq(&act.id).and(session_id.eq(id)))
-                                .set((
-                                    ended_at.eq(Local::now().naive_local()),
-                                    info.eq(&player_info),
-                                ))
-                                .execute(conn)
-                            {
-                                Ok(_) => ctx.notify(ServerMessage::Left {
-                                    user_id: act.id.to_owned(),
-                                    managed_entities: player_info.managed_entities,
-                                }),
-
-                                Err(e) => ctx.notify(ServerError::Database(e)),
-                            };
-                        }
-                        None => {}
+                async move {
+                    match session.send(msg).await.unwrap() {
+                        Some((session_id, info)) => run(EndPlayerSession {
+                            session_id,
+                            user_id,
+                            info: info.to_owned(),
+                        })
+                        .await
+                        .map(|_| Some(info)),
+
+                        None => Ok(None),
+                    }
+                }
+                .into_actor(self)
+                .map(|res, act, ctx| match res {
+                    Ok(Some(player_info)) => ctx.notify(ServerMessage::Left {
+                        user_id: act.id.to_owned(),
+                        managed_entities: player_info.managed_entities,
                     }),
+
+                    Ok(None) => {}
+
+                    Err(e) => ctx.notify(e),
+                }),
             );
         }
     }
 
-    fn join(&mut self, session_id: Uuid, ctx: &mut ws::WebsocketContext<Self>) {
-        use schema::player_sessions::dsl::{player_sessions, user_id};
-        use schema::sessions::dsl::{id, sessions};
+    fn join(
+        &mut self,
+        session_id: Uuid,
+        password: Option<String>,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) {
+        self.stop_replay();
 
-        let mut db = DB.get();
+        self.stop_spectating();
 
-        let conn = db.as_mut().unwrap();
+        let msg = PrepareJoin {
+            user_id: self.id.to_owned(),
+            session_id,
+            password,
+        };
 
-        match player_sessions
-            .inner_join(sessions)
-            .filter(id.eq(&session_id).and(user_id.eq(&self.id)))
-            .get_result::<(PlayerSession, Session)>(conn)
-        {
-            Ok((
+        let checks = async move {
+            let (
                 PlayerSession {
                     info, account_id, ..
                 },
                 session,
-            )) => {
-                let mut guard = SESSIONS.lock().unwrap();
+                config,
+                events,
+            ) = run(msg).await?;
 
-                let session_actor = guard
-                    .entry(session.id.to_owned())
-                    .or_insert(SessionActor::new(session, self.id.to_owned()).start())
-                    .to_owned();
+            let account_id = match account_id {
+                Some(s) => AccountId::from_str(&s).ok(),
 
-                let msg = session_actor.send(Join {
-                    user_id: self.id.to_owned(),
-                    player_info: info.unwrap_or_default(),
-                    account_id: match account_id {
-                        Some(s) => AccountId::from_str(&s).ok(),
+                None => None,
+            };
+
+            check_lvl(account_id.to_owned(), config.lvl_required).await?;
+
+            if let Some(pool_id) = session.pool_id.to_owned() {
+                check_stake(account_id.to_owned(), pool_id).await?;
+            }
+
+            Ok::<_, ServerError>((
+                session,
+                config,
+                events,
+                info.unwrap_or_default(),
+                account_id,
+            ))
+        };
+
+        ctx.spawn(checks.into_actor(self).map(move |res, act, ctx| match res {
+            Ok((session, config, events, info, account_id)) => {
+                act.enter(session, config, events, info, account_id, ctx)
+            }
+
+            Err(e) => ctx.notify(e),
+        }));
+    }
+
+    fn enter(
+        &mut self,
+        session: Session,
+        config: GameConfig,
+        events: Vec<SessionEventRow>,
+        player_info: PlayerInfo,
+        account_id: Option<AccountId>,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) {
+        let session_id = session.id.to_owned();
+
+        let host = self.id.to_owned();
+
+        let session_actor = SESSIONS
+            .lock()
+            .unwrap()
+            .entry(session_id.to_owned())
+            .or_insert_with(|| SessionActor::new(session, config, host, events).start())
+            .to_owned();
+
+        let msg = session_actor.send(Join {
+            user_id: self.id.to_owned(),
+            player_info,
+            account_id,
+        });
+
+        //the actor stops itself when the join that started it is rejected
+        let join = async move {
+            msg.await.unwrap_or_else(|e| {
+                Err(ServerError::new(
+                    std::io::ErrorKind::NotConnected,
+                    &e.to_string(),
+                ))
+            })
+        };
+
+        ctx.spawn(join.into_actor(self).map(
+            move |res, act, ctx| match res {
+                Ok((state, players)) => {
+                    act.hb_handle = Some(heartbeat(ctx));
+                    act.session = Some(session_actor);
+
+                    println!("[Server] {:?} has joined {}", &act.id, &session_id);
+
+                    ctx.notify(ServerMessage::Joined {
+                        session_id,
+                        state: state.to_owned(),
+                        players: players.to_owned(),
+                    });
+                }
+
+                Err(e) => ctx.notify(e),
+            },
+        ));
+    }
+
+    //live sessions and replays send the same messages, so only one can be followed at a time
+    fn watch(
+        &mut self,
+        session_id: Uuid,
+        speed: Option<f32>,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) {
+        if let Err(e) = require(&self.roles, Permission::WatchReplay) {
+            return ctx.notify(e);
+        }
+
+        if self.session.is_some() {
+            return ctx.notify(ServerError::new(
+                std::io::ErrorKind::PermissionDenied,
+                "Must leave the current game to watch a replay",
+            ));
+        }
+
+        let speed = match check_speed(speed.unwrap_or(1.0)) {
+            Ok(speed) => speed,
+
+            Err(e) => return ctx.notify(e),
+        };
+
+        self.stop_replay();
+
+        self.stop_spectating();
+
+        self.replay = Some(ReplayActor::new(session_id, ctx.address(), speed).start());
+    }
+
+    fn spectate(
+        &mut self,
+        session_id: Uuid,
+        password: Option<String>,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) {
+        if self.session.is_some() {
+            return ctx.notify(ServerError::new(
+                std::io::ErrorKind::PermissionDenied,
+                "Must leave the current game to spectate",
+            ));
+        }
+
+        let msg = CheckAccess {
+            user_id: self.id.to_owned(),
+            session_id,
+            password,
+        };
+
+        ctx.spawn(
+            async move { run(msg).await }
+                .into_actor(self)
+                .map(move |res, act, ctx| {
+                    if let Err(e) = res {
+                        return ctx.notify(e);
+                    }
+
+                    let session = match SESSIONS.lock().unwrap().get(&session_id) {
+                        Some(session) => session.to_owned(),
+
+                        None => {
+                            return ctx.notify(ServerError::Query(format!(
+                                "Session {} is not running",
+                                &session_id
+                            )))
+                        }
+                    };
+
+                    act.stop_replay();
+
+                    act.stop_spectating();
+
+                    let msg = session.send(Spectate {
+                        user_id: act.id.to_owned(),
+                        actor: ctx.address(),
+                    });
+
+                    ctx.spawn(async move { msg.await.unwrap() }.into_actor(act).map(
+                        move |res, act, ctx| match res {
+                            Ok(_) => act.spectating = Some(session),
+
+                            Err(e) => ctx.notify(e),
+                        },
+                    ));
+                }),
+        );
+    }
+
+    fn stop_spectating(&mut self) {
+        if let Some(session) = self.spectating.take() {
+            session.do_send(StopSpectating(self.id.to_owned()));
+        }
+    }
+
+    fn stop_replay(&mut self) {
+        if let Some(replay) = self.replay.take() {
+            replay.do_send(ReplayStop);
+        }
+    }
+
+    //ending an ended session retries any unresolved pool and player outcomes
+    fn resolve(&mut self, session_id: Uuid, ctx: &mut ws::WebsocketContext<Self>) {
+        if let Some(session) = SESSIONS.lock().unwrap().get(&session_id) {
+            session.do_send(SessionEnd);
+
+            return;
+        }
+
+        ctx.spawn(
+            async move { run(LoadSession(session_id)).await }
+                .into_actor(self)
+                .map(move |res, _act, ctx| match res {
+                    Ok((session, config, events)) if session.ended_at.is_some() => {
+                        let host = session.creator.to_owned();
+
+                        SESSIONS
+                            .lock()
+                            .unwrap()
+                            .entry(session_id)
+                            .or_insert_with(|| {
+                                SessionActor::new(session, config, host, events).start()
+                            })
+                            .do_send(SessionEnd);
+                    }
+
+                    Ok(_) => ctx.notify(ServerError::Query(format!(
+                        "Session {} has not ended",
+                        &session_id
+                    ))),
+
+                    Err(e) => ctx.notify(e),
+                }),
+        );
+    }
+
+    //answered with a Response carrying the same request id, errors included
+    fn wallet(
+        &mut self,
+        request_id: String,
+        account_id: Option<AccountId>,
+        query: WalletQuery,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) {
+        let msg = OwnedAccount {
+            user_id: self.id.to_owned(),
+            account_id,
+        };
+
+        ctx.spawn(
+            async move { wallet::query(run(msg).await?, query).await }
+                .into_actor(self)
+                .map(move |res, _act, ctx| {
+                    let response = match res {
+                        Ok(response) => response,
+
+                        Err(e) => WalletResponse::Error {
+                            kind: e.kind().to_string(),
+                            message: e.to_string(),
+                        },
+                    };
+
+                    ctx.notify(ServerMessage::Response {
+                        request_id,
+                        response,
+                    })
+                }),
+        );
+    }
+
+    fn handle_message(&mut self, msg: ClientMessage, ctx: &mut ws::WebsocketContext<Self>) {
+        match msg {
+            ClientMessage::Update(update) => match (&self.session, &self.spectating) {
+                (Some(game), _) => game.do_send(SessionUpdate {
+                    updater: self.id.to_owned(),
+                    update,
+                }),
+
+                (None, Some(_)) => ctx.notify(ServerError::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    "Spectators cannot send updates",
+                )),
+
+                (None, None) => ctx.notify(ServerError::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    "Must be connected to a game to send updates",
+                )),
+            },
+
+            ClientMessage::Join {
+                session_id,
+                password,
+            } => {
+                self.join(session_id, password, ctx);
+            }
+
+            ClientMessage::Leave => self.leave(ctx),
+
+            ClientMessage::Ack { seq } => {
+                if let Some(session) = &self.session {
+                    session.do_send(TickAck {
+                        user_id: self.id.to_owned(),
+                        seq,
+                    })
+                }
+            }
+
+            ClientMessage::Resync => match &self.session {
+                Some(session) => session.do_send(Resync(self.id.to_owned())),
+
+                None => ctx.notify(ServerError::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    "Must be connected to a game to resync",
+                )),
+            },
+
+            ClientMessage::SubscribeLobby => GLOBAL.do_send(LobbySubscribe {
+                user_id: self.id.to_owned(),
+                actor: ctx.address(),
+            }),
+
+            ClientMessage::UnsubscribeLobby => GLOBAL.do_send(LobbyUnsubscribe(self.id.to_owned())),
+
+            ClientMessage::Whitelist { add, remove } => match &self.session {
+                Some(session) => session.do_send(WhitelistUpdate {
+                    updater: self.id.to_owned(),
+                    add,
+                    remove,
+                }),
+
+                None => ctx.notify(ServerError::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    "Must be connected to a game to change its whitelist",
+                )),
+            },
+
+            ClientMessage::Kick {
+                session_id,
+                user_id,
+            } => match SESSIONS.lock().unwrap().get(&session_id) {
+                Some(session) => {
+                    let msg = session.send(KickPlayer {
+                        user_id,
+                        by: self.id.to_owned(),
+                        moderator: permitted(&self.roles, Permission::KickPlayer),
+                    });
+
+                    ctx.spawn(async move { msg.await.unwrap() }.into_actor(self).map(
+                        |res, _act, ctx| {
+                            if let Err(e) = res {
+                                ctx.notify(e)
+                            }
+                        },
+                    ));
+                }
 
-                        None => None,
+                None => ctx.notify(ServerError::Query(format!(
+                    "Session {} is not running",
+                    &session_id
+                ))),
+            },
+
+            ClientMessage::EndSession { session_id } => {
+                match require(&self.roles, Permission::EndAnySession) {
+                    Ok(_) => match SESSIONS.lock().unwrap().get(&session_id) {
+                        Some(session) => session.do_send(SessionEnd),
+
+                        None => ctx.notify(ServerError::Query(format!(
+                            "Session {} is not running",
+                            &session_id
+                        ))),
                     },
-                });
 
-                ctx.spawn(async move { msg.await.unwrap() }.into_actor(self).map(
-                    move |(state, players), act, ctx| {
-                        act.hb_handle = Some(heartbeat(ctx));
-                        act.session = Some(session_actor);
+                    Err(e) => ctx.notify(e),
+                }
+            }
 
-                        println!("[Server] {:?} has joined {}", &act.id, &session_id);
+            ClientMessage::ResolvePool { session_id } => {
+                match require(&self.roles, Permission::ResolvePool) {
+                    Ok(_) => self.resolve(session_id, ctx),
 
-                        ctx.notify(ServerMessage::Joined {
-                            session_id,
-                            state: state.to_owned(),
-                            players: players.to_owned(),
-                        });
+                    Err(e) => ctx.notify(e),
+                }
+            }
+
+            ClientMessage::Spectate {
+                session_id,
+                password,
+            } => self.spectate(session_id, password, ctx),
+
+            ClientMessage::WatchReplay { session_id, speed } => self.watch(session_id, speed, ctx),
+
+            ClientMessage::SeekReplay { position } => match &self.replay {
+                Some(replay) => replay.do_send(ReplaySeek(Duration::from_millis(position))),
+
+                None => ctx.notify(ServerError::new(
+                    std::io::ErrorKind::NotFound,
+                    "No replay is playing",
+                )),
+            },
+
+            ClientMessage::SetReplaySpeed { speed } => match (&self.replay, check_speed(speed)) {
+                (Some(replay), Ok(speed)) => replay.do_send(ReplaySpeed(speed)),
+
+                (None, _) => ctx.notify(ServerError::new(
+                    std::io::ErrorKind::NotFound,
+                    "No replay is playing",
+                )),
+
+                (_, Err(e)) => ctx.notify(e),
+            },
+
+            ClientMessage::StopReplay => self.stop_replay(),
+
+            ClientMessage::GetCharacter {
+                request_id,
+                account_id,
+            } => self.wallet(request_id, account_id, WalletQuery::Character, ctx),
+
+            ClientMessage::GetBalance {
+                request_id,
+                account_id,
+            } => self.wallet(request_id, account_id, WalletQuery::Balance, ctx),
+
+            ClientMessage::GetStakes {
+                request_id,
+                account_id,
+            } => self.wallet(request_id, account_id, WalletQuery::Stakes, ctx),
+
+            ClientMessage::GetLvl {
+                request_id,
+                account_id,
+            } => self.wallet(request_id, account_id, WalletQuery::Lvl, ctx),
+
+            ClientMessage::GetRewards {
+                request_id,
+                account_id,
+                offset,
+                limit,
+            } => {
+                let msg = RewardHistory {
+                    user_id: self.id.to_owned(),
+                    account_id,
+                    offset,
+                    limit: limit.unwrap_or(REWARDS_PAGE),
+                };
+
+                ctx.spawn(async move { run(msg).await }.into_actor(self).map(
+                    move |res, _act, ctx| {
+                        let response = match res {
+                            Ok(history) => WalletResponse::Rewards(history),
+
+                            Err(e) => WalletResponse::Error {
+                                kind: e.kind().to_string(),
+                                message: e.to_string(),
+                            },
+                        };
+
+                        ctx.notify(ServerMessage::Response {
+                            request_id,
+                            response,
+                        })
                     },
                 ));
             }
 
-            Err(e) => {
-                ctx.notify(ServerError::Database(e));
+            ClientMessage::Message { msg, reciptiants } => {
+                let guard = CLIENTS.lock().unwrap();
+
+                let message = ServerMessage::Message {
+                    msg,
+                    sender: self.id.to_owned(),
+                };
+
+                if reciptiants.len() > 0 {
+                    for recipient in reciptiants {
+                        match guard.get(&recipient) {
+                            Some(actor) => actor.do_send(message.to_owned()),
+
+                            None => ctx.notify(ServerError::Query(
+                                "account id does not exist".to_string(),
+                            )),
+                        }
+                    }
+                } else if let Some(session) = &self.session {
+                    session.do_send(SessionMessage {
+                        msg: message,
+                        exclude: vec![self.id.to_owned()],
+                    })
+                };
             }
-        };
+        }
     }
 }
 
@@ -181,37 +829,27 @@ impl Actor for ClientActor {
             existing.do_send(ServerMessage::Disconnected);
         }
 
-        use schema::player_sessions::dsl::{ended_at, player_sessions, user_id};
-        use schema::sessions::dsl::{ended_at as session_ended_at, id, sessions};
+        let user_id = self.id.to_owned();
 
-        let mut db = DB.get();
+        //password protected sessions have to be rejoined explicitly
+        ctx.spawn(
+            async move { run(RejoinableSession(user_id)).await }
+                .into_actor(self)
+                .map(|res, act, ctx| match res {
+                    Ok(Some(session_id)) => act.join(session_id, None, ctx),
 
-        let conn = db.as_mut().unwrap();
-
-        match player_sessions
-            .inner_join(sessions)
-            .filter(
-                user_id
-                    .eq(&self.id)
-                    .and(session_ended_at.is_not_null())
-                    .and(ended_at.is_not_null()),
-            )
-            .select(id)
-            .get_result::<Uuid>(conn)
-        {
-            Ok(session_id) => {
-                self.join(session_id, ctx);
-            }
-
-            Err(_) => {
-                ctx.notify(ServerMessage::Connected);
-            }
-        };
+                    _ => ctx.notify(ServerMessage::Connected),
+                }),
+        );
     }
 
     fn stopping(&mut self, ctx: &mut Self::Context) -> actix::Running {
         self.leave(ctx);
 
+        self.stop_replay();
+
+        GLOBAL.do_send(LobbyUnsubscribe(self.id.to_owned()));
+
         ctx.notify(ServerMessage::Disconnected);
 
         CLIENTS.lock().unwrap().remove(&self.id);
@@ -245,56 +883,18 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ClientActor {
                     self.hb = Instant::now();
 
                     match from_str::<ClientMessage>(text.trim()) {
-                        Ok(msg) => match msg {
-                            ClientMessage::Update(update) => match &self.session {
-                                Some(game) => game.do_send(SessionUpdate {
-                                    updater: self.id.to_owned(),
-                                    update,
-                                }),
-
-                                None => ctx.notify(ServerError::new(
-                                    std::io::ErrorKind::PermissionDenied,
-                                    "Must be connected to a game to send updates",
-                                )),
-                            },
-
-                            ClientMessage::Join { session_id } => {
-                                self.join(session_id, ctx);
-                            }
-
-                            ClientMessage::Leave => self.leave(ctx),
-
-                            ClientMessage::Message { msg, reciptiants } => {
-                                let guard = CLIENTS.lock().unwrap();
-
-                                let message = ServerMessage::Message {
-                                    msg,
-                                    sender: self.id.to_owned(),
-                                };
-
-                                if reciptiants.len() > 0 {
-                                    for recipient in reciptiants {
-                                        match guard.get(&recipient) {
-                                            Some(actor) => actor.do_send(message.to_owned()),
-
-                                            None => ctx.notify(ServerError::Query(
-                                                "account id does not exist".to_string(),
-                                            )),
-                                        }
-                                    }
-                                } else if let Some(session) = &self.session {
-                                    session.do_send(SessionMessage {
-                                        msg: message,
-                                        exclude: vec![self.id.to_owned()],
-                                    })
-                                };
-                            }
-                        },
+                        Ok(msg) => self.handle_message(msg, ctx),
                         Err(e) => ctx.notify(ServerError::Serde(e)),
                     }
                 }
-                ws::Message::Binary(_) => {
-                    println!("Unexpected binary");
+                ws::Message::Binary(bytes) => {
+                    self.hb = Instant::now();
+
+                    match rmp_serde::from_slice::<ClientMessage>(&bytes) {
+                        Ok(msg) => self.handle_message(msg, ctx),
+
+                        Err(e) => ctx.notify(ServerError::Decode(e)),
+                    }
                 }
                 ws::Message::Close(reason) => {
                     ctx.close(reason);
@@ -1,44 +1,55 @@
-use actix::clock::sleep;
 use delt_d::{
     character::Character,
     staking::{Pool, PoolId, StakeId},
 };
-use near_crypto::{InMemorySigner, PublicKey};
+use near_crypto::PublicKey;
 use near_jsonrpc_client::{
     methods::{self, query::RpcQueryRequest, tx::RpcTransactionError},
-    JsonRpcClient, NEAR_TESTNET_RPC_URL,
+    JsonRpcClient,
 };
 use near_jsonrpc_primitives::types::query::QueryResponseKind;
 use near_primitives::{
     hash::CryptoHash,
-    transaction::SignedTransaction,
     types::{AccountId, Balance, BlockReference, Finality, FunctionArgs},
-    views::{CallResult, FinalExecutionOutcomeView, FinalExecutionStatus, QueryRequest},
+    views::{
+        AccessKeyPermissionView, AccessKeyView, CallResult, FinalExecutionOutcomeView,
+        FinalExecutionStatus, QueryRequest,
+    },
 };
 use serde::Deserialize;
 use serde_json::{from_slice, to_string, Value};
-use std::{
-    collections::{HashMap, HashSet},
-    path::Path,
-    str::FromStr,
-    time::{Duration, Instant},
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    config::near,
+    handlers::messages::ServerError,
+    types::{Content, PlayerOutcome},
 };
 
-use crate::{handlers::messages::ServerError, types::Content};
+use super::signer::{submit, submit_batch, OnSigned};
 
 lazy_static::lazy_static! {
-    pub static ref RPC: JsonRpcClient = JsonRpcClient::connect(NEAR_TESTNET_RPC_URL);
+    pub static ref RPC: JsonRpcClient = JsonRpcClient::connect(near().rpc_url.as_str());
 
-    static ref ADMIN: InMemorySigner = InMemorySigner::from_file(Path::new("C:/Users/j10st/.near-credentials/testnet/delt.testnet.json")).expect("error while parsing admin signer");
+    static ref DELTD: AccountId = near().contracts.deltd.to_owned();
 
-    static ref DELTD: AccountId = AccountId::from_str("delt-d.delt.testnet").unwrap();
+    static ref DELTFT: AccountId = near().contracts.deltft.to_owned();
 
-    static ref DELTFT: AccountId = AccountId::from_str("delt-ft.delt.testnet").unwrap();
+    static ref DELTMT: AccountId = near().contracts.deltmt.to_owned();
+}
 
-    static ref DELTMT: AccountId = AccountId::from_str("delt-mt.delt.testnet").unwrap();
+//value returned by the last action, with the hash of the transaction that carried it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Success {
+    pub tx_hash: Option<CryptoHash>,
+    pub value: Vec<u8>,
 }
 
-pub type Success = Vec<u8>;
+impl Success {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
 
 pub async fn get_pools(owner: Option<AccountId>) -> Result<HashMap<PoolId, Pool>, ServerError> {
     let mut args = Content::new();
@@ -75,27 +86,8 @@ pub async fn create_pool(
     args.insert("pool_results", pool_results);
     args.insert("required_xp", required_xp);
 
-    match get_current_nonce(&ADMIN.account_id, &ADMIN.public_key).await {
-        Ok((hash, current_nonce)) => {
-            match poll_transaction(&methods::broadcast_tx_commit::RpcBroadcastTxCommitRequest {
-                signed_transaction: SignedTransaction::call(
-                    current_nonce + 1,
-                    ADMIN.account_id.to_owned(),
-                    DELTD.to_owned(),
-                    &*ADMIN,
-                    10u128.pow(24),
-                    "create_pool".to_string(),
-                    args.into_bytes(),
-                    5_000_000_000_000,
-                    hash,
-                ),
-            })
-            .await
-            {
-                Ok(bytes) => from_slice::<Pool>(&bytes).map_err(|e| ServerError::Serde(e)),
-                Err(e) => Err(e),
-            }
-        }
+    match submit(DELTD.to_owned(), "create_pool", args.into_bytes(), None).await {
+        Ok(success) => from_slice::<Pool>(&success.value).map_err(ServerError::Serde),
 
         Err(e) => Err(e),
     }
@@ -110,26 +102,7 @@ pub async fn register_stake(
     args.insert("stake_id", &stake_id.to_string());
     args.insert("staker_id", staker_id.as_str());
 
-    match get_current_nonce(&ADMIN.account_id, &ADMIN.public_key).await {
-        Ok((hash, current_nonce)) => {
-            poll_transaction(&methods::broadcast_tx_commit::RpcBroadcastTxCommitRequest {
-                signed_transaction: SignedTransaction::call(
-                    current_nonce + 1,
-                    ADMIN.account_id.to_owned(),
-                    DELTD.to_owned(),
-                    &*ADMIN,
-                    10u128.pow(24),
-                    "register_stake".to_string(),
-                    args.into_bytes(),
-                    5_000_000_000_000,
-                    hash,
-                ),
-            })
-            .await
-        }
-
-        Err(e) => Err(e),
-    }
+    submit(DELTD.to_owned(), "register_stake", args.into_bytes(), None).await
 }
 
 pub async fn unregister_stake(
@@ -146,26 +119,13 @@ pub async fn unregister_stake(
         args.insert("reregister", id.as_str());
     }
 
-    match get_current_nonce(&ADMIN.account_id, &ADMIN.public_key).await {
-        Ok((hash, current_nonce)) => {
-            poll_transaction(&methods::broadcast_tx_commit::RpcBroadcastTxCommitRequest {
-                signed_transaction: SignedTransaction::call(
-                    current_nonce + 1,
-                    ADMIN.account_id.to_owned(),
-                    DELTD.to_owned(),
-                    &*ADMIN,
-                    10u128.pow(24),
-                    "unregister_stake".to_string(),
-                    args.into_bytes(),
-                    5_000_000_000_000,
-                    hash,
-                ),
-            })
-            .await
-        }
-
-        Err(e) => Err(e),
-    }
+    submit(
+        DELTD.to_owned(),
+        "unregister_stake",
+        args.into_bytes(),
+        None,
+    )
+    .await
 }
 
 pub async fn transfer_stake(
@@ -182,26 +142,13 @@ pub async fn transfer_stake(
         args.insert("amount", &x.to_string());
     }
 
-    match get_current_nonce(&ADMIN.account_id, &ADMIN.public_key).await {
-        Ok((hash, current_nonce)) => {
-            poll_transaction(&methods::broadcast_tx_commit::RpcBroadcastTxCommitRequest {
-                signed_transaction: SignedTransaction::call(
-                    current_nonce + 1,
-                    ADMIN.account_id.to_owned(),
-                    DELTD.to_owned(),
-                    &*ADMIN,
-                    10u128.pow(24),
-                    "unregister_stake".to_string(),
-                    args.into_bytes(),
-                    5_000_000_000_000,
-                    hash,
-                ),
-            })
-            .await
-        }
-
-        Err(e) => Err(e),
-    }
+    submit(
+        DELTD.to_owned(),
+        "transfer_stake",
+        args.into_bytes(),
+        None,
+    )
+    .await
 }
 
 pub async fn get_stakes(
@@ -245,7 +192,7 @@ pub async fn verify_stake(
             block_reference: BlockReference::Finality(Finality::Final),
             request: QueryRequest::CallFunction {
                 account_id: DELTD.to_owned(),
-                method_name: "get_stakes".to_string(),
+                method_name: "verify_stake".to_string(),
                 args: FunctionArgs::from(args.into_bytes()),
             },
         })
@@ -257,32 +204,23 @@ pub async fn verify_stake(
     }
 }
 
-pub async fn toggle_pool_active(pool_id: PoolId, toggle: bool) -> Result<Success, ServerError> {
+pub async fn toggle_pool_active(
+    pool_id: PoolId,
+    toggle: bool,
+    on_signed: OnSigned,
+) -> Result<Success, ServerError> {
     let mut args = Content::new();
 
     args.insert("pool_id", &pool_id);
     args.insert("toggle", &toggle.to_string());
 
-    match get_current_nonce(&ADMIN.account_id, &ADMIN.public_key).await {
-        Ok((hash, current_nonce)) => {
-            poll_transaction(&methods::broadcast_tx_commit::RpcBroadcastTxCommitRequest {
-                signed_transaction: SignedTransaction::call(
-                    current_nonce + 1,
-                    ADMIN.account_id.to_owned(),
-                    DELTD.to_owned(),
-                    &*ADMIN,
-                    10u128.pow(24),
-                    "toggle_pool_active".to_string(),
-                    args.into_bytes(),
-                    5_000_000_000_000,
-                    hash,
-                ),
-            })
-            .await
-        }
-
-        Err(e) => Err(e),
-    }
+    submit(
+        DELTD.to_owned(),
+        "toggle_pool_active",
+        args.into_bytes(),
+        Some(on_signed),
+    )
+    .await
 }
 
 pub async fn assert_pool_result(
@@ -297,53 +235,30 @@ pub async fn assert_pool_result(
         args.insert("pool_result", result.as_str());
     }
 
-    match get_current_nonce(&ADMIN.account_id, &ADMIN.public_key).await {
-        Ok((hash, current_nonce)) => {
-            poll_transaction(&methods::broadcast_tx_commit::RpcBroadcastTxCommitRequest {
-                signed_transaction: SignedTransaction::call(
-                    current_nonce + 1,
-                    ADMIN.account_id.to_owned(),
-                    DELTD.to_owned(),
-                    &*ADMIN,
-                    10u128.pow(24),
-                    "assert_pool_result".to_string(),
-                    args.into_bytes(),
-                    5_000_000_000_000,
-                    hash,
-                ),
-            })
-            .await
-        }
-
-        Err(e) => Err(e),
-    }
+    submit(
+        DELTD.to_owned(),
+        "assert_pool_result",
+        args.into_bytes(),
+        None,
+    )
+    .await
 }
 
-pub async fn distribute_stakes(pool_id: PoolId) -> Result<Success, ServerError> {
+pub async fn distribute_stakes(
+    pool_id: PoolId,
+    on_signed: OnSigned,
+) -> Result<Success, ServerError> {
     let mut args = Content::new();
 
     args.insert("pool_id", &pool_id);
 
-    match get_current_nonce(&ADMIN.account_id, &ADMIN.public_key).await {
-        Ok((hash, current_nonce)) => {
-            poll_transaction(&methods::broadcast_tx_commit::RpcBroadcastTxCommitRequest {
-                signed_transaction: SignedTransaction::call(
-                    current_nonce + 1,
-                    ADMIN.account_id.to_owned(),
-                    DELTD.to_owned(),
-                    &*ADMIN,
-                    10u128.pow(24),
-                    "distribute_stakes".to_string(),
-                    args.into_bytes(),
-                    5_000_000_000_000,
-                    hash,
-                ),
-            })
-            .await
-        }
-
-        Err(e) => Err(e),
-    }
+    submit(
+        DELTD.to_owned(),
+        "distribute_stakes",
+        args.into_bytes(),
+        Some(on_signed),
+    )
+    .await
 }
 
 pub async fn get_character(account_id: &AccountId) -> Result<Character, ServerError> {
@@ -395,80 +310,116 @@ pub async fn set_default_attributes(attributes: &Value) -> Result<Success, Serve
 
     args.insert("attributes", &to_string(attributes).unwrap());
 
-    match get_current_nonce(&ADMIN.account_id, &ADMIN.public_key).await {
-        Ok((hash, current_nonce)) => {
-            poll_transaction(&methods::broadcast_tx_commit::RpcBroadcastTxCommitRequest {
-                signed_transaction: SignedTransaction::call(
-                    current_nonce + 1,
-                    ADMIN.account_id.to_owned(),
-                    DELTD.to_owned(),
-                    &*ADMIN,
-                    10u128.pow(24),
-                    "set_default_attributes".to_string(),
-                    args.into_bytes(),
-                    5_000_000_000_000,
-                    hash,
-                ),
-            })
-            .await
-        }
-
-        Err(e) => Err(e),
-    }
+    submit(
+        DELTD.to_owned(),
+        "set_default_attributes",
+        args.into_bytes(),
+        None,
+    )
+    .await
 }
 
-pub async fn give_xp(account_id: &AccountId, xp: &u128) -> Result<Success, ServerError> {
+pub async fn give_xp(
+    account_id: &AccountId,
+    xp: &u128,
+    on_signed: OnSigned,
+) -> Result<Success, ServerError> {
     let mut args = Content::new();
 
     args.insert("account_id", account_id);
     args.insert("amount", xp);
 
-    match get_current_nonce(&ADMIN.account_id, &ADMIN.public_key).await {
-        Ok((hash, current_nonce)) => {
-            poll_transaction(&methods::broadcast_tx_commit::RpcBroadcastTxCommitRequest {
-                signed_transaction: SignedTransaction::call(
-                    current_nonce + 1,
-                    ADMIN.account_id.to_owned(),
-                    DELTD.to_owned(),
-                    &*ADMIN,
-                    10u128.pow(24),
-                    "ft_mint".to_string(),
-                    args.into_bytes(),
-                    5_000_000_000_000,
-                    hash,
-                ),
-            })
-            .await
-        }
-
-        Err(e) => Err(e),
-    }
+    submit(
+        DELTD.to_owned(),
+        "ft_mint",
+        args.into_bytes(),
+        Some(on_signed),
+    )
+    .await
 }
 
-pub async fn kill_character(account_id: &AccountId) -> Result<Success, ServerError> {
+pub async fn kill_character(
+    account_id: &AccountId,
+    on_signed: OnSigned,
+) -> Result<Success, ServerError> {
     let mut args = Content::new();
 
     args.insert("account_id", &account_id);
 
-    match get_current_nonce(&ADMIN.account_id, &ADMIN.public_key).await {
-        Ok((hash, current_nonce)) => {
-            poll_transaction(&methods::broadcast_tx_commit::RpcBroadcastTxCommitRequest {
-                signed_transaction: SignedTransaction::call(
-                    current_nonce + 1,
-                    ADMIN.account_id.to_owned(),
-                    DELTD.to_owned(),
-                    &*ADMIN,
-                    10u128.pow(24),
-                    "death".to_string(),
-                    args.into_bytes(),
-                    5_000_000_000_000,
-                    hash,
-                ),
-            })
-            .await
-        }
+    submit(
+        DELTD.to_owned(),
+        "death",
+        args.into_bytes(),
+        Some(on_signed),
+    )
+    .await
+}
 
-        Err(e) => Err(e),
+//one transaction for every outcome, xp mints and deaths apply together or not at all
+pub async fn settle_players(
+    outcomes: &[PlayerOutcome],
+    on_signed: OnSigned,
+) -> Result<Success, ServerError> {
+    let calls = outcomes
+        .iter()
+        .map(|PlayerOutcome { account_id, xp }| {
+            let mut args = Content::new();
+
+            args.insert("account_id", account_id);
+
+            match xp {
+                Some(xp) => {
+                    args.insert("amount", xp);
+
+                    ("ft_mint", args.into_bytes())
+                }
+
+                None => ("death", args.into_bytes()),
+            }
+        })
+        .collect();
+
+    submit_batch(DELTD.to_owned(), calls, Some(on_signed)).await
+}
+
+//None when the transaction never reached the chain or failed there, so its calls can be sent again
+pub async fn tx_status(
+    signer_id: &AccountId,
+    tx_hash: &CryptoHash,
+) -> Result<Option<Success>, ServerError> {
+    match RPC
+        .call(methods::tx::RpcTransactionStatusRequest {
+            transaction_info: methods::tx::TransactionInfo::TransactionId {
+                hash: tx_hash.to_owned(),
+                account_id: signer_id.to_owned(),
+            },
+        })
+        .await
+    {
+        Ok(FinalExecutionOutcomeView {
+            status: FinalExecutionStatus::SuccessValue(value),
+            transaction,
+            ..
+        }) => Ok(Some(Success {
+            tx_hash: Some(transaction.hash),
+            value,
+        })),
+
+        Ok(FinalExecutionOutcomeView {
+            status: FinalExecutionStatus::Failure(_),
+            ..
+        }) => Ok(None),
+
+        Ok(_) => Err(ServerError::Transaction(format!(
+            "Transaction {} is not final yet",
+            tx_hash
+        ))),
+
+        Err(e) => match e.handler_error() {
+            Some(RpcTransactionError::UnknownTransaction { .. }) => Ok(None),
+
+            _ => Err(ServerError::Query(e.to_string())),
+        },
     }
 }
 
@@ -497,8 +448,44 @@ where
     }
 }
 
+//function call keys can only act on the contract they were issued for, so ownership needs full access
+pub async fn verify_access_key(
+    account_id: &AccountId,
+    public_key: &PublicKey,
+) -> Result<(), ServerError> {
+    match RPC
+        .call(methods::query::RpcQueryRequest {
+            block_reference: BlockReference::Finality(Finality::Final),
+            request: QueryRequest::ViewAccessKey {
+                account_id: account_id.to_owned(),
+                public_key: public_key.to_owned(),
+            },
+        })
+        .await
+    {
+        Ok(response) => match response.kind {
+            QueryResponseKind::AccessKey(AccessKeyView {
+                permission: AccessKeyPermissionView::FullAccess,
+                ..
+            }) => Ok(()),
+
+            QueryResponseKind::AccessKey(_) => Err(ServerError::new(
+                std::io::ErrorKind::PermissionDenied,
+                &format!("{} is not a full access key of {}", public_key, account_id),
+            )),
+
+            _ => Err(ServerError::new(
+                std::io::ErrorKind::InvalidData,
+                "Expected AccessKey",
+            )),
+        },
+
+        Err(e) => Err(ServerError::Query(e.to_string())),
+    }
+}
+
 // https://github.com/near/near-jsonrpc-client-rs/blob/master/examples/create_account.rs
-async fn get_current_nonce(
+pub(super) async fn get_current_nonce(
     account_id: &AccountId,
     public_key: &PublicKey,
 ) -> Result<(CryptoHash, u64), ServerError> {
@@ -524,52 +511,3 @@ async fn get_current_nonce(
         Err(e) => Err(ServerError::Query(e.handler_error().unwrap().to_string())),
     }
 }
-
-async fn poll_transaction(
-    req: &methods::broadcast_tx_commit::RpcBroadcastTxCommitRequest,
-) -> Result<Vec<u8>, ServerError> {
-    let sent_at = Instant::now();
-    loop {
-        match RPC.call(req).await {
-            Ok(FinalExecutionOutcomeView {
-                status: FinalExecutionStatus::SuccessValue(bytes),
-                ..
-            }) => {
-                break Ok(bytes);
-            }
-
-            Ok(FinalExecutionOutcomeView {
-                status: FinalExecutionStatus::Failure(e),
-                ..
-            }) => {
-                break Err(ServerError::Transaction(e.to_string()));
-            }
-
-            Err(error) => {
-                let e = error
-                    .handler_error()
-                    .unwrap_or(&RpcTransactionError::TimeoutError);
-
-                match e {
-                    RpcTransactionError::TimeoutError
-                    | RpcTransactionError::UnknownTransaction { .. } => {
-                        sleep(Duration::from_secs(2)).await;
-                        continue;
-                    }
-
-                    _ => {
-                        break Err(ServerError::Transaction(e.to_string()));
-                    }
-                }
-            }
-
-            _ => {}
-        };
-
-        if Instant::now().duration_since(sent_at).as_secs() > 60 {
-            break Err(ServerError::Transaction(
-                RpcTransactionError::TimeoutError.to_string(),
-            ));
-        };
-    }
-}
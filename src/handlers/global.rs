@@ -1,78 +1,81 @@
-use actix::{Actor, ActorFutureExt, AsyncContext, Context, Handler, WrapFuture};
-use chrono::Local;
-use delt_d::staking::Pool;
-use diesel::{prelude::*, update};
-use near_primitives::types::AccountId;
+use actix::{Actor, ActorFutureExt, Addr, AsyncContext, Context, Handler, WrapFuture};
 
 use std::{
-    str::FromStr,
+    collections::HashMap,
     time::{Duration, Instant},
 };
+use uuid::Uuid;
 
 use crate::{
-    db::{
-        models::{PlayerSession, PoolRef, Session},
-        schema, DB,
-    },
-    handlers::{messages::SessionEnd, session::SessionActor},
-    types::UserId,
+    db::actor::{run, JoinableSessions, UnresolvedSessions},
+    handlers::{client::ClientActor, messages::SessionEnd, session::SessionActor},
+    types::{ChainOperation, UserId},
 };
 
 use super::{
-    contract_methods::{assert_pool_result, distribute_stakes, get_pools, give_xp, kill_character},
-    messages::{PlayerSessionResolve, ServerError, SessionResolve},
+    messages::{
+        LobbyEvent, LobbySubscribe, LobbyUnsubscribe, LobbyUpdate, PlayerSessionResolve,
+        ServerMessage, SessionResolve, SessionSettle,
+    },
+    outbox::store,
+    signer::MAX_BATCH_CALLS,
     SESSIONS,
 };
 
 const GLOBAL_TICK_INTERVAL: Duration = Duration::from_millis(1000 / 60);
 pub struct GlobalActor {
     tick: Instant,
+    lobby: HashMap<UserId, Addr<ClientActor>>,
 }
 
 impl Default for GlobalActor {
     fn default() -> Self {
         Self {
             tick: Instant::now(),
+            lobby: HashMap::new(),
         }
     }
 }
 
+fn enqueue(session_id: Uuid, operations: Vec<ChainOperation>, context: &'static str) {
+    actix::spawn(async move {
+        if let Err(e) = store().enqueue(operations).await {
+            println!(
+                "[Server] DB Error During {} - {}: {}",
+                context,
+                session_id,
+                e.to_string()
+            )
+        }
+    });
+}
+
 impl Actor for GlobalActor {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        use schema::player_sessions::dsl::{player_sessions, resolved_at};
-        use schema::pools::dsl::{pools, resolved_at as pool_resolved_at};
-        use schema::sessions::dsl::{ended_at, sessions};
-
-        let mut db = DB.get();
-
-        let conn = db.as_mut().unwrap();
-
-        let mut guard = SESSIONS.lock().unwrap();
-
-        match sessions
-            .inner_join(pools)
-            .inner_join(player_sessions)
-            .filter(
-                ended_at
-                    .is_not_null()
-                    .and(pool_resolved_at.is_null().or(resolved_at.is_null())),
-            )
-            .get_results::<(Session, PoolRef, PlayerSession)>(conn)
-        {
-            Ok(s) => {
-                for (session, _, _) in s {
-                    let session_actor = guard.entry(session.id.to_owned()).or_insert(
-                        SessionActor::new(session.to_owned(), session.creator.to_owned()).start(),
-                    );
-
-                    session_actor.do_send(SessionEnd);
-                }
-            }
+        ctx.spawn(
+            async { run(UnresolvedSessions).await }
+                .into_actor(self)
+                .map(|res, _act, _ctx| match res {
+                    Ok(unresolved) => {
+                        let mut guard = SESSIONS.lock().unwrap();
+
+                        for (session, config, events) in unresolved {
+                            let host = session.creator.to_owned();
+
+                            guard
+                                .entry(session.id.to_owned())
+                                .or_insert_with(|| {
+                                    SessionActor::new(session, config, host, events).start()
+                                })
+                                .do_send(SessionEnd);
+                        }
+                    }
 
-            Err(_) => {}
-        }
+                    Err(e) => println!("[Server] DB Error Resuming Sessions: {}", e.to_string()),
+                }),
+        );
 
         ctx.run_interval(GLOBAL_TICK_INTERVAL, |act, _ctx| {
             act.tick = Instant::now();
@@ -86,85 +89,22 @@ impl Handler<SessionResolve> for GlobalActor {
     fn handle(
         &mut self,
         SessionResolve {
-            results,
-            pool_id,
             session_id,
+            pool_id,
+            strategy,
+            outcome,
         }: SessionResolve,
-        ctx: &mut Self::Context,
+        _: &mut Self::Context,
     ) {
-        let pid = pool_id.to_owned();
-
-        ctx.spawn(
-            async move {
-                match get_pools(None).await {
-                    Ok(all_pools) => match all_pools.get(&pool_id) {
-                        Some(Pool {
-                            required_stakes,
-                            result,
-                            ..
-                        }) if result.is_none() => {
-                            let mut final_result = None;
-
-                            for (aid, _) in results.iter() {
-                                for (res, stakes) in required_stakes.0.iter() {
-                                    let pool_result = AccountId::from_str(res.as_str()).unwrap();
-
-                                    if aid == &pool_result {
-                                        final_result = Some(pool_result);
-                                        break;
-                                    } else {
-                                        for (account_id, _) in stakes.iter() {
-                                            if account_id.as_str() == aid.as_str() {
-                                                final_result = Some(pool_result);
-
-                                                break;
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-
-                            match assert_pool_result(pool_id.to_owned(), final_result).await {
-                                Ok(_) => distribute_stakes(pool_id.to_owned()).await,
-
-                                Err(e) => Err(e),
-                            }
-                        }
-
-                        Some(_) => Err(ServerError::Transaction(
-                            format!("Pool has already been resolved {}", &pool_id).to_string(),
-                        )),
-
-                        None => Err(ServerError::Query(
-                            format!("Pool has been removed {}", &pool_id).to_string(),
-                        )),
-                    },
-
-                    Err(e) => Err(e),
-                }
-            }
-            .into_actor(self)
-            .map(move |res, _act, _ctx| match res {
-                Ok(_) => {
-                    let mut db = DB.get();
-
-                    let conn = db.as_mut().unwrap();
-
-                    use schema::pools::dsl::{id, pools, resolved_at};
-
-                    update(pools)
-                        .filter(id.eq(&pid))
-                        .set(resolved_at.eq(Local::now().naive_local()))
-                        .execute(conn)
-                        .ok();
-                }
-
-                Err(e) => println!(
-                    "[Server] RPC Error During Session End - {}: {}",
-                    &session_id,
-                    e.to_string()
-                ),
-            }),
+        enqueue(
+            session_id,
+            vec![ChainOperation::AssertPoolResult {
+                session_id,
+                pool_id,
+                strategy,
+                outcome,
+            }],
+            "Session End",
         );
     }
 }
@@ -179,76 +119,379 @@ impl Handler<PlayerSessionResolve> for GlobalActor {
             account_id,
             xp,
         }: PlayerSessionResolve,
-        ctx: &mut Self::Context,
+        _: &mut Self::Context,
     ) {
-        use schema::player_sessions::dsl::{
-            account_id as aid, player_sessions, resolved_at, session_id as id,
+        let operation = match xp {
+            Some(xp) => ChainOperation::GiveXp {
+                session_id,
+                account_id,
+                xp,
+            },
+
+            None => ChainOperation::KillCharacter {
+                session_id,
+                account_id,
+            },
         };
 
-        let mut db = DB.get();
+        enqueue(session_id, vec![operation], "Player Resolve");
+    }
+}
 
-        let conn = db.as_mut().unwrap();
+impl Handler<SessionSettle> for GlobalActor {
+    type Result = ();
 
-        match player_sessions
-            .filter(
-                aid.eq(account_id.as_str())
-                    .and(id.eq(&session_id))
-                    .and(resolved_at.is_null()),
-            )
-            .get_result::<PlayerSession>(conn)
-        {
-            Ok(_) => {
-                ctx.spawn(
-                    async move {
-                        match xp {
-                            Some(xp) => match give_xp(&account_id, &xp).await {
-                                Ok(_) => Ok(account_id),
-                                Err(e) => Err(e),
-                            },
-
-                            None => match kill_character(&account_id).await {
-                                Ok(_) => Ok(account_id),
-                                Err(e) => Err(e),
-                            },
-                        }
-                    }
-                    .into_actor(self)
-                    .map(move |res, _act, _ctx| match res {
-                        Ok(account_id) => {
-                            let mut db = DB.get();
-
-                            let conn = db.as_mut().unwrap();
-
-                            use schema::accounts::dsl::{account_id as aid, accounts, user_id};
-
-                            match accounts
-                                .filter(aid.eq(account_id.to_string()))
-                                .select(user_id)
-                                .get_result::<UserId>(conn)
-                            {
-                                Ok(uid) => {
-                                    use schema::player_sessions::dsl::user_id;
-
-                                    match update(player_sessions)
-                                        .filter(id.eq(&session_id).and(user_id.eq(&uid)))
-                                        .set(resolved_at.eq(Local::now().naive_local()))
-                                        .execute(conn)
-                                    {
-                                        Ok(_) => {}
-
-                                        Err(e) => println!("[Server] DB Error: {}", e.to_string()),
-                                    }
-                                }
-
-                                Err(e) => println!("[Server] Internal Error: {}", e.to_string()),
-                            }
-                        }
-                        Err(e) => println!("[Server] RPC Error: {}", e.to_string()),
-                    }),
-                );
+    fn handle(
+        &mut self,
+        SessionSettle {
+            session_id,
+            mut outcomes,
+        }: SessionSettle,
+        _: &mut Self::Context,
+    ) {
+        //each batch is one transaction, all of them or none get recorded as resolved
+        //sorted so a repeated settlement splits into the same batches
+        outcomes.sort_by(|a, b| a.account_id.cmp(&b.account_id));
+
+        let operations = outcomes
+            .chunks(MAX_BATCH_CALLS)
+            .map(|outcomes| ChainOperation::SettlePlayers {
+                session_id,
+                outcomes: outcomes.to_vec(),
+            })
+            .collect();
+
+        enqueue(session_id, operations, "Session Settlement");
+    }
+}
+
+impl Handler<LobbySubscribe> for GlobalActor {
+    type Result = ();
+
+    fn handle(&mut self, LobbySubscribe { user_id, actor }: LobbySubscribe, _: &mut Self::Context) {
+        let subscriber = actor.to_owned();
+
+        actix::spawn(async move {
+            match run(JoinableSessions).await {
+                Ok(sessions) => {
+                    subscriber.do_send(ServerMessage::Lobby(LobbyUpdate::Sessions(sessions)))
+                }
+
+                Err(e) => subscriber.do_send(e),
             }
+        });
+
+        self.lobby.insert(user_id, actor);
+    }
+}
+
+impl Handler<LobbyUnsubscribe> for GlobalActor {
+    type Result = ();
+
+    fn handle(&mut self, LobbyUnsubscribe(user_id): LobbyUnsubscribe, _: &mut Self::Context) {
+        self.lobby.remove(&user_id);
+    }
+}
+
+impl Handler<LobbyEvent> for GlobalActor {
+    type Result = ();
+
+    fn handle(&mut self, LobbyEvent(update): LobbyEvent, _: &mut Self::Context) {
+        for (_, actor) in self.lobby.iter() {
+            actor.do_send(ServerMessage::Lobby(update.to_owned()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{str::FromStr, time::Duration};
+
+    use actix::{Actor, Context, Handler, Message};
+    use diesel::{insert_into, prelude::*};
+    use near_primitives::types::AccountId;
+    use uuid::Uuid;
+
+    use crate::{
+        db::{
+            connection,
+            models::{NewGame, NewSession, NewUser},
+            run_migrations, schema,
+        },
+        handlers::{
+            memory_chain::{install, ChainCall, MemoryChain},
+            memory_outbox::{install as install_outbox, install_database, MemoryOutbox},
+            messages::{PlayerSessionResolve, SessionResolve, SessionSettle},
+            outbox::{OutboxActor, ProcessOutbox, DONE, PENDING},
+        },
+        types::{Content, GameConfig, PlayerOutcome, ResultStrategy, SessionOutcome, SessionState},
+    };
+
+    use super::GlobalActor;
+
+    fn seed_session() -> Uuid {
+        let mut conn = connection().unwrap();
+
+        let user_id = format!("resolver-{}", Uuid::new_v4());
+
+        insert_into(schema::users::table)
+            .values(&NewUser {
+                id: user_id.to_owned(),
+                password: String::new(),
+                email: format!("{}@test", &user_id),
+            })
+            .execute(&mut conn)
+            .unwrap();
+
+        insert_into(schema::games::table)
+            .values(&NewGame {
+                id: user_id.to_owned(),
+                creator: user_id.to_owned(),
+                config: GameConfig::default(),
+                expiry: None,
+            })
+            .execute(&mut conn)
+            .unwrap();
+
+        insert_into(schema::sessions::table)
+            .values(&NewSession {
+                game_id: user_id.to_owned(),
+                pool_id: None,
+                creator: user_id,
+                password: None,
+                private: true,
+                state: SessionState::default(),
+            })
+            .returning(schema::sessions::id)
+            .get_result::<Uuid>(&mut conn)
+            .unwrap()
+    }
+
+    //accounts unique to this run, the outbox may still hold entries from earlier ones
+    fn account() -> AccountId {
+        AccountId::from_str(&format!("{}.testnet", Uuid::new_v4().simple())).unwrap()
+    }
+
+    async fn calls_for(chain: &MemoryChain, accounts: &[AccountId], n: usize) -> Vec<ChainCall> {
+        let ours = || {
+            chain
+                .calls()
+                .into_iter()
+                .filter(|call| match call {
+                    ChainCall::GiveXp(account_id, _) | ChainCall::KillCharacter(account_id) => {
+                        accounts.contains(account_id)
+                    }
+
+                    ChainCall::SettlePlayers(outcomes) => outcomes
+                        .iter()
+                        .any(|outcome| accounts.contains(&outcome.account_id)),
 
-            Err(_) => {}
+                    _ => false,
+                })
+                .collect::<Vec<ChainCall>>()
         };
+
+        for _ in 0..50 {
+            if ours().len() >= n {
+                break;
+            }
+
+            actix::clock::sleep(Duration::from_millis(100)).await;
+        }
+
+        ours()
+    }
+
+    //handled without starting the actor, which would resume sessions from the database
+    fn handle<M>(msg: M)
+    where
+        M: Message<Result = ()>,
+        GlobalActor: Handler<M, Result = ()>,
+    {
+        GlobalActor::default().handle(msg, &mut Context::new());
+    }
+
+    async fn until(done: impl Fn() -> bool) {
+        for _ in 0..50 {
+            if done() {
+                break;
+            }
+
+            actix::clock::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    #[actix::test]
+    async fn resolutions_are_sent_through_the_outbox_once() {
+        let chain = MemoryChain::new();
+
+        let _installed = install(&chain).await;
+
+        let store = MemoryOutbox::new();
+
+        let _outbox_installed = install_outbox(&store).await;
+
+        let session_id = Uuid::new_v4();
+
+        let (survivor, casualty, settled) = (account(), account(), account());
+
+        for (account_id, xp) in [
+            (survivor.to_owned(), Some(10)),
+            (casualty.to_owned(), None),
+            //enqueued under the same key as the first
+            (survivor.to_owned(), Some(10)),
+        ] {
+            handle(PlayerSessionResolve {
+                session_id,
+                account_id,
+                xp,
+            });
+        }
+
+        let outcomes = vec![PlayerOutcome {
+            account_id: settled.to_owned(),
+            xp: Some(3),
+        }];
+
+        handle(SessionSettle {
+            session_id,
+            outcomes: outcomes.to_owned(),
+        });
+
+        //the pool was never created, nothing is asserted for it
+        let pool_id = format!("missing-{}", Uuid::new_v4());
+
+        handle(SessionResolve {
+            session_id,
+            pool_id: pool_id.to_owned(),
+            strategy: ResultStrategy::LastSurvivor,
+            outcome: SessionOutcome {
+                players: vec![],
+                data: Content::new(),
+            },
+        });
+
+        until(|| store.entries().len() == 4).await;
+
+        assert_eq!(store.entries().len(), 4);
+
+        let outbox = OutboxActor::default().start();
+
+        outbox.send(ProcessOutbox).await.unwrap();
+
+        let pool_key = format!("pool_result:{}", &pool_id);
+
+        until(|| {
+            store
+                .entries()
+                .iter()
+                .all(|entry| entry.status == DONE || entry.attempts > 0)
+        })
+        .await;
+
+        //nothing due is left, done entries are not claimed again
+        outbox.send(ProcessOutbox).await.unwrap();
+
+        actix::clock::sleep(Duration::from_millis(200)).await;
+
+        let calls = chain.calls();
+
+        assert_eq!(calls.len(), 3);
+
+        assert!(calls.contains(&ChainCall::GiveXp(survivor, 10)));
+        assert!(calls.contains(&ChainCall::KillCharacter(casualty)));
+        assert!(calls.contains(&ChainCall::SettlePlayers(outcomes)));
+
+        for entry in store.entries() {
+            match entry.idempotency_key == pool_key {
+                true => {
+                    assert_eq!(entry.status, PENDING);
+                    assert_eq!(entry.attempts, 1);
+                }
+
+                false => assert_eq!(entry.status, DONE),
+            }
+        }
+    }
+
+    #[actix::test]
+    #[ignore = "needs a postgres DATABASE_URL"]
+    async fn resolutions_reach_the_chain_once() {
+        run_migrations();
+
+        let chain = MemoryChain::new();
+
+        let _installed = install(&chain).await;
+
+        let _outbox_installed = install_database().await;
+
+        let global = GlobalActor::default().start();
+
+        let session_id = seed_session();
+
+        let (survivor, casualty, settled) = (account(), account(), account());
+
+        let accounts = [survivor.to_owned(), casualty.to_owned(), settled.to_owned()];
+
+        for (account_id, xp) in [
+            (survivor.to_owned(), Some(10)),
+            (casualty.to_owned(), None),
+            //enqueued under the same key as the first
+            (survivor.to_owned(), Some(10)),
+        ] {
+            global
+                .send(PlayerSessionResolve {
+                    session_id,
+                    account_id,
+                    xp,
+                })
+                .await
+                .unwrap();
+        }
+
+        let outcomes = vec![PlayerOutcome {
+            account_id: settled.to_owned(),
+            xp: Some(3),
+        }];
+
+        global
+            .send(SessionSettle {
+                session_id,
+                outcomes: outcomes.to_owned(),
+            })
+            .await
+            .unwrap();
+
+        //the pool was never created, nothing is asserted for it
+        global
+            .send(SessionResolve {
+                session_id,
+                pool_id: format!("missing-{}", Uuid::new_v4()),
+                strategy: ResultStrategy::LastSurvivor,
+                outcome: SessionOutcome {
+                    players: vec![],
+                    data: Content::new(),
+                },
+            })
+            .await
+            .unwrap();
+
+        calls_for(&chain, &accounts, 3).await;
+
+        //give duplicates a chance to show up
+        actix::clock::sleep(Duration::from_millis(500)).await;
+
+        let calls = calls_for(&chain, &accounts, 3).await;
+
+        assert_eq!(calls.len(), 3);
+
+        assert!(calls.contains(&ChainCall::GiveXp(survivor, 10)));
+        assert!(calls.contains(&ChainCall::KillCharacter(casualty)));
+        assert!(calls.contains(&ChainCall::SettlePlayers(outcomes)));
+
+        assert!(!chain
+            .calls()
+            .iter()
+            .any(|call| matches!(call, ChainCall::AssertPoolResult(..))));
     }
 }
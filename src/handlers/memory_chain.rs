@@ -0,0 +1,512 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use delt_d::{
+    character::Character,
+    staking::{Pool, PoolId, StakeId},
+};
+use futures::{
+    future::{ready, FutureExt},
+    lock::{Mutex as AsyncMutex, MutexGuard},
+};
+use near_crypto::PublicKey;
+use near_primitives::{
+    hash::CryptoHash,
+    types::{AccountId, Balance},
+};
+use serde_json::Value;
+
+use crate::types::PlayerOutcome;
+
+use super::{
+    chain::{set_chain, Chain, ChainResult},
+    contract_methods::Success,
+    messages::ServerError,
+    signer::OnSigned,
+};
+
+lazy_static::lazy_static! {
+    static ref INSTALLED: AsyncMutex<()> = AsyncMutex::new(());
+}
+
+//the chain is global, tests hold the guard for as long as their chain is installed
+pub async fn install(chain: &MemoryChain) -> MutexGuard<'static, ()> {
+    let guard = INSTALLED.lock().await;
+
+    set_chain(Arc::new(chain.to_owned()));
+
+    guard
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChainCall {
+    CreatePool(PoolId),
+    RegisterStake(String, AccountId),
+    UnregisterStake(String, AccountId),
+    TransferStake(String, AccountId),
+    TogglePoolActive(PoolId, bool),
+    AssertPoolResult(PoolId, Option<AccountId>),
+    DistributeStakes(PoolId),
+    SetDefaultAttributes(Value),
+    GiveXp(AccountId, u128),
+    KillCharacter(AccountId),
+    SettlePlayers(Vec<PlayerOutcome>),
+}
+
+#[derive(Default)]
+pub struct MemoryState {
+    pub pools: HashMap<PoolId, Pool>,
+    pub active: HashMap<PoolId, bool>,
+    pub results: HashMap<PoolId, Option<AccountId>>,
+    //keyed by the stake id string, StakeId is opaque here
+    pub stakes: HashMap<String, (StakeId, AccountId, Option<PoolId>)>,
+    pub characters: HashMap<AccountId, Character>,
+    //killed characters, the contract refuses to kill one twice
+    pub dead: HashSet<AccountId>,
+    pub balances: HashMap<AccountId, Balance>,
+    //full access keys
+    pub access_keys: HashMap<AccountId, HashSet<PublicKey>>,
+    //signed transactions that landed
+    pub transactions: HashMap<CryptoHash, Success>,
+    pub nonce: u64,
+    //transactions still land but their callers hear a timeout, as a dropped rpc response
+    pub lose_responses: bool,
+    pub calls: Vec<ChainCall>,
+}
+
+//simulates the delt contracts in memory, pools and characters are seeded since they are built by the contract
+#[derive(Default, Clone)]
+pub struct MemoryChain {
+    pub state: Arc<Mutex<MemoryState>>,
+}
+
+impl MemoryChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_balance(self, account_id: AccountId, balance: Balance) -> Self {
+        self.state
+            .lock()
+            .unwrap()
+            .balances
+            .insert(account_id, balance);
+
+        self
+    }
+
+    pub fn with_access_key(self, account_id: AccountId, public_key: PublicKey) -> Self {
+        self.state
+            .lock()
+            .unwrap()
+            .access_keys
+            .entry(account_id)
+            .or_default()
+            .insert(public_key);
+
+        self
+    }
+
+    pub fn losing_responses(self) -> Self {
+        self.state.lock().unwrap().lose_responses = true;
+
+        self
+    }
+
+    pub fn signer_id() -> AccountId {
+        "memory.testnet".parse().unwrap()
+    }
+
+    pub fn calls(&self) -> Vec<ChainCall> {
+        self.state.lock().unwrap().calls.to_owned()
+    }
+
+    fn call<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut MemoryState) -> Result<T, ServerError>,
+    ) -> ChainResult<T> {
+        let res = f(&mut self.state.lock().unwrap());
+
+        ready(res).boxed()
+    }
+
+    //signs and records a transaction for f, which applies it once signed
+    fn transact(
+        &self,
+        on_signed: OnSigned,
+        f: impl FnOnce(&mut MemoryState) -> Result<Success, ServerError> + Send + 'static,
+    ) -> ChainResult<Success> {
+        let state = self.state.to_owned();
+
+        async move {
+            let tx_hash = {
+                let mut state = state.lock().unwrap();
+
+                state.nonce += 1;
+
+                CryptoHash::hash_bytes(&state.nonce.to_le_bytes())
+            };
+
+            on_signed(Self::signer_id(), tx_hash).await?;
+
+            let mut state = state.lock().unwrap();
+
+            let success = Success {
+                tx_hash: Some(tx_hash),
+                ..f(&mut state)?
+            };
+
+            state.transactions.insert(tx_hash, success.to_owned());
+
+            match state.lose_responses {
+                true => Err(ServerError::Transaction(format!(
+                    "Transaction {} timed out",
+                    &tx_hash
+                ))),
+
+                false => Ok(success),
+            }
+        }
+        .boxed()
+    }
+}
+
+fn not_found(msg: String) -> ServerError {
+    ServerError::Query(msg)
+}
+
+impl Chain for MemoryChain {
+    fn get_pools(&self, _owner: Option<AccountId>) -> ChainResult<HashMap<PoolId, Pool>> {
+        self.call(|state| Ok(state.pools.to_owned()))
+    }
+
+    fn create_pool(
+        &self,
+        pool_id: PoolId,
+        _pool_results: HashSet<AccountId>,
+        _required_xp: u128,
+    ) -> ChainResult<Pool> {
+        self.call(move |state| {
+            state.calls.push(ChainCall::CreatePool(pool_id.to_owned()));
+
+            match state.pools.get(&pool_id) {
+                Some(pool) => {
+                    state.active.insert(pool_id.to_owned(), true);
+
+                    Ok(pool.to_owned())
+                }
+
+                None => Err(not_found(format!("Pool {} was not seeded", &pool_id))),
+            }
+        })
+    }
+
+    fn register_stake(&self, stake_id: StakeId, staker_id: AccountId) -> ChainResult<Success> {
+        self.call(move |state| {
+            let key = stake_id.to_string();
+
+            state.calls.push(ChainCall::RegisterStake(
+                key.to_owned(),
+                staker_id.to_owned(),
+            ));
+
+            state.stakes.insert(key, (stake_id, staker_id, None));
+
+            Ok(Success::new())
+        })
+    }
+
+    fn unregister_stake(
+        &self,
+        stake_id: StakeId,
+        staker_id: AccountId,
+        reregister: Option<AccountId>,
+    ) -> ChainResult<Success> {
+        self.call(move |state| {
+            let key = stake_id.to_string();
+
+            state.calls.push(ChainCall::UnregisterStake(
+                key.to_owned(),
+                staker_id.to_owned(),
+            ));
+
+            match state.stakes.get(&key) {
+                Some((_, owner, _)) if owner == &staker_id => {}
+
+                _ => {
+                    return Err(not_found(format!(
+                        "{} does not own stake {}",
+                        &staker_id, &key
+                    )))
+                }
+            }
+
+            match reregister {
+                Some(receiver) => {
+                    if let Some(stake) = state.stakes.get_mut(&key) {
+                        stake.1 = receiver;
+                        stake.2 = None;
+                    }
+                }
+
+                None => {
+                    state.stakes.remove(&key);
+                }
+            }
+
+            Ok(Success::new())
+        })
+    }
+
+    fn transfer_stake(
+        &self,
+        stake_id: StakeId,
+        receiver_id: AccountId,
+        _amount: Option<u128>,
+    ) -> ChainResult<Success> {
+        self.call(move |state| {
+            let key = stake_id.to_string();
+
+            state.calls.push(ChainCall::TransferStake(
+                key.to_owned(),
+                receiver_id.to_owned(),
+            ));
+
+            match state.stakes.get_mut(&key) {
+                Some((_, owner, _)) => {
+                    *owner = receiver_id;
+
+                    Ok(Success::new())
+                }
+
+                None => Err(not_found(format!("Stake {} does not exist", &key))),
+            }
+        })
+    }
+
+    fn get_stakes(&self, staker_id: AccountId) -> ChainResult<Vec<(StakeId, Option<PoolId>)>> {
+        self.call(move |state| {
+            Ok(state
+                .stakes
+                .values()
+                .filter(|(_, owner, _)| owner == &staker_id)
+                .map(|(stake_id, _, pool_id)| (stake_id.to_owned(), pool_id.to_owned()))
+                .collect())
+        })
+    }
+
+    fn verify_stake(&self, stake_id: StakeId, check_id: Option<AccountId>) -> ChainResult<String> {
+        self.call(move |state| {
+            let key = stake_id.to_string();
+
+            match (state.stakes.get(&key), check_id) {
+                (Some((_, owner, _)), Some(check_id)) if owner != &check_id => Err(not_found(
+                    format!("{} does not own stake {}", &check_id, &key),
+                )),
+
+                (Some((_, owner, _)), _) => Ok(owner.to_string()),
+
+                (None, _) => Err(not_found(format!("Stake {} does not exist", &key))),
+            }
+        })
+    }
+
+    fn toggle_pool_active(
+        &self,
+        pool_id: PoolId,
+        toggle: bool,
+        on_signed: OnSigned,
+    ) -> ChainResult<Success> {
+        self.transact(on_signed, move |state| {
+            state
+                .calls
+                .push(ChainCall::TogglePoolActive(pool_id.to_owned(), toggle));
+
+            state.active.insert(pool_id, toggle);
+
+            Ok(Success::new())
+        })
+    }
+
+    fn assert_pool_result(
+        &self,
+        pool_id: PoolId,
+        pool_result: Option<AccountId>,
+    ) -> ChainResult<Success> {
+        self.call(move |state| {
+            state.calls.push(ChainCall::AssertPoolResult(
+                pool_id.to_owned(),
+                pool_result.to_owned(),
+            ));
+
+            if !state.pools.contains_key(&pool_id) {
+                return Err(not_found(format!("Pool {} does not exist", &pool_id)));
+            }
+
+            match state.results.get(&pool_id) {
+                Some(_) => Err(ServerError::Transaction(format!(
+                    "Pool {} has already been resolved",
+                    &pool_id
+                ))),
+
+                None => {
+                    state.results.insert(pool_id.to_owned(), pool_result);
+
+                    state.active.insert(pool_id, false);
+
+                    Ok(Success::new())
+                }
+            }
+        })
+    }
+
+    //distributed pools are removed, as the contract does
+    fn distribute_stakes(&self, pool_id: PoolId, on_signed: OnSigned) -> ChainResult<Success> {
+        self.transact(on_signed, move |state| {
+            state
+                .calls
+                .push(ChainCall::DistributeStakes(pool_id.to_owned()));
+
+            match state.results.contains_key(&pool_id) {
+                true => {
+                    state.pools.remove(&pool_id);
+
+                    state
+                        .stakes
+                        .retain(|_, (_, _, staked)| staked.as_ref() != Some(&pool_id));
+
+                    Ok(Success::new())
+                }
+
+                false => Err(ServerError::Transaction(format!(
+                    "Pool {} has no result",
+                    &pool_id
+                ))),
+            }
+        })
+    }
+
+    fn get_character(&self, account_id: AccountId) -> ChainResult<Character> {
+        self.call(move |state| match state.characters.get(&account_id) {
+            Some(_) if state.dead.contains(&account_id) => {
+                Err(not_found(format!("{} has no character", &account_id)))
+            }
+
+            Some(character) => Ok(character.to_owned()),
+
+            None => Err(not_found(format!("{} has no character", &account_id))),
+        })
+    }
+
+    fn get_ft_balance(&self, account_id: AccountId) -> ChainResult<Balance> {
+        self.call(move |state| Ok(state.balances.get(&account_id).copied().unwrap_or(0)))
+    }
+
+    fn set_default_attributes(&self, attributes: Value) -> ChainResult<Success> {
+        self.call(move |state| {
+            state
+                .calls
+                .push(ChainCall::SetDefaultAttributes(attributes));
+
+            Ok(Success::new())
+        })
+    }
+
+    fn give_xp(
+        &self,
+        account_id: AccountId,
+        xp: u128,
+        on_signed: OnSigned,
+    ) -> ChainResult<Success> {
+        self.transact(on_signed, move |state| {
+            state
+                .calls
+                .push(ChainCall::GiveXp(account_id.to_owned(), xp));
+
+            *state.balances.entry(account_id).or_insert(0) += xp;
+
+            Ok(Success::new())
+        })
+    }
+
+    fn kill_character(&self, account_id: AccountId, on_signed: OnSigned) -> ChainResult<Success> {
+        self.transact(on_signed, move |state| {
+            state
+                .calls
+                .push(ChainCall::KillCharacter(account_id.to_owned()));
+
+            match state.dead.insert(account_id.to_owned()) {
+                true => Ok(Success::new()),
+
+                false => Err(ServerError::Transaction(format!(
+                    "{} is already dead",
+                    &account_id
+                ))),
+            }
+        })
+    }
+
+    //nothing is applied unless every outcome can be
+    fn settle_players(
+        &self,
+        outcomes: Vec<PlayerOutcome>,
+        on_signed: OnSigned,
+    ) -> ChainResult<Success> {
+        self.transact(on_signed, move |state| {
+            state
+                .calls
+                .push(ChainCall::SettlePlayers(outcomes.to_owned()));
+
+            for PlayerOutcome { account_id, xp } in outcomes.iter() {
+                if xp.is_none() && state.dead.contains(account_id) {
+                    return Err(ServerError::Transaction(format!(
+                        "{} is already dead",
+                        account_id
+                    )));
+                }
+            }
+
+            for PlayerOutcome { account_id, xp } in outcomes {
+                match xp {
+                    Some(xp) => *state.balances.entry(account_id).or_insert(0) += xp,
+
+                    None => {
+                        state.dead.insert(account_id);
+                    }
+                }
+            }
+
+            Ok(Success::new())
+        })
+    }
+
+    fn tx_status(&self, signer_id: AccountId, tx_hash: CryptoHash) -> ChainResult<Option<Success>> {
+        self.call(move |state| match signer_id == Self::signer_id() {
+            true => Ok(state.transactions.get(&tx_hash).cloned()),
+
+            false => Err(not_found(format!(
+                "{} did not sign {}",
+                &signer_id, &tx_hash
+            ))),
+        })
+    }
+
+    fn verify_access_key(&self, account_id: AccountId, public_key: PublicKey) -> ChainResult<()> {
+        self.call(move |state| {
+            match state
+                .access_keys
+                .get(&account_id)
+                .map(|keys| keys.contains(&public_key))
+            {
+                Some(true) => Ok(()),
+
+                _ => Err(not_found(format!(
+                    "{} is not a full access key of {}",
+                    &public_key, &account_id
+                ))),
+            }
+        })
+    }
+}
@@ -0,0 +1,229 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use chrono::Local;
+use futures::{
+    future::{ready, FutureExt},
+    lock::{Mutex as AsyncMutex, MutexGuard},
+};
+use near_primitives::{hash::CryptoHash, types::AccountId};
+use uuid::Uuid;
+
+use crate::{
+    db::models::OutboxEntry,
+    types::{ChainOperation, Content},
+};
+
+use super::{
+    contract_methods::Success,
+    messages::ServerError,
+    outbox::{retry, set_store, DbStore, OutboxStore, StoreResult, DONE, PENDING, RUNNING},
+};
+
+lazy_static::lazy_static! {
+    static ref INSTALLED: AsyncMutex<()> = AsyncMutex::new(());
+}
+
+//the store is global, tests hold the guard for as long as their outbox is installed
+pub async fn install(outbox: &MemoryOutbox) -> MutexGuard<'static, ()> {
+    let guard = INSTALLED.lock().await;
+
+    set_store(Arc::new(outbox.to_owned()));
+
+    guard
+}
+
+//tests of the database store hold the same guard, so no MemoryOutbox replaces it meanwhile
+pub async fn install_database() -> MutexGuard<'static, ()> {
+    let guard = INSTALLED.lock().await;
+
+    set_store(Arc::new(DbStore));
+
+    guard
+}
+
+#[derive(Default)]
+pub struct OutboxState {
+    pub entries: Vec<OutboxEntry>,
+    //what each completed entry recorded alongside it
+    pub records: HashMap<Uuid, Option<Content>>,
+}
+
+//keeps the outbox in memory with the same key and retry rules as chain_outbox
+#[derive(Default, Clone)]
+pub struct MemoryOutbox {
+    pub state: Arc<Mutex<OutboxState>>,
+}
+
+impl MemoryOutbox {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn entries(&self) -> Vec<OutboxEntry> {
+        self.state.lock().unwrap().entries.to_owned()
+    }
+
+    pub fn entry(&self, key: &str) -> Option<OutboxEntry> {
+        self.state
+            .lock()
+            .unwrap()
+            .entries
+            .iter()
+            .find(|entry| entry.idempotency_key == key)
+            .cloned()
+    }
+
+    fn call<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut OutboxState) -> Result<T, ServerError>,
+    ) -> StoreResult<T> {
+        let res = f(&mut self.state.lock().unwrap());
+
+        ready(res).boxed()
+    }
+
+    fn update(&self, id: Uuid, f: impl FnOnce(&mut OutboxEntry)) -> StoreResult<()> {
+        self.call(move |state| {
+            if let Some(entry) = state.entries.iter_mut().find(|entry| entry.id == id) {
+                f(entry);
+            }
+
+            Ok(())
+        })
+    }
+}
+
+//a second entry with the same key is dropped, as the unique index does
+fn push(state: &mut OutboxState, operation: ChainOperation) {
+    let idempotency_key = operation.idempotency_key();
+
+    if state
+        .entries
+        .iter()
+        .any(|entry| entry.idempotency_key == idempotency_key)
+    {
+        return;
+    }
+
+    let now = Local::now().naive_local();
+
+    state.entries.push(OutboxEntry {
+        id: Uuid::new_v4(),
+        idempotency_key,
+        session_id: operation.session_id(),
+        operation,
+        status: PENDING.to_string(),
+        attempts: 0,
+        last_error: None,
+        next_attempt_at: now,
+        created_at: now,
+        completed_at: None,
+        tx_signer: None,
+        tx_hash: None,
+    });
+}
+
+impl OutboxStore for MemoryOutbox {
+    fn enqueue(&self, operations: Vec<ChainOperation>) -> StoreResult<()> {
+        self.call(move |state| {
+            for operation in operations {
+                push(state, operation);
+            }
+
+            Ok(())
+        })
+    }
+
+    fn requeue(&self) -> StoreResult<usize> {
+        self.call(|state| {
+            let mut requeued = 0;
+
+            for entry in state.entries.iter_mut() {
+                if entry.status == RUNNING {
+                    entry.status = PENDING.to_string();
+
+                    requeued += 1;
+                }
+            }
+
+            Ok(requeued)
+        })
+    }
+
+    fn claim(&self) -> StoreResult<Vec<OutboxEntry>> {
+        self.call(|state| {
+            let now = Local::now().naive_local();
+
+            Ok(state
+                .entries
+                .iter_mut()
+                .filter(|entry| entry.status == PENDING && entry.next_attempt_at <= now)
+                .map(|entry| {
+                    entry.status = RUNNING.to_string();
+
+                    entry.to_owned()
+                })
+                .collect())
+        })
+    }
+
+    fn record_transaction(
+        &self,
+        id: Uuid,
+        signer_id: AccountId,
+        tx_hash: CryptoHash,
+    ) -> StoreResult<()> {
+        self.update(id, move |entry| {
+            entry.tx_signer = Some(signer_id.to_string());
+            entry.tx_hash = Some(tx_hash.to_string());
+        })
+    }
+
+    //only the outbox side of completing, rewards and resolutions live in the database
+    fn complete(
+        &self,
+        entry: OutboxEntry,
+        _success: Success,
+        record: Option<Content>,
+    ) -> StoreResult<()> {
+        self.call(move |state| {
+            if let Some(done) = state.entries.iter_mut().find(|e| e.id == entry.id) {
+                done.status = DONE.to_string();
+                done.completed_at = Some(Local::now().naive_local());
+            }
+
+            state.records.insert(entry.id, record);
+
+            if let ChainOperation::AssertPoolResult {
+                session_id,
+                pool_id,
+                ..
+            } = entry.operation
+            {
+                push(
+                    state,
+                    ChainOperation::DistributeStakes {
+                        session_id,
+                        pool_id,
+                    },
+                );
+            }
+
+            Ok(())
+        })
+    }
+
+    fn fail(&self, entry: OutboxEntry, error: String) -> StoreResult<()> {
+        let (tries, next_status, next_attempt) = retry(&entry);
+
+        self.update(entry.id, move |entry| {
+            entry.status = next_status.to_string();
+            entry.attempts = tries;
+            entry.last_error = Some(error);
+            entry.next_attempt_at = next_attempt;
+        })
+    }
+}
@@ -1,12 +1,14 @@
 use crate::types::{
-    Content, Entities, EntityId, PlayerInfo, PlayerStats, SessionState, SessionStatus, Spawn,
+    Content, Entities, EntityId, Lvl, PlayerInfo, PlayerOutcome, PlayerStats, ResultStrategy,
+    RewardEntry, SessionInfo, SessionOutcome, SessionState, SessionStatus, Spawn, StateDelta,
     UserId,
 };
 use actix::prelude::*;
-use chrono::NaiveDateTime;
+use actix_web::{http::StatusCode, HttpRequest, HttpResponse, ResponseError};
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
 use near_primitives::types::AccountId;
 use serde::{Deserialize, Serialize, Serializer};
-use serde_json::to_string;
+use serde_json::{to_string, Value};
 use std::{
     collections::{HashMap, HashSet},
     fmt, io,
@@ -14,7 +16,7 @@ use std::{
 };
 use uuid::Uuid;
 
-use super::ClientStatus;
+use super::{client::ClientActor, ClientStatus};
 
 #[derive(Message, Serialize, Deserialize, Debug, PartialEq, Clone)]
 #[serde(tag = "msg_type", content = "content")]
@@ -30,8 +32,84 @@ pub enum ClientMessage {
     },
     Join {
         session_id: Uuid,
+        #[serde(default)]
+        password: Option<String>,
     },
     Leave,
+    //the seq of the last Tick or Delta applied, not its tick timestamp
+    Ack {
+        seq: u64,
+    },
+    Resync,
+    SubscribeLobby,
+    UnsubscribeLobby,
+    Whitelist {
+        #[serde(default = "Vec::new")]
+        add: Vec<UserId>,
+        #[serde(default = "Vec::new")]
+        remove: Vec<UserId>,
+    },
+    Kick {
+        session_id: Uuid,
+        user_id: UserId,
+    },
+    EndSession {
+        session_id: Uuid,
+    },
+    ResolvePool {
+        session_id: Uuid,
+    },
+    //follows a running session without playing in it, no player session needed
+    Spectate {
+        session_id: Uuid,
+        #[serde(default)]
+        password: Option<String>,
+    },
+    //plays a recorded session back over the same messages a live one sends
+    WatchReplay {
+        session_id: Uuid,
+        #[serde(default)]
+        speed: Option<f32>,
+    },
+    //milliseconds from the start of the recording
+    SeekReplay {
+        position: u64,
+    },
+    SetReplaySpeed {
+        speed: f32,
+    },
+    StopReplay,
+    //wallet queries default to the caller's most recently active account
+    GetCharacter {
+        request_id: String,
+        #[serde(default)]
+        account_id: Option<AccountId>,
+    },
+    GetBalance {
+        request_id: String,
+        #[serde(default)]
+        account_id: Option<AccountId>,
+    },
+    GetStakes {
+        request_id: String,
+        #[serde(default)]
+        account_id: Option<AccountId>,
+    },
+    GetLvl {
+        request_id: String,
+        #[serde(default)]
+        account_id: Option<AccountId>,
+    },
+    //unlike wallet queries, covers every linked account unless one is given
+    GetRewards {
+        request_id: String,
+        #[serde(default)]
+        account_id: Option<AccountId>,
+        #[serde(default)]
+        offset: usize,
+        #[serde(default)]
+        limit: Option<usize>,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
@@ -64,10 +142,19 @@ pub enum ServerMessage {
     Update(Update),
     Tick {
         tick: u128,
+        seq: u64,
         state: SessionState,
         players: HashMap<UserId, PlayerInfo>,
         status: SessionStatus,
     },
+    Delta {
+        tick: u128,
+        seq: u64,
+        base: u64,
+        delta: StateDelta,
+        players: HashMap<UserId, PlayerInfo>,
+        status: SessionStatus,
+    },
     Message {
         sender: UserId,
         msg: String,
@@ -84,12 +171,107 @@ pub enum ServerMessage {
     Disconnected,
     Connected,
     Notification(Content),
+    Lobby(LobbyUpdate),
+    Response {
+        request_id: String,
+        response: WalletResponse,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(tag = "lobby_type", content = "lobby")]
+#[serde(rename_all = "snake_case")]
+pub enum LobbyUpdate {
+    Sessions(Vec<SessionInfo>),
+    Opened(SessionInfo),
+    Players {
+        session_id: Uuid,
+        players: i64,
+        full: bool,
+    },
+    Started(Uuid),
+    Ended(Uuid),
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(tag = "response_type", content = "response")]
+#[serde(rename_all = "snake_case")]
+pub enum WalletResponse {
+    Character(Value),
+    //ft amounts overflow json numbers
+    Balance(String),
+    Stakes(Value),
+    Lvl(Lvl),
+    Rewards(Vec<RewardEntry>),
+    Error { kind: String, message: String },
 }
 
 impl ServerMessage {
     pub fn to_message(&self) -> String {
         to_string(self).unwrap()
     }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        rmp_serde::to_vec_named(self).unwrap()
+    }
+}
+
+//wire format for a websocket connection, json unless the client asks for msgpack
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Json,
+    MsgPack,
+}
+
+impl Encoding {
+    pub const PROTOCOLS: [&'static str; 2] = ["msgpack", "json"];
+
+    pub fn negotiate(req: &HttpRequest) -> Self {
+        let query = req.query_string().split('&').find_map(|pair| {
+            match pair.split_once('=') {
+                Some(("encoding", value)) => Some(value.to_owned()),
+                _ => None,
+            }
+        });
+
+        //the handshake echoes the offered protocol back, so it decides over the query
+        match (Self::offered(req), query) {
+            (Some(protocol), _) if protocol == "msgpack" => Self::MsgPack,
+
+            (Some(_), _) => Self::Json,
+
+            (None, Some(encoding)) if encoding == "msgpack" => Self::MsgPack,
+
+            (None, _) => Self::Json,
+        }
+    }
+
+    //the encoding protocol the handshake echoes back, if the client offered one
+    pub fn offered(req: &HttpRequest) -> Option<String> {
+        req.headers()
+            .get("Sec-WebSocket-Protocol")
+            .and_then(|header| header.to_str().ok())
+            .and_then(|header| {
+                header
+                    .split(',')
+                    .map(|p| p.trim())
+                    .find(|p| Self::PROTOCOLS.iter().any(|protocol| protocol == p))
+                    .map(|p| p.to_owned())
+            })
+    }
+
+    //browsers fail the handshake unless one of the offered protocols is echoed back,
+    //and a ticket protocol never is
+    pub fn check_offered(req: &HttpRequest) -> Result<(), String> {
+        match req.headers().get("Sec-WebSocket-Protocol") {
+            Some(_) if Self::offered(req).is_none() => Err(format!(
+                "Sec-WebSocket-Protocol must offer {} alongside the ticket",
+                Self::PROTOCOLS.join(" or ")
+            )),
+
+            _ => Ok(()),
+        }
+    }
 }
 
 #[derive(Message, Debug)]
@@ -97,9 +279,11 @@ impl ServerMessage {
 pub enum ServerError {
     Std(io::Error),
     Serde(serde_json::Error),
+    Decode(rmp_serde::decode::Error),
     Database(diesel::result::Error),
     Transaction(String),
     Query(String),
+    Rejected { entity_id: EntityId, reason: String },
 }
 
 impl ServerError {
@@ -107,14 +291,39 @@ impl ServerError {
         Self::Std(io::Error::new(kind, msg))
     }
 
-    pub fn to_message(&self) -> String {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Std(_) => "std",
+            Self::Serde(_) | Self::Decode(_) => "parse",
+            Self::Database(_) => "database",
+            Self::Transaction(_) => "transaction",
+            Self::Query(_) => "query",
+            Self::Rejected { .. } => "rejected",
+        }
+    }
+
+    fn body(&self) -> Content {
         let mut body = Content::new();
 
         body.insert("msg_type", "error");
 
+        body.insert("kind", self.kind());
+
         body.insert("content", &self);
 
-        to_string(&body).unwrap()
+        if let Self::Rejected { entity_id, .. } = self {
+            body.insert("entity_id", entity_id);
+        }
+
+        body
+    }
+
+    pub fn to_message(&self) -> String {
+        to_string(&self.body()).unwrap()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        rmp_serde::to_vec_named(&self.body()).unwrap()
     }
 }
 
@@ -129,14 +338,63 @@ impl Serialize for ServerError {
 
 impl std::error::Error for ServerError {}
 
+impl From<DieselError> for ServerError {
+    fn from(e: DieselError) -> Self {
+        Self::Database(e)
+    }
+}
+
+impl ResponseError for ServerError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::Std(e) => match e.kind() {
+                io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+                io::ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+                io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => {
+                    StatusCode::BAD_REQUEST
+                }
+                io::ErrorKind::AlreadyExists | io::ErrorKind::AddrInUse => StatusCode::CONFLICT,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            },
+
+            Self::Serde(_) | Self::Decode(_) | Self::Rejected { .. } => StatusCode::BAD_REQUEST,
+
+            Self::Database(DieselError::NotFound) => StatusCode::NOT_FOUND,
+
+            Self::Database(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+                StatusCode::CONFLICT
+            }
+
+            Self::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+
+            Self::Transaction(_) | Self::Query(_) => StatusCode::BAD_GATEWAY,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code())
+            .content_type("application/json")
+            .body(self.to_message())
+    }
+}
+
 impl fmt::Display for ServerError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", to_string(self).unwrap())
+        match self {
+            Self::Std(e) => write!(f, "{}", e),
+            Self::Serde(e) => write!(f, "{}", e),
+            Self::Decode(e) => write!(f, "{}", e),
+            Self::Database(e) => write!(f, "{}", e),
+            Self::Transaction(msg) | Self::Query(msg) => write!(f, "{}", msg),
+            Self::Rejected { entity_id, reason } => {
+                write!(f, "update to {} rejected: {}", entity_id.0, reason)
+            }
+        }
     }
 }
 
 #[derive(Message, Clone)]
-#[rtype(result = "(SessionState, HashMap<UserId, PlayerInfo>)")]
+#[rtype(result = "Result<(SessionState, HashMap<UserId, PlayerInfo>), ServerError>")]
 pub struct Join {
     pub user_id: UserId,
     pub account_id: Option<AccountId>,
@@ -147,12 +405,58 @@ pub struct Join {
 #[rtype(result = "Option<(Uuid, PlayerInfo)>")]
 pub struct Leave(pub UserId);
 
+#[derive(Message)]
+#[rtype(result = "Result<(), ServerError>")]
+pub struct Spectate {
+    pub user_id: UserId,
+    pub actor: Addr<ClientActor>,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct StopSpectating(pub UserId);
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct TickAck {
+    pub user_id: UserId,
+    pub seq: u64,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Resync(pub UserId);
+
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct SessionUpdate {
     pub updater: UserId,
     pub update: Update,
 }
+#[derive(Message)]
+#[rtype(result = "Result<(), ServerError>")]
+pub struct KickPlayer {
+    pub user_id: UserId,
+    pub by: UserId,
+    //kicks by moderators skip the host check
+    pub moderator: bool,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Kicked {
+    pub session_id: Uuid,
+    pub by: UserId,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct WhitelistUpdate {
+    pub updater: UserId,
+    pub add: Vec<UserId>,
+    pub remove: Vec<UserId>,
+}
+
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct SessionMessage {
@@ -160,6 +464,21 @@ pub struct SessionMessage {
     pub exclude: Vec<UserId>,
 }
 
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct LobbySubscribe {
+    pub user_id: UserId,
+    pub actor: Addr<ClientActor>,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct LobbyUnsubscribe(pub UserId);
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct LobbyEvent(pub LobbyUpdate);
+
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct SessionEnd;
@@ -176,10 +495,56 @@ pub struct PlayerSessionResolve {
     pub xp: Option<u128>,
 }
 
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SessionSettle {
+    pub session_id: Uuid,
+    pub outcomes: Vec<PlayerOutcome>,
+}
+
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct SessionResolve {
     pub session_id: Uuid,
-    pub results: Vec<(AccountId, NaiveDateTime)>,
     pub pool_id: String,
+    pub strategy: ResultStrategy,
+    pub outcome: SessionOutcome,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ReplaySeek(pub Duration);
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ReplaySpeed(pub f32);
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ReplayStop;
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::Encoding;
+
+    #[test]
+    fn the_echoed_protocol_decides_the_encoding() {
+        let req = TestRequest::with_uri("/ws?encoding=json")
+            .insert_header(("Sec-WebSocket-Protocol", "ticket.abc, msgpack"))
+            .to_http_request();
+
+        assert_eq!(Encoding::negotiate(&req), Encoding::MsgPack);
+
+        let req = TestRequest::with_uri("/ws?encoding=msgpack")
+            .insert_header(("Sec-WebSocket-Protocol", "json"))
+            .to_http_request();
+
+        assert_eq!(Encoding::negotiate(&req), Encoding::Json);
+
+        let req = TestRequest::with_uri("/ws?encoding=msgpack").to_http_request();
+
+        assert_eq!(Encoding::negotiate(&req), Encoding::MsgPack);
+    }
 }
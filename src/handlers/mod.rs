@@ -11,15 +11,28 @@ use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::{
-    handlers::{client::ClientActor, global::GlobalActor, session::SessionActor},
+    handlers::{
+        client::ClientActor, global::GlobalActor, outbox::OutboxActor, session::SessionActor,
+    },
     types::UserId,
 };
 
+pub mod chain;
 pub mod client;
 pub mod contract_methods;
 pub mod global;
+#[cfg(test)]
+pub mod memory_chain;
+#[cfg(test)]
+pub mod memory_outbox;
 pub mod messages;
+pub mod outbox;
+pub mod replay;
 pub mod session;
+pub mod signer;
+pub mod strategy;
+pub mod validation;
+pub mod wallet;
 
 lazy_static::lazy_static! {
     pub static ref CLIENTS: Mutex<HashMap<UserId, Addr<ClientActor>>> = Mutex::new(HashMap::new());
@@ -27,6 +40,8 @@ lazy_static::lazy_static! {
     pub static ref SESSIONS: Mutex<HashMap<Uuid, Addr<SessionActor>>> = Mutex::new(HashMap::new());
 
     pub static ref GLOBAL: Addr<GlobalActor> = GlobalActor::default().start();
+
+    pub static ref OUTBOX: Addr<OutboxActor> = OutboxActor::default().start();
 }
 
 pub struct ClientInfo {
@@ -36,6 +51,16 @@ pub struct ClientInfo {
     pub actor: Addr<ClientActor>,
     pub account_id: Option<AccountId>,
     pub status: ClientStatus,
+    pub team: Option<i32>,
+    pub acked_seq: Option<u64>,
+    pub last_keyframe: Option<u64>,
+}
+
+//spectators are kept apart from clients so they never count as players
+pub struct SpectatorInfo {
+    pub actor: Addr<ClientActor>,
+    //deltas are only sent once a full tick has been
+    pub synced: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
@@ -57,6 +82,9 @@ impl ClientInfo {
             actor,
             account_id,
             status: ClientStatus::Loading(Local::now().naive_local()),
+            team: None,
+            acked_seq: None,
+            last_keyframe: None,
         }
     }
 }
@@ -0,0 +1,874 @@
+use std::{
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use actix::{Actor, ActorFutureExt, AsyncContext, Context, Handler, Message, WrapFuture};
+use chrono::{Local, NaiveDateTime};
+use diesel::{insert_into, prelude::*, update};
+use futures::{future::BoxFuture, FutureExt};
+use near_primitives::{hash::CryptoHash, types::AccountId};
+use serde_json::from_value;
+use uuid::Uuid;
+
+use crate::{
+    db::{
+        actor::{
+            run, ClaimOperations, CompleteOperation, Enqueue, FailOperation, RecordTransaction,
+            RequeueOperations,
+        },
+        models::{NewOutboxEntry, OutboxEntry},
+        record_reward, schema,
+    },
+    types::{ChainOperation, Content, Reward, RewardKind},
+};
+
+use super::{
+    chain::chain,
+    contract_methods::Success,
+    messages::ServerError,
+    signer::OnSigned,
+    strategy::{pool_result, record, stakers, Strategy},
+    OUTBOX,
+};
+
+pub const PENDING: &str = "pending";
+pub const RUNNING: &str = "running";
+pub const DONE: &str = "done";
+//gave up after MAX_ATTEMPTS, only replayed by an operator
+pub const DEAD: &str = "dead";
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const BATCH_SIZE: i64 = 20;
+const MAX_ATTEMPTS: i32 = 8;
+const BASE_BACKOFF: i64 = 5;
+const MAX_BACKOFF: i64 = 60 * 60;
+
+lazy_static::lazy_static! {
+    static ref STORE: RwLock<Arc<dyn OutboxStore>> = RwLock::new(Arc::new(DbStore));
+}
+
+//where entries are kept, the database unless a test installs a MemoryOutbox
+pub fn store() -> Arc<dyn OutboxStore> {
+    STORE.read().unwrap().to_owned()
+}
+
+#[cfg(test)]
+pub fn set_store(store: Arc<dyn OutboxStore>) {
+    *STORE.write().unwrap() = store;
+}
+
+pub type StoreResult<T> = BoxFuture<'static, Result<T, ServerError>>;
+
+pub trait OutboxStore: Send + Sync {
+    //enqueued together or not at all
+    fn enqueue(&self, operations: Vec<ChainOperation>) -> StoreResult<()>;
+
+    fn requeue(&self) -> StoreResult<usize>;
+
+    fn claim(&self) -> StoreResult<Vec<OutboxEntry>>;
+
+    fn record_transaction(
+        &self,
+        id: Uuid,
+        signer_id: AccountId,
+        tx_hash: CryptoHash,
+    ) -> StoreResult<()>;
+
+    fn complete(
+        &self,
+        entry: OutboxEntry,
+        success: Success,
+        record: Option<Content>,
+    ) -> StoreResult<()>;
+
+    fn fail(&self, entry: OutboxEntry, error: String) -> StoreResult<()>;
+}
+
+//the chain_outbox table, reached through the database threads
+pub struct DbStore;
+
+impl OutboxStore for DbStore {
+    fn enqueue(&self, operations: Vec<ChainOperation>) -> StoreResult<()> {
+        run(Enqueue(operations)).boxed()
+    }
+
+    fn requeue(&self) -> StoreResult<usize> {
+        run(RequeueOperations).boxed()
+    }
+
+    fn claim(&self) -> StoreResult<Vec<OutboxEntry>> {
+        run(ClaimOperations).boxed()
+    }
+
+    fn record_transaction(
+        &self,
+        id: Uuid,
+        signer_id: AccountId,
+        tx_hash: CryptoHash,
+    ) -> StoreResult<()> {
+        run(RecordTransaction {
+            id,
+            signer_id,
+            tx_hash,
+        })
+        .boxed()
+    }
+
+    fn complete(
+        &self,
+        entry: OutboxEntry,
+        success: Success,
+        record: Option<Content>,
+    ) -> StoreResult<()> {
+        run(CompleteOperation {
+            entry,
+            success,
+            record,
+        })
+        .boxed()
+    }
+
+    fn fail(&self, entry: OutboxEntry, error: String) -> StoreResult<()> {
+        run(FailOperation { entry, error }).boxed()
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ProcessOutbox;
+
+#[derive(Default)]
+pub struct OutboxActor;
+
+impl Actor for OutboxActor {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.spawn(
+            store()
+                .requeue()
+                .into_actor(self)
+                .map(|res, _act, _ctx| match res {
+                    Ok(0) => {}
+
+                    Ok(n) => println!("[Server] Requeued {} interrupted chain operations", n),
+
+                    Err(e) => println!("[Server] DB Error: {}", e.to_string()),
+                }),
+        );
+
+        ctx.run_interval(POLL_INTERVAL, |_act, ctx| ctx.notify(ProcessOutbox));
+    }
+}
+
+//entries left running by a previous process never reported back, attempts reconcile them before resending
+pub fn requeue(conn: &mut PgConnection) -> Result<usize, ServerError> {
+    use schema::chain_outbox::dsl::{chain_outbox, status};
+
+    update(chain_outbox)
+        .filter(status.eq(RUNNING))
+        .set(status.eq(PENDING))
+        .execute(conn)
+        .map_err(ServerError::Database)
+}
+
+//due entries, marked running so they are not picked up twice
+pub fn claim(conn: &mut PgConnection) -> Result<Vec<OutboxEntry>, ServerError> {
+    use schema::chain_outbox::dsl::{chain_outbox, created_at, id, next_attempt_at, status};
+
+    let due = chain_outbox
+        .filter(
+            status
+                .eq(PENDING)
+                .and(next_attempt_at.le(Local::now().naive_local())),
+        )
+        .order(created_at.asc())
+        .limit(BATCH_SIZE)
+        .get_results::<OutboxEntry>(conn)
+        .map_err(ServerError::Database)?;
+
+    let mut claimed = Vec::new();
+
+    for entry in due {
+        //another claim may have taken it in between
+        if let Ok(1) = update(chain_outbox)
+            .filter(id.eq(&entry.id).and(status.eq(PENDING)))
+            .set(status.eq(RUNNING))
+            .execute(conn)
+        {
+            claimed.push(entry);
+        }
+    }
+
+    Ok(claimed)
+}
+
+//retrying the same effect is a no-op while an entry with its key exists
+pub fn enqueue(operation: ChainOperation, conn: &mut PgConnection) -> Result<(), ServerError> {
+    use schema::chain_outbox::dsl::chain_outbox;
+
+    match insert_into(chain_outbox)
+        .values(&NewOutboxEntry {
+            idempotency_key: operation.idempotency_key(),
+            session_id: operation.session_id(),
+            operation,
+        })
+        .on_conflict_do_nothing()
+        .execute(conn)
+    {
+        Ok(_) => {
+            OUTBOX.do_send(ProcessOutbox);
+
+            Ok(())
+        }
+
+        Err(e) => Err(ServerError::Database(e)),
+    }
+}
+
+fn backoff(attempts: i32) -> chrono::Duration {
+    let secs = BASE_BACKOFF.saturating_mul(1 << attempts.clamp(0, 20));
+
+    chrono::Duration::seconds(secs.min(MAX_BACKOFF))
+}
+
+impl Handler<ProcessOutbox> for OutboxActor {
+    type Result = ();
+
+    fn handle(&mut self, _: ProcessOutbox, ctx: &mut Self::Context) {
+        ctx.spawn(store().claim().into_actor(self).map(|res, act, ctx| {
+            let due = match res {
+                Ok(due) => due,
+
+                Err(e) => return println!("[Server] DB Error: {}", e.to_string()),
+            };
+
+            for entry in due {
+                ctx.spawn(async move { attempt(entry).await }.into_actor(act));
+            }
+        }));
+    }
+}
+
+//stored on the entry before broadcasting, so a later attempt can tell whether it landed
+fn record_signed(entry_id: Uuid) -> OnSigned {
+    Arc::new(move |signer_id, tx_hash| store().record_transaction(entry_id, signer_id, tx_hash))
+}
+
+pub fn record_transaction(
+    entry_id: &Uuid,
+    signer_id: &AccountId,
+    hash: &CryptoHash,
+    conn: &mut PgConnection,
+) -> Result<(), ServerError> {
+    use schema::chain_outbox::dsl::{chain_outbox, id, tx_hash, tx_signer};
+
+    update(chain_outbox)
+        .filter(id.eq(entry_id))
+        .set((
+            tx_signer.eq(signer_id.as_str()),
+            tx_hash.eq(hash.to_string()),
+        ))
+        .execute(conn)
+        .map(|_| ())
+        .map_err(ServerError::Database)
+}
+
+//the transaction an earlier attempt signed, if it landed without that attempt hearing back
+async fn reconcile(entry: &OutboxEntry) -> Result<Option<Success>, ServerError> {
+    match (&entry.tx_signer, &entry.tx_hash) {
+        (Some(signer_id), Some(tx_hash)) => {
+            let signer_id = AccountId::from_str(signer_id)
+                .map_err(|e| ServerError::Query(format!("{}: {}", signer_id, e)))?;
+
+            let tx_hash = CryptoHash::from_str(tx_hash)
+                .map_err(|e| ServerError::Query(format!("{}: {}", tx_hash, e)))?;
+
+            chain().tx_status(signer_id, tx_hash).await
+        }
+
+        _ => Ok(None),
+    }
+}
+
+//an entry is only executed again once its last transaction is known not to have landed
+async fn resolve(
+    entry: &OutboxEntry,
+    on_signed: OnSigned,
+) -> Result<(Success, Option<Content>), ServerError> {
+    match reconcile(entry).await? {
+        Some(success) => Ok((success, None)),
+
+        None => execute(entry.operation.to_owned(), on_signed).await,
+    }
+}
+
+//executes a claimed entry and records how it went
+async fn attempt(entry: OutboxEntry) {
+    let key = entry.idempotency_key.to_owned();
+
+    let res = match resolve(&entry, record_signed(entry.id)).await {
+        Ok((success, record)) => store().complete(entry, success, record).await,
+
+        Err(e) => store().fail(entry, e.to_string()).await,
+    };
+
+    if let Err(e) = res {
+        println!(
+            "[Server] DB Error Completing Chain Operation - {}: {}",
+            &key,
+            e.to_string()
+        )
+    }
+}
+
+//returns what should be recorded alongside the operation, if anything
+async fn execute(
+    operation: ChainOperation,
+    on_signed: OnSigned,
+) -> Result<(Success, Option<Content>), ServerError> {
+    match operation {
+        ChainOperation::GiveXp { account_id, xp, .. } => chain()
+            .give_xp(account_id, xp, on_signed)
+            .await
+            .map(|s| (s, None)),
+
+        ChainOperation::KillCharacter { account_id, .. } => chain()
+            .kill_character(account_id, on_signed)
+            .await
+            .map(|s| (s, None)),
+
+        ChainOperation::AssertPoolResult {
+            pool_id,
+            strategy,
+            outcome,
+            ..
+        } => {
+            let ranking = strategy.rank(&outcome).map_err(ServerError::Query)?;
+
+            match chain().get_pools(None).await?.get(&pool_id) {
+                Some(pool) => {
+                    //asserted by an earlier attempt whose response was lost,
+                    //the result on chain is recorded rather than one computed again
+                    let (success, final_result) = match &pool.result {
+                        None => {
+                            let final_result = pool_result(&ranking.accounts, pool)?;
+
+                            let success = chain()
+                                .assert_pool_result(pool_id, final_result.to_owned())
+                                .await?;
+
+                            (success, final_result)
+                        }
+
+                        Some(asserted) => {
+                            let asserted = AccountId::from_str(asserted.as_str())
+                                .map_err(|e| ServerError::Query(format!("{}: {}", asserted, e)))?;
+
+                            (Success::new(), Some(asserted))
+                        }
+                    };
+
+                    Ok((
+                        success,
+                        Some(record(
+                            &strategy,
+                            &ranking,
+                            &final_result,
+                            &stakers(pool, &final_result)?,
+                        )),
+                    ))
+                }
+
+                None => Err(ServerError::Query(format!(
+                    "Pool has been removed {}",
+                    &pool_id
+                ))),
+            }
+        }
+
+        ChainOperation::DistributeStakes { pool_id, .. } => {
+            //a pool that is gone was distributed by an earlier attempt whose response was lost
+            match chain().get_pools(None).await?.contains_key(&pool_id) {
+                true => chain()
+                    .distribute_stakes(pool_id, on_signed)
+                    .await
+                    .map(|s| (s, None)),
+
+                false => Ok((Success::new(), None)),
+            }
+        }
+
+        ChainOperation::SettlePlayers { outcomes, .. } => chain()
+            .settle_players(outcomes, on_signed)
+            .await
+            .map(|s| (s, None)),
+
+        //sets the pool inactive rather than flipping it, sending it again changes nothing
+        ChainOperation::DeactivatePool { pool_id, .. } => chain()
+            .toggle_pool_active(pool_id, false, on_signed)
+            .await
+            .map(|s| (s, None)),
+    }
+}
+
+pub fn complete(
+    entry: &OutboxEntry,
+    success: Success,
+    record: Option<Content>,
+    conn: &mut PgConnection,
+) -> Result<(), ServerError> {
+    let now = Local::now().naive_local();
+
+    let tx_hash = success.tx_hash.map(|hash| hash.to_string());
+
+    conn.transaction::<_, ServerError, _>(|conn| {
+        use schema::chain_outbox::dsl::{chain_outbox, completed_at, id, status};
+
+        update(chain_outbox)
+            .filter(id.eq(&entry.id))
+            .set((status.eq(DONE), completed_at.eq(now)))
+            .execute(conn)
+            .map_err(ServerError::Database)?;
+
+        match &entry.operation {
+            ChainOperation::GiveXp {
+                session_id,
+                account_id,
+                ..
+            }
+            | ChainOperation::KillCharacter {
+                session_id,
+                account_id,
+            } => {
+                use schema::player_sessions::dsl::{
+                    account_id as aid, player_sessions, resolved_at, session_id as sid,
+                };
+
+                update(player_sessions)
+                    .filter(sid.eq(session_id).and(aid.eq(account_id.as_str())))
+                    .set(resolved_at.eq(now))
+                    .execute(conn)
+                    .map_err(ServerError::Database)?;
+
+                let (kind, amount) = match &entry.operation {
+                    ChainOperation::GiveXp { xp, .. } => (RewardKind::Xp, Some(xp.to_owned())),
+
+                    _ => (RewardKind::Death, None),
+                };
+
+                record_reward(
+                    account_id.as_str(),
+                    &Reward {
+                        kind,
+                        session_id: session_id.to_owned(),
+                        tx_hash: tx_hash.to_owned(),
+                        amount,
+                        pool_id: None,
+                    },
+                    conn,
+                )?;
+            }
+
+            ChainOperation::AssertPoolResult {
+                session_id,
+                pool_id,
+                ..
+            } => {
+                use schema::pools::dsl::{id as pid, pools, result};
+
+                update(pools)
+                    .filter(pid.eq(pool_id))
+                    .set(result.eq(record))
+                    .execute(conn)
+                    .map_err(ServerError::Database)?;
+
+                enqueue(
+                    ChainOperation::DistributeStakes {
+                        session_id: session_id.to_owned(),
+                        pool_id: pool_id.to_owned(),
+                    },
+                    conn,
+                )?
+            }
+
+            ChainOperation::DistributeStakes {
+                session_id,
+                pool_id,
+            } => {
+                use schema::pools::dsl::{id as pid, pools, resolved_at, result};
+
+                let resolved = update(pools)
+                    .filter(pid.eq(pool_id))
+                    .set(resolved_at.eq(now))
+                    .returning(result)
+                    .get_result::<Option<Content>>(conn)
+                    .optional()
+                    .map_err(ServerError::Database)?
+                    .flatten();
+
+                //stakes go to those who staked on the asserted result, nothing is distributed without one
+                let stakers = resolved
+                    .as_ref()
+                    .and_then(|record| record.0.get("stakers"))
+                    .map(|stakers| from_value::<Vec<AccountId>>(stakers.to_owned()))
+                    .transpose()
+                    .map_err(ServerError::Serde)?
+                    .unwrap_or_default();
+
+                //the contract decides the amounts, the transaction is where they can be looked up
+                for account_id in stakers {
+                    record_reward(
+                        account_id.as_str(),
+                        &Reward {
+                            kind: RewardKind::Stakes,
+                            session_id: session_id.to_owned(),
+                            tx_hash: tx_hash.to_owned(),
+                            amount: None,
+                            pool_id: Some(pool_id.to_owned()),
+                        },
+                        conn,
+                    )?;
+                }
+            }
+
+            ChainOperation::SettlePlayers {
+                session_id,
+                outcomes,
+                ..
+            } => {
+                use schema::player_sessions::dsl::{
+                    account_id as aid, player_sessions, resolved_at, session_id as sid,
+                };
+
+                let accounts: Vec<String> = outcomes
+                    .iter()
+                    .map(|outcome| outcome.account_id.to_string())
+                    .collect();
+
+                update(player_sessions)
+                    .filter(sid.eq(session_id).and(aid.eq_any(accounts)))
+                    .set(resolved_at.eq(now))
+                    .execute(conn)
+                    .map_err(ServerError::Database)?;
+
+                for outcome in outcomes.iter() {
+                    record_reward(
+                        outcome.account_id.as_str(),
+                        &Reward {
+                            kind: match outcome.xp {
+                                Some(_) => RewardKind::Xp,
+
+                                None => RewardKind::Death,
+                            },
+                            session_id: session_id.to_owned(),
+                            tx_hash: tx_hash.to_owned(),
+                            amount: outcome.xp,
+                            pool_id: None,
+                        },
+                        conn,
+                    )?;
+                }
+            }
+
+            ChainOperation::DeactivatePool { .. } => {}
+        }
+
+        Ok(())
+    })
+}
+
+//attempts so far and when to try again, an entry is dead after MAX_ATTEMPTS
+pub fn retry(entry: &OutboxEntry) -> (i32, &'static str, NaiveDateTime) {
+    let tries = entry.attempts + 1;
+
+    if tries >= MAX_ATTEMPTS {
+        (tries, DEAD, entry.next_attempt_at)
+    } else {
+        (tries, PENDING, Local::now().naive_local() + backoff(tries))
+    }
+}
+
+pub fn fail(entry: &OutboxEntry, e: &str, conn: &mut PgConnection) -> Result<(), ServerError> {
+    use schema::chain_outbox::dsl::{
+        attempts, chain_outbox, id, last_error, next_attempt_at, status,
+    };
+
+    let (tries, next_status, next_attempt) = retry(entry);
+
+    println!(
+        "[Server] Chain Operation Failed ({}/{}) - {}: {}",
+        tries, MAX_ATTEMPTS, &entry.idempotency_key, e
+    );
+
+    update(chain_outbox)
+        .filter(id.eq(&entry.id))
+        .set((
+            status.eq(next_status),
+            attempts.eq(tries),
+            last_error.eq(e),
+            next_attempt_at.eq(next_attempt),
+        ))
+        .execute(conn)
+        .map(|_| ())
+        .map_err(ServerError::Database)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        str::FromStr,
+        sync::{Arc, Mutex},
+    };
+
+    use chrono::Local;
+    use futures::{future::ready, FutureExt};
+    use near_primitives::{hash::CryptoHash, types::AccountId};
+    use uuid::Uuid;
+
+    use crate::{
+        db::models::OutboxEntry,
+        handlers::{
+            memory_chain::{install, ChainCall, MemoryChain},
+            signer::OnSigned,
+        },
+        types::{ChainOperation, Content, PlayerOutcome, ResultStrategy, SessionOutcome},
+    };
+
+    use super::{execute, resolve, RUNNING};
+
+    fn account(id: &str) -> AccountId {
+        AccountId::from_str(id).unwrap()
+    }
+
+    fn unrecorded() -> OnSigned {
+        Arc::new(|_, _| ready(Ok(())).boxed())
+    }
+
+    //an entry claimed by an attempt that signed the given transaction
+    fn claimed(operation: ChainOperation, signed: Option<(AccountId, CryptoHash)>) -> OutboxEntry {
+        let now = Local::now().naive_local();
+
+        OutboxEntry {
+            id: Uuid::new_v4(),
+            idempotency_key: operation.idempotency_key(),
+            session_id: operation.session_id(),
+            operation,
+            status: RUNNING.to_string(),
+            attempts: 1,
+            last_error: None,
+            next_attempt_at: now,
+            created_at: now,
+            completed_at: None,
+            tx_signer: signed.as_ref().map(|(signer_id, _)| signer_id.to_string()),
+            tx_hash: signed.map(|(_, tx_hash)| tx_hash.to_string()),
+        }
+    }
+
+    #[actix::test]
+    async fn player_resolutions_are_sent_to_the_chain() {
+        let chain = MemoryChain::new();
+
+        let _installed = install(&chain).await;
+
+        let session_id = Uuid::new_v4();
+
+        let give_xp = ChainOperation::GiveXp {
+            session_id,
+            account_id: account("alice.testnet"),
+            xp: 20,
+        };
+
+        let kill = ChainOperation::KillCharacter {
+            session_id,
+            account_id: account("bob.testnet"),
+        };
+
+        execute(give_xp, unrecorded()).await.unwrap();
+
+        execute(kill.to_owned(), unrecorded()).await.unwrap();
+
+        //the contract refuses to kill a character twice
+        assert!(execute(kill, unrecorded()).await.is_err());
+
+        assert_eq!(
+            chain.calls(),
+            vec![
+                ChainCall::GiveXp(account("alice.testnet"), 20),
+                ChainCall::KillCharacter(account("bob.testnet")),
+                ChainCall::KillCharacter(account("bob.testnet")),
+            ]
+        );
+    }
+
+    #[actix::test]
+    async fn settlements_are_sent_as_one_call() {
+        let chain = MemoryChain::new();
+
+        let _installed = install(&chain).await;
+
+        let outcomes = vec![
+            PlayerOutcome {
+                account_id: account("alice.testnet"),
+                xp: Some(5),
+            },
+            PlayerOutcome {
+                account_id: account("bob.testnet"),
+                xp: None,
+            },
+        ];
+
+        execute(
+            ChainOperation::SettlePlayers {
+                session_id: Uuid::new_v4(),
+                outcomes: outcomes.to_owned(),
+            },
+            unrecorded(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(chain.calls(), vec![ChainCall::SettlePlayers(outcomes)]);
+    }
+
+    #[actix::test]
+    async fn landed_transactions_are_not_sent_again() {
+        let chain = MemoryChain::new().losing_responses();
+
+        let _installed = install(&chain).await;
+
+        let operation = ChainOperation::KillCharacter {
+            session_id: Uuid::new_v4(),
+            account_id: account("bob.testnet"),
+        };
+
+        let signed = Arc::new(Mutex::new(None));
+
+        let recorder = signed.to_owned();
+
+        let on_signed: OnSigned = Arc::new(move |signer_id, tx_hash| {
+            *recorder.lock().unwrap() = Some((signer_id, tx_hash));
+
+            ready(Ok(())).boxed()
+        });
+
+        //the death landed but the response was lost
+        assert!(execute(operation.to_owned(), on_signed).await.is_err());
+
+        let signed = signed.lock().unwrap().to_owned();
+
+        let tx_hash = signed.as_ref().map(|(_, tx_hash)| tx_hash.to_owned());
+
+        let (success, _) = resolve(&claimed(operation, signed), unrecorded())
+            .await
+            .unwrap();
+
+        assert_eq!(success.tx_hash, tx_hash);
+
+        assert_eq!(
+            chain.calls(),
+            vec![ChainCall::KillCharacter(account("bob.testnet"))]
+        );
+    }
+
+    #[actix::test]
+    async fn landed_deactivations_are_not_sent_again() {
+        let chain = MemoryChain::new().losing_responses();
+
+        let _installed = install(&chain).await;
+
+        let operation = ChainOperation::DeactivatePool {
+            session_id: Some(Uuid::new_v4()),
+            pool_id: "deactivated".to_string(),
+        };
+
+        let signed = Arc::new(Mutex::new(None));
+
+        let recorder = signed.to_owned();
+
+        let on_signed: OnSigned = Arc::new(move |signer_id, tx_hash| {
+            *recorder.lock().unwrap() = Some((signer_id, tx_hash));
+
+            ready(Ok(())).boxed()
+        });
+
+        assert!(execute(operation.to_owned(), on_signed).await.is_err());
+
+        let signed = signed.lock().unwrap().to_owned();
+
+        assert!(signed.is_some());
+
+        resolve(&claimed(operation, signed), unrecorded())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            chain.calls(),
+            vec![ChainCall::TogglePoolActive(
+                "deactivated".to_string(),
+                false
+            )]
+        );
+    }
+
+    #[actix::test]
+    async fn unsigned_entries_are_executed() {
+        let chain = MemoryChain::new();
+
+        let _installed = install(&chain).await;
+
+        let operation = ChainOperation::GiveXp {
+            session_id: Uuid::new_v4(),
+            account_id: account("alice.testnet"),
+            xp: 3,
+        };
+
+        resolve(&claimed(operation, None), unrecorded())
+            .await
+            .unwrap();
+
+        //signed but never broadcast
+        let operation = ChainOperation::GiveXp {
+            session_id: Uuid::new_v4(),
+            account_id: account("alice.testnet"),
+            xp: 4,
+        };
+
+        let dropped = Some((MemoryChain::signer_id(), CryptoHash::hash_bytes(b"dropped")));
+
+        resolve(&claimed(operation, dropped), unrecorded())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            chain.calls(),
+            vec![
+                ChainCall::GiveXp(account("alice.testnet"), 3),
+                ChainCall::GiveXp(account("alice.testnet"), 4),
+            ]
+        );
+    }
+
+    #[actix::test]
+    async fn unknown_pools_are_not_asserted() {
+        let chain = MemoryChain::new();
+
+        let _installed = install(&chain).await;
+
+        let operation = ChainOperation::AssertPoolResult {
+            session_id: Uuid::new_v4(),
+            pool_id: "missing".to_string(),
+            strategy: ResultStrategy::LastSurvivor,
+            outcome: SessionOutcome {
+                players: vec![],
+                data: Content::new(),
+            },
+        };
+
+        assert!(execute(operation, unrecorded()).await.is_err());
+
+        assert!(chain.calls().is_empty());
+    }
+}
@@ -0,0 +1,223 @@
+use std::time::{Duration, Instant};
+
+use actix::{
+    Actor, ActorContext, ActorFutureExt, Addr, AsyncContext, Context, Handler, WrapFuture,
+};
+use uuid::Uuid;
+
+use crate::{
+    db::{
+        actor::{run, LoadReplay},
+        models::ReplayFrameRow,
+    },
+    types::{Content, ReplayFrame},
+};
+
+use super::{client::ClientActor, messages::*};
+
+const PLAYBACK_INTERVAL: Duration = Duration::from_millis(1000 / 60);
+const MAX_SPEED: f32 = 8.0;
+
+//streams a recorded session to a single viewer, paced by when each frame was recorded
+pub struct ReplayActor {
+    pub session_id: Uuid,
+    pub viewer: Addr<ClientActor>,
+    pub frames: Vec<ReplayFrameRow>,
+    //next frame to send
+    pub position: usize,
+    //how far into the recording playback is
+    pub clock: Duration,
+    pub speed: f32,
+    pub last: Instant,
+    pub ended: bool,
+}
+
+pub fn check_speed(speed: f32) -> Result<f32, ServerError> {
+    if speed > 0.0 && speed <= MAX_SPEED {
+        Ok(speed)
+    } else {
+        Err(ServerError::new(
+            std::io::ErrorKind::InvalidInput,
+            &format!("Replay speed must be above 0 and at most {}", MAX_SPEED),
+        ))
+    }
+}
+
+impl ReplayActor {
+    pub fn new(session_id: Uuid, viewer: Addr<ClientActor>, speed: f32) -> Self {
+        Self {
+            session_id,
+            viewer,
+            frames: Vec::new(),
+            position: 0,
+            clock: Duration::default(),
+            speed,
+            last: Instant::now(),
+            ended: false,
+        }
+    }
+
+    fn offset(&self, row: &ReplayFrameRow) -> Duration {
+        match self.frames.first() {
+            Some(first) => row
+                .recorded_at
+                .signed_duration_since(first.recorded_at)
+                .to_std()
+                .unwrap_or_default(),
+
+            None => Duration::default(),
+        }
+    }
+
+    fn length(&self) -> Duration {
+        self.frames
+            .last()
+            .map(|row| self.offset(row))
+            .unwrap_or_default()
+    }
+
+    fn notify(&self, message: &str) {
+        let mut notif = Content::new();
+
+        notif
+            .insert("message", message)
+            .insert("session_id", &self.session_id)
+            .insert("position", &(self.clock.as_millis() as u64))
+            .insert("length", &(self.length().as_millis() as u64));
+
+        self.viewer.do_send(ServerMessage::Notification(notif));
+    }
+
+    //sends every frame recorded before the playback clock
+    fn play(&mut self) {
+        let now = Instant::now();
+
+        self.clock += now.duration_since(self.last).mul_f32(self.speed);
+
+        self.last = now;
+
+        while let Some(row) = self.frames.get(self.position) {
+            let offset = self.offset(row);
+
+            if offset > self.clock {
+                break;
+            }
+
+            let msg = match &row.frame {
+                ReplayFrame::Keyframe {
+                    state,
+                    players,
+                    status,
+                } => ServerMessage::Tick {
+                    tick: offset.as_millis(),
+                    seq: row.tick as u64,
+                    state: state.to_owned(),
+                    players: players.to_owned(),
+                    status: status.to_owned(),
+                },
+
+                ReplayFrame::Update { update, .. } => ServerMessage::Update(update.to_owned()),
+
+                ReplayFrame::Status { status } => {
+                    let mut notif = Content::new();
+
+                    notif
+                        .insert("message", "status changed")
+                        .insert("status", status);
+
+                    ServerMessage::Notification(notif)
+                }
+            };
+
+            self.viewer.do_send(msg);
+
+            self.position += 1;
+        }
+
+        if !self.ended && self.position >= self.frames.len() {
+            self.ended = true;
+
+            self.notify("replay ended");
+        }
+    }
+}
+
+impl Actor for ReplayActor {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let session_id = self.session_id.to_owned();
+
+        ctx.spawn(
+            async move { run(LoadReplay(session_id)).await }
+                .into_actor(self)
+                .map(|res, act, ctx| match res {
+                    Ok(frames) if frames.is_empty() => {
+                        act.viewer.do_send(ServerError::new(
+                            std::io::ErrorKind::NotFound,
+                            &format!("No replay recorded for session {}", &act.session_id),
+                        ));
+
+                        ctx.stop();
+                    }
+
+                    Ok(frames) => {
+                        act.frames = frames;
+
+                        act.last = Instant::now();
+
+                        act.notify("replay started");
+
+                        ctx.run_interval(PLAYBACK_INTERVAL, |act, _| act.play());
+                    }
+
+                    Err(e) => {
+                        act.viewer.do_send(e);
+
+                        ctx.stop();
+                    }
+                }),
+        );
+    }
+}
+
+//playback restarts from the last keyframe before the position and catches up at once
+impl Handler<ReplaySeek> for ReplayActor {
+    type Result = ();
+
+    fn handle(&mut self, ReplaySeek(position): ReplaySeek, _: &mut Context<Self>) {
+        let position = position.min(self.length());
+
+        self.position = self
+            .frames
+            .iter()
+            .rposition(|row| {
+                matches!(row.frame, ReplayFrame::Keyframe { .. }) && self.offset(row) <= position
+            })
+            .unwrap_or(0);
+
+        self.clock = position;
+
+        self.last = Instant::now();
+
+        self.ended = false;
+    }
+}
+
+impl Handler<ReplaySpeed> for ReplayActor {
+    type Result = ();
+
+    fn handle(&mut self, ReplaySpeed(speed): ReplaySpeed, _: &mut Context<Self>) {
+        self.play();
+
+        self.speed = speed;
+    }
+}
+
+impl Handler<ReplayStop> for ReplayActor {
+    type Result = ();
+
+    fn handle(&mut self, _: ReplayStop, ctx: &mut Context<Self>) {
+        ctx.stop();
+    }
+}
@@ -1,34 +1,48 @@
 use crate::{
     db::{
-        models::{PlayerSession, Session, PoolRef, Game},
-        schema, DB,
+        actor::{
+            run, AppendEvents, AppendReplay, EndSession, PlayerProgress, SaveSession,
+            UpdateWhitelist,
+        },
+        models::{NewReplayFrame, PlayerSession, Session, PoolRef, SessionEventRow},
     },
     handlers:: GLOBAL,
-    types::{Content, GameId, Logs, PlayerStats, SessionState, SessionStatus, UserId, GameConfig},
+    types::{ChainOperation, Content, Entities, EntityId, EventLog, GameId, PlayerOutcome, PlayerResult, PlayerStats, ReplayFrame, SessionEvent, SessionOutcome, SessionState, SessionStatus, Settlement, StateDelta, UserId, GameConfig},
 };
 use actix::{
-    prelude::Actor, ActorContext, AsyncContext, Context, Handler, MessageResult,
+    prelude::Actor, ActorContext, ActorFutureExt, AsyncContext, Context, Handler, MessageResult,
+    WrapFuture,
 };
 use chrono::{self, Local, NaiveDateTime};
-use diesel::{prelude::*, sql_types::Jsonb, update};
 use near_primitives::types::AccountId;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{HashMap, HashSet, VecDeque},
+    mem::discriminant,
     str::FromStr,
-    sync::Mutex,
+    sync::{Arc, Mutex},
     time::{Duration, Instant},
 };
 use uuid::Uuid;
 
-use super::{messages::*, ClientInfo, ClientStatus, CLIENTS, SESSIONS};
+use super::{
+    messages::*, outbox::store, validation::EntityValidator, ClientInfo, ClientStatus,
+    SpectatorInfo, CLIENTS, SESSIONS,
+};
 
 pub struct SessionActor {
     pub id: Uuid,
     pub game_id: GameId,
     pub host: UserId,
     pub creator: UserId,
+    pub private: bool,
     pub clients: Mutex<HashMap<UserId, ClientInfo>>,
+    pub spectators: HashMap<UserId, SpectatorInfo>,
+    //broadcasts held back from spectators until the delay has passed
+    pub broadcasts: VecDeque<(Instant, ServerMessage)>,
+    pub spectator_delay: Duration,
+    pub kicked: HashSet<UserId>,
     pub pool_id: Option<String>,
+    pub config: GameConfig,
     pub resolving: Option<NaiveDateTime>,
     pub state: Mutex<SessionState>,
     pub status: SessionStatus,
@@ -37,12 +51,32 @@ pub struct SessionActor {
     pub pause_time: Duration,
     pub paused_at: Option<NaiveDateTime>,
     pub ended_at: Option<NaiveDateTime>,
-    pub logger: Logs,
+    //what happened in the session, appended with the next flush
+    pub logger: EventLog,
+    //state as of the last appended event, the next flush diffs against it
+    pub persisted: SessionState,
+    pub event_seq: i64,
+    //numbered events not yet confirmed by the db, resent with every flush until they are
+    pub unacked: Arc<Mutex<Vec<(i64, NaiveDateTime, SessionEvent)>>>,
+    //replay frames waiting for the next flush
+    pub replay: Vec<NewReplayFrame>,
     pub tick: Instant,
+    pub seq: u64,
+    pub history: VecDeque<(u64, SessionState)>,
+    pub validator: EntityValidator,
+    //when each entity last had a spawn or update accepted, it may move for the time since
+    pub moved_at: HashMap<EntityId, Instant>,
 }
 
 const TICK_INTERVAL: Duration = Duration::from_millis(1000 / 60);
-const LOG_INTERVAL: Duration = Duration::from_secs(10);
+//state changes are appended as events this often
+const EVENT_INTERVAL: Duration = Duration::from_secs(1);
+//events are folded into sessions.state this often
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60);
+//every client receives a full state at least this often
+const KEYFRAME_INTERVAL: u64 = 300;
+//ticks kept to diff against, acks older than this get a keyframe
+const TICK_HISTORY: usize = 120;
 
 impl SessionActor {
     pub fn new(
@@ -50,35 +84,54 @@ impl SessionActor {
             id,
             game_id,
             state,
-            logs,
             pool_id,
             started_at,
             creator,
+            private,
+            snapshot_seq,
             ..
         }: Session,
+        config: GameConfig,
         host: UserId,
+        events: Vec<SessionEventRow>,
     ) -> Self {
+        let mut state = state;
 
-        let mut db = DB.get();
-
-        let conn = db.as_mut().unwrap();
+        let mut event_seq = snapshot_seq;
 
-        use schema::games::dsl::{games, id as gid};
+        //events are loaded in order, anything past the snapshot is replayed on top of it
+        for SessionEventRow { seq, event, .. } in events.iter() {
+            state.apply(event);
 
-        let config: GameConfig = games
-            .filter(gid.eq(&game_id))
-            .get_result::<Game>(conn).unwrap().config;
+            event_seq = event_seq.max(*seq);
+        }
 
         Self {
             id,
             game_id,
             host,
             creator,
+            private,
             clients: Mutex::new(HashMap::new()),
+            spectators: HashMap::new(),
+            broadcasts: VecDeque::new(),
+            spectator_delay: config
+                .spectator_delay
+                .and_then(|secs| Duration::try_from_secs_f32(secs).ok())
+                .unwrap_or_default(),
+            kicked: HashSet::new(),
             resolving: None,
+            persisted: state.to_owned(),
             state: Mutex::new(state),
-            logger: logs,
+            event_seq,
+            unacked: Arc::new(Mutex::new(Vec::new())),
+            replay: Vec::new(),
+            logger: EventLog::default(),
             tick: Instant::now(),
+            seq: 0,
+            history: VecDeque::new(),
+            validator: EntityValidator::from_config(&config.entity_rules),
+            moved_at: HashMap::new(),
             pool_id,
             status: if started_at.is_some() {
                 SessionStatus::Standby {
@@ -90,6 +143,7 @@ impl SessionActor {
                 SessionStatus::Starting(None::<Duration>)
             },
             duration: Duration::from_secs_f32(config.duration*60.0),
+            config,
             pause_time: Duration::default(),
             paused_at: None,
             ended_at: None,
@@ -97,54 +151,172 @@ impl SessionActor {
         }
     }
 
-    pub fn log(&self) {
-        let mut db = DB.get();
+    //stakes can no longer be placed once play begins
+    fn deactivate_pool(&self) {
+        if let Some(pool_id) = &self.pool_id {
+            let session_id = self.id.to_owned();
+
+            let operation = ChainOperation::DeactivatePool {
+                session_id: Some(session_id),
+                pool_id: pool_id.to_owned(),
+            };
+
+            actix::spawn(async move {
+                if let Err(e) = store().enqueue(vec![operation]).await {
+                    println!(
+                        "[Server] DB Error Starting Session - {}: {}",
+                        &session_id,
+                        e.to_string()
+                    )
+                }
+            });
+        }
+    }
 
-        let conn = db.as_mut().unwrap();
+    //only transitions are recorded, not the countdowns in between
+    fn set_status(&mut self, status: SessionStatus) {
+        if discriminant(&self.status) != discriminant(&status) {
+            self.logger.log(SessionEvent::Status {
+                status: status.to_owned(),
+            });
 
-        use schema::sessions::dsl::{id, last_update, logs, sessions, started_at, state};
+            self.record(ReplayFrame::Status {
+                status: status.to_owned(),
+            });
+        }
+
+        self.status = status;
+    }
+
+    fn record(&mut self, frame: ReplayFrame) {
+        self.replay.push(NewReplayFrame {
+            session_id: self.id.to_owned(),
+            tick: self.seq as i64,
+            frame,
+            recorded_at: Local::now().naive_local(),
+        });
+    }
+
+    //appends what changed since the last flush
+    pub fn flush(&mut self) {
+        if !self.replay.is_empty() {
+            let msg = AppendReplay(std::mem::take(&mut self.replay));
+
+            let session_id = self.id.to_owned();
+
+            actix::spawn(async move {
+                if let Err(e) = run(msg).await {
+                    println!(
+                        "[Server] DB Error Recording Replay - {}: {}",
+                        &session_id,
+                        e.to_string()
+                    )
+                }
+            });
+        }
 
         let session_state = self.state.lock().unwrap().to_owned();
 
-        update(sessions)
-            .filter(id.eq(&self.id))
-            .set((
-                logs.eq(self.logger.as_sql::<Jsonb>()),
-                state.eq(session_state.as_sql::<Jsonb>()),
-                last_update.eq(Local::now().naive_local()),
-                started_at.eq(self.started_at),
-            ))
-            .execute(conn)
-            .ok();
+        let mut events = self.logger.take();
 
-        let clients = self.clients.lock().unwrap();
+        let now = Local::now().naive_local();
+
+        events.extend(
+            session_state
+                .events(&self.persisted)
+                .into_iter()
+                .map(|event| (now, event)),
+        );
 
-        use schema::player_sessions::dsl::{ended_at, info, player_sessions, session_id, user_id};
+        self.persisted = session_state;
 
-        for (uid, client_info) in clients.iter() {
-            let req = update(player_sessions).filter(
-                session_id
-                    .eq(&self.id)
-                    .and(user_id.eq(uid))
-                    .and(ended_at.is_null()),
-            );
+        let mut unacked = self.unacked.lock().unwrap();
 
-            match client_info.status {
-                ClientStatus::InProgress(_) => {
-                    req.set(info.eq(session_state.player_info(&uid, &client_info)))
-                        .execute(conn)
-                        .ok();
-                }
+        unacked.extend(events.into_iter().map(|(at, event)| {
+            self.event_seq += 1;
 
-                ClientStatus::Ended(t) => {
-                    req.set(ended_at.eq(t)).execute(conn).ok();
-                }
+            (self.event_seq, at, event)
+        }));
 
-                _ => {}
+        let last = match unacked.last() {
+            Some((seq, ..)) => *seq,
+
+            None => return,
+        };
+
+        //seqs are fixed once numbered, so events that did land are skipped on conflict
+        let msg = AppendEvents {
+            session_id: self.id.to_owned(),
+            events: unacked.to_owned(),
+        };
+
+        drop(unacked);
+
+        let unacked = self.unacked.clone();
+
+        actix::spawn(async move {
+            let session_id = msg.session_id.to_owned();
+
+            match run(msg).await {
+                Ok(()) => unacked.lock().unwrap().retain(|(seq, ..)| *seq > last),
+
+                Err(e) => println!(
+                    "[Server] DB Error Appending Session Events - {}: {}",
+                    &session_id,
+                    e.to_string()
+                )
             }
+        });
+    }
+
+    //flushes first so the snapshot covers every appended event
+    fn snapshot(&mut self) -> SaveSession {
+        self.flush();
+
+        let session_state = self.persisted.to_owned();
+
+        let players = self
+            .clients
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(uid, client_info)| match client_info.status {
+                ClientStatus::InProgress(_) => Some((
+                    uid.to_owned(),
+                    PlayerProgress::Playing(session_state.player_info(&uid, &client_info)),
+                )),
+
+                ClientStatus::Ended(t) => Some((uid.to_owned(), PlayerProgress::Ended(t))),
+
+                _ => None,
+            })
+            .collect();
+
+        SaveSession {
+            session_id: self.id.to_owned(),
+            state: session_state,
+            started_at: self.started_at,
+            players,
+            snapshot_seq: self.event_seq,
         }
     }
 
+    pub fn log(&mut self) {
+        let save = self.snapshot();
+
+        actix::spawn(async move {
+            let session_id = save.session_id.to_owned();
+
+            if let Err(e) = run(save).await {
+                println!(
+                    "[Server] DB Error Saving Session - {}: {}",
+                    &session_id,
+                    e.to_string()
+                )
+            }
+        });
+    }
+
     pub fn toggle_timer(&mut self) {
         match self.paused_at {
             Some(t) => {
@@ -171,18 +343,57 @@ impl SessionActor {
         }) - self.pause_time;
     }
 
+    fn broadcast(&mut self, msg: ServerMessage) {
+        if !self.spectators.is_empty() {
+            self.broadcasts.push_back((Instant::now(), msg));
+        }
+    }
+
+    //sends spectators every broadcast older than the delay
+    //live is the current tick, for spectators that have not had one without a delay
+    fn release(&mut self, live: Option<ServerMessage>) {
+        while let Some((queued_at, _)) = self.broadcasts.front() {
+            if queued_at.elapsed() < self.spectator_delay {
+                break;
+            }
+
+            let (_, msg) = self.broadcasts.pop_front().unwrap();
+
+            for (_, spectator) in self.spectators.iter_mut() {
+                match (&msg, &live) {
+                    (ServerMessage::Tick { .. }, _) => {
+                        spectator.synced = true;
+
+                        spectator.actor.do_send(msg.to_owned());
+                    }
+
+                    (ServerMessage::Delta { .. }, _) if spectator.synced => {
+                        spectator.actor.do_send(msg.to_owned())
+                    }
+
+                    (ServerMessage::Delta { .. }, Some(live)) => {
+                        spectator.synced = true;
+
+                        spectator.actor.do_send(live.to_owned());
+                    }
+
+                    (ServerMessage::Delta { .. }, None) => {}
+
+                    _ => spectator.actor.do_send(msg.to_owned()),
+                }
+            }
+        }
+    }
+
     pub fn send_tick(&mut self) {
 
         let mut clients = self.clients.lock().unwrap();
 
         let session_state = self.state.lock().unwrap().to_owned();
 
-        let mut actors = Vec::new();
-
         let mut players = HashMap::new();
 
         for (id, client_info) in clients.iter_mut() {
-            actors.push(client_info.actor.to_owned());
             players.insert(id.to_owned(), session_state.player_info(&id, &client_info));
 
             match client_info.status {
@@ -197,18 +408,112 @@ impl SessionActor {
                 _ => {}
             }
         }
-        
-        let tick = ServerMessage::Tick {
-            players,
-            state: session_state.to_owned(),
-            tick: Instant::now()
-                .duration_since(self.tick.to_owned())
-                .as_millis(),
-            status: self.status.to_owned(),
+
+        self.seq += 1;
+
+        let tick = Instant::now()
+            .duration_since(self.tick.to_owned())
+            .as_millis();
+
+        let keyframe = self.seq % KEYFRAME_INTERVAL == 0;
+
+        let mut deltas: HashMap<u64, StateDelta> = HashMap::new();
+
+        for (_, client_info) in clients.iter_mut() {
+            let base = match (client_info.acked_seq, client_info.last_keyframe) {
+                _ if keyframe => None,
+
+                (Some(acked), Some(sent)) => Some(acked.max(sent)),
+
+                (acked, sent) => acked.or(sent),
+            }
+            .and_then(|base| self.history.iter().find(|(seq, _)| *seq == base));
+
+            match base {
+                Some((base, base_state)) => {
+                    let delta = deltas
+                        .entry(base.to_owned())
+                        .or_insert_with(|| session_state.diff(base_state));
+
+                    client_info.actor.do_send(ServerMessage::Delta {
+                        tick,
+                        seq: self.seq,
+                        base: base.to_owned(),
+                        delta: delta.to_owned(),
+                        players: players.to_owned(),
+                        status: self.status.to_owned(),
+                    });
+                }
+
+                None => {
+                    client_info.last_keyframe = Some(self.seq);
+
+                    client_info.actor.do_send(ServerMessage::Tick {
+                        tick,
+                        seq: self.seq,
+                        state: session_state.to_owned(),
+                        players: players.to_owned(),
+                        status: self.status.to_owned(),
+                    });
+                }
+            }
+        }
+
+        drop(clients);
+
+        //spectators have no acks, each delta is against the tick before it
+        if !self.spectators.is_empty() {
+            let frame = match self.history.back() {
+                Some((base, base_state)) if !keyframe => ServerMessage::Delta {
+                    tick,
+                    seq: self.seq,
+                    base: base.to_owned(),
+                    delta: session_state.diff(base_state),
+                    players: players.to_owned(),
+                    status: self.status.to_owned(),
+                },
+
+                _ => ServerMessage::Tick {
+                    tick,
+                    seq: self.seq,
+                    state: session_state.to_owned(),
+                    players: players.to_owned(),
+                    status: self.status.to_owned(),
+                },
+            };
+
+            self.broadcast(frame);
+        }
+
+        let live = if self.spectator_delay.is_zero()
+            && self.spectators.values().any(|spectator| !spectator.synced)
+        {
+            Some(ServerMessage::Tick {
+                tick,
+                seq: self.seq,
+                state: session_state.to_owned(),
+                players: players.to_owned(),
+                status: self.status.to_owned(),
+            })
+        } else {
+            None
         };
 
-        for actor in actors {
-            actor.do_send(tick.to_owned());
+        self.release(live);
+
+        //replays start from the first tick and can seek to any keyframe
+        if keyframe || self.seq == 1 {
+            self.record(ReplayFrame::Keyframe {
+                state: session_state.to_owned(),
+                players,
+                status: self.status.to_owned(),
+            });
+        }
+
+        self.history.push_back((self.seq, session_state));
+
+        while self.history.len() > TICK_HISTORY {
+            self.history.pop_front();
         }
 
         self.tick = Instant::now();
@@ -237,14 +542,18 @@ impl Actor for SessionActor {
                                 .naive_local(),
                         );
 
+                        if !act.private {
+                            GLOBAL.do_send(LobbyEvent(LobbyUpdate::Started(act.id.to_owned())));
+                        }
+
                         t = act.started_at.map(|s| {
-                            Local::now()
-                                .naive_local()
-                                .signed_duration_since(s)
+                            s.signed_duration_since(Local::now().naive_local())
                                 .to_std()
-                                .unwrap()
+                                .unwrap_or_default()
                         });
 
+                        act.status = SessionStatus::Starting(t);
+
                         for (_, client_info) in clients.iter_mut() {
                             client_info.status = ClientStatus::InProgress(
                                 Local::now()
@@ -282,20 +591,25 @@ impl Actor for SessionActor {
                         if Local::now().naive_local() > start_time {
                             act.toggle_timer();
 
-                            act.status = SessionStatus::InProgress(act.elapsed())
+                            act.deactivate_pool();
+
+                            act.set_status(SessionStatus::InProgress(act.elapsed()))
                         } else {
-                            t = Local::now()
-                                .naive_local()
-                                .signed_duration_since(start_time)
+                            t = start_time
+                                .signed_duration_since(Local::now().naive_local())
                                 .to_std()
-                                .unwrap();
+                                .unwrap_or_default();
+
+                            act.status = SessionStatus::Starting(Some(t));
                         }
                     }
                 }
 
                 SessionStatus::InProgress(mut t) => {
                     if act.elapsed() >= act.duration {
-                        act.status = SessionStatus::PostSession;
+                        act.logger.log(SessionEvent::Ended { by: None });
+
+                        act.set_status(SessionStatus::PostSession);
                     } else {
                         t = act.elapsed();
                     }
@@ -325,7 +639,7 @@ impl Actor for SessionActor {
                                 .checked_add_signed(chrono::Duration::from_std(duration).unwrap())
                                 .unwrap()
                         {
-                            act.status = SessionStatus::InProgress(act.elapsed());
+                            act.set_status(SessionStatus::InProgress(act.elapsed()));
 
                             act.toggle_timer();
                         }
@@ -336,13 +650,32 @@ impl Actor for SessionActor {
             act.send_tick();
         });
 
-        ctx.run_interval(LOG_INTERVAL, |act, _| act.log());
+        ctx.run_interval(EVENT_INTERVAL, |act, _| act.flush());
+
+        ctx.run_interval(SNAPSHOT_INTERVAL, |act, _| act.log());
     }
 
-    fn stopped(&mut self, _ctx: &mut Self::Context) {
+    fn stopped(&mut self, ctx: &mut Self::Context) {
+        self.flush();
+
         let mut session_guard = SESSIONS.lock().unwrap();
 
-        session_guard.remove(&self.id);
+        //a new actor may already be registered for the session
+        if session_guard.get(&self.id) == Some(&ctx.address()) {
+            session_guard.remove(&self.id);
+        }
+
+        let mut notif = Content::new();
+
+        notif
+            .insert("message", "session closed")
+            .insert("session_id", &self.id);
+
+        for (_, spectator) in self.spectators.drain() {
+            spectator
+                .actor
+                .do_send(ServerMessage::Notification(notif.to_owned()));
+        }
     }
 }
 
@@ -352,14 +685,21 @@ impl Handler<SessionEnd> for SessionActor {
     fn handle(&mut self, _: SessionEnd, ctx: &mut Context<Self>) {
         self.toggle_timer();
 
+        if self.ended_at.is_none() && !self.private {
+            GLOBAL.do_send(LobbyEvent(LobbyUpdate::Ended(self.id.to_owned())));
+        }
+
+        //timeouts and ends requested by the host were logged when the status changed
+        if self.ended_at.is_none() && self.status != SessionStatus::PostSession {
+            self.logger.log(SessionEvent::Ended { by: None });
+        }
+
         let end = self
             .ended_at
             .get_or_insert(Local::now().naive_local())
             .to_owned();
 
-        let mut clients = self.clients.lock().unwrap();
-
-        for (_, client_info) in clients.iter_mut() {
+        for (_, client_info) in self.clients.lock().unwrap().iter_mut() {
             match client_info.status {
                 ClientStatus::Ended(_) => {}
 
@@ -367,154 +707,160 @@ impl Handler<SessionEnd> for SessionActor {
             }
         }
 
-        self.log();
+        self.resolving = Some(Local::now().naive_local());
 
-        use schema::sessions::dsl::{ ended_at as session_end, id as session_id, sessions
+        let msg = EndSession {
+            save: self.snapshot(),
+            ended_at: end,
+            pool_id: self.pool_id.to_owned(),
         };
 
-        let mut db = DB.get();
-
-        let conn = db.as_mut().unwrap();
-
-        self.resolving = Some(Local::now().naive_local());
+        ctx.spawn(async move { run(msg).await }.into_actor(self).map(
+            |res, act, ctx| match res {
+                Ok((players, pool)) => act.settle(players, pool, ctx),
 
-        match update(sessions)
-            .filter(session_id.eq(&self.id))
-            .set((
-                session_end.eq(end),
-            ))
-            .execute(conn)
-        {
-            Ok(_) => {
-                let session_state = self.state.lock().unwrap().to_owned();
+                Err(e) => println!(
+                    "[Server] DB Error Ending Session - {} : {}",
+                    &act.id,
+                    e.to_string()
+                ),
+            },
+        ));
+    }
+}
 
-                use schema::player_sessions::dsl::{player_sessions, session_id};
+impl SessionActor {
+    //hands unresolved outcomes to the global actor, stops once everything is resolved
+    fn settle(
+        &mut self,
+        res: Vec<PlayerSession>,
+        pool: Option<PoolRef>,
+        ctx: &mut Context<Self>,
+    ) {
+        let session_state = self.state.lock().unwrap().to_owned();
 
-                match player_sessions.filter(session_id.eq(&self.id)).load::<PlayerSession>(conn) {
-                    Ok(res) => {
+        let clients = self.clients.lock().unwrap();
 
-                    }
+        let mut players = Vec::new();
 
-                    _ => {}
-                }
+        let mut outcomes = Vec::new();
 
-                match player_sessions
-                    .filter(session_id.eq(&self.id))
-                    .get_results::<PlayerSession>(conn)
-                {
-                    Ok(ref mut res) => {
-                        let mut result = HashMap::new();
-
-                        for PlayerSession {
-                            user_id,
-                            account_id,
-                            ended_at,
-                            resolved_at,
-                            ..
-                        } in (res as &mut Vec<PlayerSession>).iter_mut() {
-
-                            if let Some(player_session_end) = ended_at {
-
-                                let PlayerStats {
-                                    kills,
-                                    xp_accrual,
-                                    death,
-                                } = session_state.stats.get(&user_id as &UserId).unwrap();
-
-                                match account_id {
-                                    Some(id) => match AccountId::from_str(&id) {
-                                        Ok(id) => {
-                                            let xp = match death {
-                                                Some(_) => None,
-                                                None => {
-                                                    result.insert(id.to_owned(), player_session_end.to_owned());
-            
-                                                    Some(xp_accrual)
-                                                }
-                                            };
-            
-                                            if resolved_at.is_none() {
-                                                GLOBAL.do_send(PlayerSessionResolve {
-                                                    session_id: self.id.to_owned(),
-                                                    account_id: id.to_owned(),
-                                                    xp: xp.copied(),
-                                                });
-                                            }
-                                        },
-                                        Err(_) => {},
-                                    },
-                                    None => {},
-                                };
+        for PlayerSession {
+            user_id,
+            account_id,
+            ended_at,
+            resolved_at,
+            info,
+            ..
+        } in res.iter() {
+
+            if let Some(player_session_end) = ended_at {
+
+                let stats = session_state.stats.get(&user_id as &UserId).unwrap();
+
+                let PlayerStats {
+                    xp_accrual,
+                    death,
+                    ..
+                } = stats;
+
+                match account_id {
+                    Some(id) => match AccountId::from_str(&id) {
+                        Ok(id) => {
+                            players.push(PlayerResult {
+                                account_id: id.to_owned(),
+                                team: clients
+                                    .get(&user_id as &UserId)
+                                    .and_then(|c| c.team)
+                                    .or(info.as_ref().and_then(|i| i.team)),
+                                stats: stats.to_owned(),
+                                ended_at: player_session_end.to_owned(),
+                            });
+
+                            let xp = match death {
+                                Some(_) => None,
+                                None => Some(xp_accrual),
+                            };
+
+                            if resolved_at.is_none() {
+                                match self.config.settlement {
+                                    Settlement::PerPlayer => GLOBAL.do_send(PlayerSessionResolve {
+                                        session_id: self.id.to_owned(),
+                                        account_id: id.to_owned(),
+                                        xp: xp.copied(),
+                                    }),
+
+                                    Settlement::Batched => outcomes.push(PlayerOutcome {
+                                        account_id: id.to_owned(),
+                                        xp: xp.copied(),
+                                    }),
+                                }
                             }
-                        }
+                        },
+                        Err(_) => {},
+                    },
+                    None => {},
+                };
+            }
+        }
 
-                        if let Some(pool_id) = &self.pool_id {
+        if !outcomes.is_empty() {
+            GLOBAL.do_send(SessionSettle {
+                session_id: self.id.to_owned(),
+                outcomes,
+            });
+        }
 
-                            use schema::pools::dsl::{pools, id};
+        let all_resolved = res.iter().all(|s| s.resolved_at.is_some());
 
-                            match pools
-                                .filter(
-                                    id.eq(&pool_id)
-                                )
-                                .get_result::<PoolRef>(conn)
-                            {
-                                Ok(pool) if pool.resolved_at.is_some() => {
-                                    if (res as &mut Vec<PlayerSession>).iter().all(|s| s.resolved_at.is_some()) {
-                                        ctx.stop();
-                                    }
-                                },
-                            
-                                Err(_) => println!(
-                                        "[Server] DB Error During Session Resolve - {}: Unregistered Pool {}",
-                                        &self.id,
-                                        &pool_id
-                                ),
-                    
-                                Ok(_) => {
-                                    let mut results: Vec<(AccountId, NaiveDateTime)> = result
-                                        .iter()
-                                        .map(|(account_id, player_session_end)| (account_id.to_owned(), player_session_end.to_owned()))
-                                        .collect();
-
-                                    results.sort_by(|a, b| b.1.cmp(&a.1));
-
-                                    println!("Results: {:?}", results);
-
-                                    GLOBAL.do_send(SessionResolve {
-                                        session_id: self.id.to_owned(),
-                                        results,
-                                        pool_id: pool_id.to_owned(),
-                                    });
-                                }
-                            }
-                        } else {
-                            if (res as &mut Vec<PlayerSession>).iter().all(|s| s.resolved_at.is_some()) {
-                                ctx.stop();  
-                            }
-                        }
+        if let Some(pool_id) = &self.pool_id {
+            match pool {
+                Some(pool) if pool.resolved_at.is_some() => {
+                    if all_resolved {
+                        self.logger.log(SessionEvent::Resolved {
+                            pool_id: Some(pool_id.to_owned()),
+                        });
+
+                        ctx.stop();
                     }
+                },
 
-                    Err(e) => println!(
-                        "[Server] DB Error Ending Session - {} : {}",
+                None => println!(
+                        "[Server] DB Error During Session Resolve - {}: Unregistered Pool {}",
                         &self.id,
-                        e.to_string()
-                    ),
+                        &pool_id
+                ),
+
+                Some(_) => {
+                    GLOBAL.do_send(SessionResolve {
+                        session_id: self.id.to_owned(),
+                        pool_id: pool_id.to_owned(),
+                        strategy: self.config.pool_result.to_owned(),
+                        outcome: SessionOutcome {
+                            players,
+                            data: session_state.data.to_owned(),
+                        },
+                    });
                 }
             }
+        } else {
+            if all_resolved {
+                self.logger.log(SessionEvent::Resolved { pool_id: None });
 
-            Err(e) => println!(
-                "[Server] DB Error Ending Session - {} : {}",
-                &self.id,
-                e.to_string()
-            ),
-        };
+                ctx.stop();  
+            }
+        }
     }
 }
 
 impl Handler<SessionResolve> for SessionActor {
     type Result = ();
 
-    fn handle(&mut self, SessionResolve { .. }: SessionResolve, ctx: &mut Context<Self>) {
+    fn handle(&mut self, SessionResolve { pool_id, .. }: SessionResolve, ctx: &mut Context<Self>) {
+        self.logger.log(SessionEvent::Resolved {
+            pool_id: Some(pool_id),
+        });
+
         ctx.stop();
     }
 }
@@ -528,7 +874,17 @@ impl Handler<SessionMessage> for SessionActor {
                 client.actor.do_send(msg.to_owned());
             }
         }
-        self.logger.log(&msg);
+
+        if let ServerMessage::Message { sender, msg } = &msg {
+            self.logger.log(SessionEvent::Chat {
+                sender: sender.to_owned(),
+                msg: msg.to_owned(),
+            });
+        }
+
+        if let ServerMessage::Notification(_) | ServerMessage::Update(_) = &msg {
+            self.broadcast(msg.to_owned());
+        }
     }
 }
 
@@ -548,18 +904,68 @@ impl Handler<Join> for SessionActor {
 
         let client_actor = guard.get(&user_id).unwrap();
 
+        if self.kicked.contains(&user_id) {
+            let e = ServerError::new(
+                std::io::ErrorKind::PermissionDenied,
+                &format!("{} was kicked from {}", &user_id, &self.id),
+            );
+
+            self.logger.error(&user_id, &e);
+
+            //an actor started for a rejected join would stay registered with no one in it
+            if self.clients.lock().unwrap().is_empty() {
+                ctx.stop();
+            }
+
+            return MessageResult(Err(e));
+        }
+
         let mut clients = self.clients.lock().unwrap();
 
-        clients.insert(
-            user_id.to_owned(),
-            ClientInfo::new(client_actor.to_owned(), account_id),
-        );
+        if !clients.contains_key(&user_id) && clients.len() >= self.config.player_limit as usize {
+            let e = ServerError::new(
+                std::io::ErrorKind::PermissionDenied,
+                &format!(
+                    "Session {} is full ({} players)",
+                    &self.id, self.config.player_limit
+                ),
+            );
+
+            self.logger.error(&user_id, &e);
+
+            if clients.is_empty() {
+                ctx.stop();
+            }
+
+            return MessageResult(Err(e));
+        }
+
+        let team = match player_info.team {
+            Some(team) if team >= 0 && team < self.config.teams => team,
+
+            _ => (0..self.config.teams.max(1))
+                .min_by_key(|team| {
+                    clients
+                        .iter()
+                        .filter(|(id, c)| *id != &user_id && c.team == Some(*team))
+                        .count()
+                })
+                .unwrap_or(0),
+        };
+
+        let mut client_info = ClientInfo::new(client_actor.to_owned(), account_id);
+
+        client_info.team = Some(team);
+
+        clients.insert(user_id.to_owned(), client_info);
 
         let mut notif = Content::new();
 
         let msg = format!("{} joined.", &user_id);
 
-        self.logger.log(&msg);
+        self.logger.log(SessionEvent::Joined {
+            user_id: user_id.to_owned(),
+        });
 
         notif.insert("message", &msg).insert("id", &user_id);
 
@@ -582,7 +988,7 @@ impl Handler<Join> for SessionActor {
 
         println!("[Server] {:?} has joined {}", &user_id, &self.id);
 
-        MessageResult((session_state.to_owned(), players))
+        MessageResult(Ok((session_state.to_owned(), players)))
     }
 }
 
@@ -614,6 +1020,10 @@ impl Handler<Leave> for SessionActor {
 
                 println!("[Server] {:?} has left {}", &user_id, self.id.to_owned());
 
+                self.logger.log(SessionEvent::Left {
+                    user_id: user_id.to_owned(),
+                });
+
                 match clients.iter().next() {
                     Some((new_manager, _)) => {
                         managed_entites = session_state.entities.managed(&user_id);
@@ -641,10 +1051,207 @@ impl Handler<Leave> for SessionActor {
     }
 }
 
+impl Handler<KickPlayer> for SessionActor {
+    type Result = Result<(), ServerError>;
+
+    fn handle(
+        &mut self,
+        KickPlayer {
+            user_id,
+            by,
+            moderator,
+        }: KickPlayer,
+        ctx: &mut Context<Self>,
+    ) -> Self::Result {
+        if !moderator && by != self.host {
+            return Err(ServerError::new(
+                std::io::ErrorKind::PermissionDenied,
+                "Only the host or a moderator can kick players",
+            ));
+        }
+
+        let actor = match self.clients.lock().unwrap().get(&user_id) {
+            Some(client_info) => client_info.actor.to_owned(),
+
+            None => {
+                return Err(ServerError::new(
+                    std::io::ErrorKind::NotFound,
+                    &format!("{} is not in session {}", &user_id, &self.id),
+                ))
+            }
+        };
+
+        self.kicked.insert(user_id.to_owned());
+
+        let msg = format!("{} was kicked by {}.", &user_id, &by);
+
+        self.logger.log(SessionEvent::Kicked {
+            user_id: user_id.to_owned(),
+            by: by.to_owned(),
+        });
+
+        let mut notif = Content::new();
+
+        notif.insert("message", &msg).insert("id", &user_id);
+
+        ctx.notify(SessionMessage {
+            msg: ServerMessage::Notification(notif),
+            exclude: vec![user_id.to_owned()],
+        });
+
+        actor.do_send(Kicked {
+            session_id: self.id.to_owned(),
+            by,
+        });
+
+        Ok(())
+    }
+}
+
+impl Handler<WhitelistUpdate> for SessionActor {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        WhitelistUpdate {
+            updater,
+            add,
+            remove,
+        }: WhitelistUpdate,
+        ctx: &mut Context<Self>,
+    ) {
+        let actor = match self.clients.lock().unwrap().get(&updater) {
+            Some(client_info) => client_info.actor.to_owned(),
+
+            None => return,
+        };
+
+        if updater != self.creator && updater != self.host {
+            actor.do_send(ServerError::new(
+                std::io::ErrorKind::PermissionDenied,
+                "Only the session creator or host can change the whitelist",
+            ));
+
+            return;
+        }
+
+        let msg = UpdateWhitelist {
+            session_id: self.id.to_owned(),
+            add: add.to_owned(),
+            remove: remove.to_owned(),
+        };
+
+        ctx.spawn(async move { run(msg).await }.into_actor(self).map(
+            move |res, act, _ctx| match res {
+                Ok(_) => {
+                    let mut notif = Content::new();
+
+                    notif
+                        .insert("message", "whitelist updated")
+                        .insert("added", &add)
+                        .insert("removed", &remove);
+
+                    act.logger.log(SessionEvent::Whitelisted {
+                        by: updater,
+                        added: add,
+                        removed: remove,
+                    });
+
+                    actor.do_send(ServerMessage::Notification(notif));
+                }
+
+                Err(e) => {
+                    act.logger.error(&updater, &e);
+
+                    actor.do_send(e)
+                }
+            },
+        ));
+    }
+}
+
+impl Handler<Spectate> for SessionActor {
+    type Result = Result<(), ServerError>;
+
+    fn handle(
+        &mut self,
+        Spectate { user_id, actor }: Spectate,
+        _: &mut Context<Self>,
+    ) -> Self::Result {
+        if self.kicked.contains(&user_id) {
+            return Err(ServerError::new(
+                std::io::ErrorKind::PermissionDenied,
+                &format!("{} was kicked from {}", &user_id, &self.id),
+            ));
+        }
+
+        if self.clients.lock().unwrap().contains_key(&user_id) {
+            return Err(ServerError::new(
+                std::io::ErrorKind::AlreadyExists,
+                &format!("{} is already playing in {}", &user_id, &self.id),
+            ));
+        }
+
+        let mut notif = Content::new();
+
+        notif
+            .insert("message", "spectating")
+            .insert("session_id", &self.id)
+            .insert("delay", &self.spectator_delay.as_secs_f32());
+
+        actor.do_send(ServerMessage::Notification(notif));
+
+        self.spectators
+            .insert(user_id.to_owned(), SpectatorInfo { actor, synced: false });
+
+        println!("[Server] {:?} is spectating {}", &user_id, &self.id);
+
+        Ok(())
+    }
+}
+
+impl Handler<StopSpectating> for SessionActor {
+    type Result = ();
+
+    fn handle(&mut self, StopSpectating(user_id): StopSpectating, _: &mut Context<Self>) {
+        self.spectators.remove(&user_id);
+    }
+}
+
+impl Handler<TickAck> for SessionActor {
+    type Result = ();
+
+    fn handle(&mut self, TickAck { user_id, seq }: TickAck, _: &mut Context<Self>) {
+        let mut clients = self.clients.lock().unwrap();
+
+        if let Some(client_info) = clients.get_mut(&user_id) {
+            if seq <= self.seq && client_info.acked_seq.map_or(true, |acked| seq > acked) {
+                client_info.acked_seq = Some(seq);
+            }
+        }
+    }
+}
+
+impl Handler<Resync> for SessionActor {
+    type Result = ();
+
+    fn handle(&mut self, Resync(user_id): Resync, _: &mut Context<Self>) {
+        let mut clients = self.clients.lock().unwrap();
+
+        if let Some(client_info) = clients.get_mut(&user_id) {
+            client_info.acked_seq = None;
+            client_info.last_keyframe = None;
+        }
+    }
+}
+
 impl Handler<SessionUpdate> for SessionActor {
     type Result = ();
 
     fn handle(&mut self, SessionUpdate { updater, update }: SessionUpdate, _: &mut Context<Self>) {
+        //the parts of the update that were applied, recorded for replays
+        let mut accepted = None;
+
         match update {
             Update::Affect {
                 affector,
@@ -675,6 +1282,12 @@ impl Handler<SessionUpdate> for SessionActor {
                             affected: affected_entities,
                         }))
                     }
+
+                    accepted = Some(Update::Affect {
+                        affector,
+                        affectors,
+                        affected,
+                    });
                 }
             }
 
@@ -688,37 +1301,114 @@ impl Handler<SessionUpdate> for SessionActor {
 
                 let updater_managed_entities = session_state.entities.managed(&updater);
 
+                let mut rejections = Vec::new();
+
+                let mut applied = Entities::default();
+
+                let mut killed = HashSet::new();
+
+                let mut spawned = Entities::default();
+
+                let now = Instant::now();
+
                 for (id, entity) in active.0.iter() {
                     if updater_managed_entities.contains(id) {
-                        session_state
-                            .entities
-                            .update(id.to_owned(), entity.to_owned());
+                        //entities the actor has no time for, such as restored ones, get a tick
+                        let ticks = self.moved_at.get(id).map_or(1.0, |t| {
+                            now.duration_since(*t).as_secs_f64() / TICK_INTERVAL.as_secs_f64()
+                        });
+
+                        let checked = match session_state.entities.0.get(id) {
+                            Some(current) => self.validator.check_update(
+                                &updater,
+                                current,
+                                entity.to_owned(),
+                                ticks,
+                            ),
+
+                            None => Ok(entity.to_owned()),
+                        };
 
-                        session_state.pending_spawns.remove(id);
+                        match checked {
+                            Ok(entity) => {
+                                self.moved_at.insert(id.to_owned(), now);
+
+                                applied.update(id.to_owned(), entity.to_owned());
+
+                                session_state.entities.update(id.to_owned(), entity);
+
+                                session_state.pending_spawns.remove(id);
+                            }
+
+                            Err(reason) => rejections.push(ServerError::Rejected {
+                                entity_id: id.to_owned(),
+                                reason,
+                            }),
+                        }
                     }
                 }
 
                 for id in kill_list.iter() {
                     if updater_managed_entities.contains(id) {
                         if let Some(entity) = session_state.entities.remove(id) {
+                            self.moved_at.remove(id);
+
                             session_state.destroyed_entities.insert(id, entity);
+
+                            killed.insert(id.to_owned());
                         }
                     }
                 }
 
                 for (id, entity) in spawns.0.iter() {
                     if !session_state.pending_spawns.contains_key(id) {
-                        let new_id = session_state.entities.insert(id, entity.to_owned());
+                        match self
+                            .validator
+                            .check_spawn(&updater, entity.to_owned(), &session_state)
+                        {
+                            Ok(entity) => {
+                                spawned.update(id.to_owned(), entity.to_owned());
+
+                                let new_id = session_state.entities.insert(id, entity);
+
+                                self.moved_at.insert(new_id.to_owned(), now);
 
-                        session_state.pending_spawns.insert(id.to_owned(), new_id);
+                                session_state.pending_spawns.insert(id.to_owned(), new_id);
+                            }
+
+                            Err(reason) => rejections.push(ServerError::Rejected {
+                                entity_id: id.to_owned(),
+                                reason,
+                            }),
+                        }
+                    }
+                }
+
+                if !rejections.is_empty() {
+                    if let Some(client_info) = self.clients.lock().unwrap().get(&updater) {
+                        for rejection in rejections {
+                            self.logger.error(&updater, &rejection);
+
+                            client_info.actor.do_send(rejection);
+                        }
                     }
                 }
+
+                if !applied.0.is_empty() || !killed.is_empty() || !spawned.0.is_empty() {
+                    accepted = Some(Update::Entities {
+                        active: applied,
+                        spawns: spawned,
+                        kill_list: killed,
+                    });
+                }
             }
 
             Update::ChangeSpawn(spawn) if updater == self.host => {
                 
                 let mut session_state = self.state.lock().unwrap();
 
+                accepted = Some(Update::ChangeSpawn(spawn.to_owned()));
+
                 session_state.spawn = spawn
             },
 
@@ -726,6 +1416,8 @@ impl Handler<SessionUpdate> for SessionActor {
 
                 let mut session_state = self.state.lock().unwrap();
 
+                accepted = Some(Update::Stats(stats.to_owned()));
+
                 session_state.stats.insert(updater.to_owned(), stats);
             }
 
@@ -735,6 +1427,8 @@ impl Handler<SessionUpdate> for SessionActor {
 
                 let updater_info = clients.get_mut(&updater).unwrap();
 
+                accepted = Some(Update::Status(status.to_owned()));
+
                 updater_info.status = status
             },
 
@@ -742,11 +1436,18 @@ impl Handler<SessionUpdate> for SessionActor {
                 SessionStatus::InProgress(_) => {
                     self.toggle_timer();
 
-                    self.status = SessionStatus::Standby {
+                    self.set_status(SessionStatus::Standby {
                         paused_at: Local::now().naive_local(),
                         for_duration: None,
                         by: Some(updater.to_owned()),
-                    }
+                    });
+
+                    self.logger.log(SessionEvent::Paused {
+                        by: updater.to_owned(),
+                        for_duration: None,
+                    });
+
+                    accepted = Some(Update::Pause(None));
                 }
                 _ => {}
             },
@@ -755,11 +1456,18 @@ impl Handler<SessionUpdate> for SessionActor {
                 SessionStatus::InProgress(_) => {
                     self.toggle_timer();
 
-                    self.status = SessionStatus::Standby {
+                    self.set_status(SessionStatus::Standby {
                         paused_at: Local::now().naive_local(),
                         for_duration,
                         by: Some(updater.to_owned()),
-                    }
+                    });
+
+                    self.logger.log(SessionEvent::Paused {
+                        by: updater.to_owned(),
+                        for_duration,
+                    });
+
+                    accepted = Some(Update::Pause(for_duration));
                 }
                 _ => {}
             },
@@ -770,7 +1478,13 @@ impl Handler<SessionUpdate> for SessionActor {
                 {
                     self.toggle_timer();
 
-                    self.status = SessionStatus::InProgress(self.elapsed());
+                    self.set_status(SessionStatus::InProgress(self.elapsed()));
+
+                    self.logger.log(SessionEvent::Resumed {
+                        by: updater.to_owned(),
+                    });
+
+                    accepted = Some(Update::Resume);
                 }
 
                 _ => {}
@@ -778,7 +1492,13 @@ impl Handler<SessionUpdate> for SessionActor {
 
             Update::End if updater == self.host => match self.status {
                 SessionStatus::InProgress(_) => {
-                    self.status = SessionStatus::PostSession;
+                    self.logger.log(SessionEvent::Ended {
+                        by: Some(updater.to_owned()),
+                    });
+
+                    self.set_status(SessionStatus::PostSession);
+
+                    accepted = Some(Update::End);
                 }
                 _ => {}
             },
@@ -786,6 +1506,17 @@ impl Handler<SessionUpdate> for SessionActor {
             _ => {}
         };
 
+        if let Some(update) = accepted {
+            if let Update::Affect { .. } = &update {
+                self.broadcast(ServerMessage::Update(update.to_owned()));
+            }
+
+            self.record(ReplayFrame::Update {
+                updater: updater.to_owned(),
+                update,
+            });
+        }
+
         let mut clients = self.clients.lock().unwrap();
 
         let updater_info = clients.get_mut(&updater).unwrap();
@@ -0,0 +1,321 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use actix::{
+    clock::sleep, Actor, ActorFutureExt, AtomicResponse, Context, Handler, Message, WrapFuture,
+};
+use futures::future::BoxFuture;
+use near_crypto::InMemorySigner;
+use near_jsonrpc_client::methods::{self, tx::RpcTransactionError};
+use near_primitives::{
+    errors::InvalidTxError,
+    hash::CryptoHash,
+    transaction::{Action, FunctionCallAction, SignedTransaction},
+    types::{AccountId, Balance, BlockReference, Finality, Gas},
+    views::{FinalExecutionOutcomeView, FinalExecutionStatus},
+};
+
+use crate::config::near;
+
+use super::{
+    contract_methods::{get_current_nonce, Success, RPC},
+    messages::ServerError,
+};
+
+const DEPOSIT: Balance = 10u128.pow(24);
+const GAS: Gas = 5_000_000_000_000;
+//a transaction can carry at most 300 Tgas, the rest is left for the receipt itself
+const MAX_BATCH_GAS: Gas = 250_000_000_000_000;
+pub const MAX_BATCH_CALLS: usize = (MAX_BATCH_GAS / GAS) as usize;
+const NONCE_RETRIES: usize = 3;
+const POLL_TIMEOUT: Duration = Duration::from_secs(60);
+
+lazy_static::lazy_static! {
+    //one submitter per admin access key, transactions are spread round robin
+    static ref SIGNERS: Vec<actix::Addr<SignerActor>> = near()
+        .signers()
+        .into_iter()
+        .map(|signer| SignerActor::new(signer).start())
+        .collect();
+
+    static ref NEXT_SIGNER: AtomicUsize = AtomicUsize::new(0);
+}
+
+//called with the signer and hash of each transaction before it is broadcast, nothing is broadcast if it fails
+pub type OnSigned =
+    Arc<dyn Fn(AccountId, CryptoHash) -> BoxFuture<'static, Result<(), ServerError>> + Send + Sync>;
+
+enum TxError {
+    InvalidNonce(u64),
+    Failed(ServerError),
+}
+
+//actions in one transaction succeed or fail together
+#[derive(Message)]
+#[rtype(result = "Result<Success, ServerError>")]
+pub struct SubmitTransaction {
+    pub receiver_id: AccountId,
+    pub actions: Vec<Action>,
+    pub on_signed: Option<OnSigned>,
+}
+
+//signs with a single access key, one transaction at a time so nonces never race
+pub struct SignerActor {
+    signer: InMemorySigner,
+    nonce: Option<u64>,
+}
+
+impl SignerActor {
+    pub fn new(signer: InMemorySigner) -> Self {
+        Self {
+            signer,
+            nonce: None,
+        }
+    }
+}
+
+impl Actor for SignerActor {
+    type Context = Context<Self>;
+
+    fn started(&mut self, _: &mut Self::Context) {
+        println!(
+            "[Server] Signer started for {} ({})",
+            self.signer.account_id, self.signer.public_key
+        );
+    }
+}
+
+impl Handler<SubmitTransaction> for SignerActor {
+    type Result = AtomicResponse<Self, Result<Success, ServerError>>;
+
+    fn handle(&mut self, tx: SubmitTransaction, _: &mut Self::Context) -> Self::Result {
+        let signer = self.signer.to_owned();
+
+        let cached = self.nonce;
+
+        AtomicResponse::new(Box::pin(
+            async move { sign_and_send(&signer, cached, tx).await }
+                .into_actor(self)
+                .map(|(res, nonce), act, _| {
+                    act.nonce = nonce;
+
+                    res
+                }),
+        ))
+    }
+}
+
+//returns the last used nonce alongside the result, None forces a refetch on the next call
+async fn sign_and_send(
+    signer: &InMemorySigner,
+    mut cached: Option<u64>,
+    SubmitTransaction {
+        receiver_id,
+        actions,
+        on_signed,
+    }: SubmitTransaction,
+) -> (Result<Success, ServerError>, Option<u64>) {
+    for _ in 0..NONCE_RETRIES {
+        let (hash, nonce) = match cached {
+            Some(nonce) => match latest_block_hash().await {
+                Ok(hash) => (hash, nonce),
+
+                Err(e) => return (Err(e), cached),
+            },
+
+            None => match get_current_nonce(&signer.account_id, &signer.public_key).await {
+                Ok(res) => res,
+
+                Err(e) => return (Err(e), None),
+            },
+        };
+
+        let signed_transaction = SignedTransaction::from_actions(
+            nonce + 1,
+            signer.account_id.to_owned(),
+            receiver_id.to_owned(),
+            signer,
+            actions.to_owned(),
+            hash,
+        );
+
+        if let Some(on_signed) = &on_signed {
+            if let Err(e) =
+                on_signed(signer.account_id.to_owned(), signed_transaction.get_hash()).await
+            {
+                return (Err(e), Some(nonce));
+            }
+        }
+
+        let req = methods::broadcast_tx_commit::RpcBroadcastTxCommitRequest { signed_transaction };
+
+        match poll_transaction(&req).await {
+            Ok(success) => return (Ok(success), Some(nonce + 1)),
+
+            Err(TxError::InvalidNonce(ak_nonce)) => {
+                println!(
+                    "[Server] Invalid nonce {} for {}, access key is at {}",
+                    nonce + 1,
+                    signer.account_id,
+                    ak_nonce
+                );
+
+                cached = Some(ak_nonce);
+            }
+
+            //the nonce is consumed once the transaction reaches a chunk, refetch to be safe
+            Err(TxError::Failed(e)) => return (Err(e), None),
+        }
+    }
+
+    (
+        Err(ServerError::Transaction(format!(
+            "Transaction to {} failed after {} nonce retries",
+            receiver_id, NONCE_RETRIES
+        ))),
+        None,
+    )
+}
+
+async fn latest_block_hash() -> Result<CryptoHash, ServerError> {
+    match RPC
+        .call(methods::block::RpcBlockRequest {
+            block_reference: BlockReference::Finality(Finality::Final),
+        })
+        .await
+    {
+        Ok(block) => Ok(block.header.hash),
+
+        Err(e) => Err(ServerError::Query(e.to_string())),
+    }
+}
+
+async fn poll_transaction(
+    req: &methods::broadcast_tx_commit::RpcBroadcastTxCommitRequest,
+) -> Result<Success, TxError> {
+    let sent_at = Instant::now();
+    loop {
+        match RPC.call(req).await {
+            Ok(FinalExecutionOutcomeView {
+                status: FinalExecutionStatus::SuccessValue(value),
+                transaction,
+                ..
+            }) => {
+                break Ok(Success {
+                    tx_hash: Some(transaction.hash),
+                    value,
+                });
+            }
+
+            Ok(FinalExecutionOutcomeView {
+                status: FinalExecutionStatus::Failure(e),
+                ..
+            }) => {
+                break Err(TxError::Failed(ServerError::Transaction(e.to_string())));
+            }
+
+            Err(error) => {
+                let e = error
+                    .handler_error()
+                    .unwrap_or(&RpcTransactionError::TimeoutError);
+
+                match e {
+                    RpcTransactionError::TimeoutError
+                    | RpcTransactionError::UnknownTransaction { .. } => {
+                        sleep(Duration::from_secs(2)).await;
+                        continue;
+                    }
+
+                    RpcTransactionError::InvalidTransaction {
+                        context: InvalidTxError::InvalidNonce { ak_nonce, .. },
+                    } => {
+                        break Err(TxError::InvalidNonce(*ak_nonce));
+                    }
+
+                    _ => {
+                        break Err(TxError::Failed(ServerError::Transaction(e.to_string())));
+                    }
+                }
+            }
+
+            _ => {}
+        };
+
+        if Instant::now().duration_since(sent_at) > POLL_TIMEOUT {
+            break Err(TxError::Failed(ServerError::Transaction(
+                RpcTransactionError::TimeoutError.to_string(),
+            )));
+        };
+    }
+}
+
+fn function_call(method_name: &str, args: Vec<u8>) -> Action {
+    Action::FunctionCall(FunctionCallAction {
+        method_name: method_name.to_string(),
+        args,
+        gas: GAS,
+        deposit: DEPOSIT,
+    })
+}
+
+async fn send(
+    receiver_id: AccountId,
+    actions: Vec<Action>,
+    on_signed: Option<OnSigned>,
+) -> Result<Success, ServerError> {
+    let signer = &SIGNERS[NEXT_SIGNER.fetch_add(1, Ordering::Relaxed) % SIGNERS.len()];
+
+    match signer
+        .send(SubmitTransaction {
+            receiver_id,
+            actions,
+            on_signed,
+        })
+        .await
+    {
+        Ok(res) => res,
+
+        Err(e) => Err(ServerError::Transaction(e.to_string())),
+    }
+}
+
+pub async fn submit(
+    receiver_id: AccountId,
+    method_name: &str,
+    args: Vec<u8>,
+    on_signed: Option<OnSigned>,
+) -> Result<Success, ServerError> {
+    send(
+        receiver_id,
+        vec![function_call(method_name, args)],
+        on_signed,
+    )
+    .await
+}
+
+//returns the value of the last call
+pub async fn submit_batch(
+    receiver_id: AccountId,
+    calls: Vec<(&str, Vec<u8>)>,
+    on_signed: Option<OnSigned>,
+) -> Result<Success, ServerError> {
+    if calls.len() > MAX_BATCH_CALLS {
+        return Err(ServerError::Transaction(format!(
+            "A batch of {} calls exceeds the limit of {}",
+            calls.len(),
+            MAX_BATCH_CALLS
+        )));
+    }
+
+    let actions = calls
+        .into_iter()
+        .map(|(method_name, args)| function_call(method_name, args))
+        .collect();
+
+    send(receiver_id, actions, on_signed).await
+}
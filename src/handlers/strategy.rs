@@ -0,0 +1,355 @@
+use std::{cmp::Reverse, collections::BTreeMap, str::FromStr};
+
+use delt_d::staking::Pool;
+use near_primitives::types::AccountId;
+use serde_json::{json, Value};
+
+use crate::types::{Content, PlayerResult, ResultStrategy, SessionOutcome};
+
+use super::messages::ServerError;
+
+//accounts from best to worst, with what the order was decided on
+pub struct Ranking {
+    pub accounts: Vec<AccountId>,
+    pub justification: Value,
+}
+
+pub trait Strategy {
+    fn rank(&self, outcome: &SessionOutcome) -> Result<Ranking, String>;
+}
+
+impl Strategy for ResultStrategy {
+    fn rank(&self, outcome: &SessionOutcome) -> Result<Ranking, String> {
+        match self {
+            ResultStrategy::LastSurvivor => {
+                let mut survivors: Vec<&PlayerResult> = outcome
+                    .players
+                    .iter()
+                    .filter(|p| p.stats.death.is_none())
+                    .collect();
+
+                survivors.sort_by_key(|p| Reverse(p.ended_at));
+
+                Ok(Ranking {
+                    accounts: accounts(&survivors),
+                    justification: json!({
+                        "survivors": survivors
+                            .iter()
+                            .map(|p| json!({ "account_id": p.account_id, "ended_at": p.ended_at }))
+                            .collect::<Vec<Value>>(),
+                    }),
+                })
+            }
+
+            ResultStrategy::MostKills => {
+                let mut players: Vec<&PlayerResult> = outcome.players.iter().collect();
+
+                players.sort_by_key(|p| (Reverse(p.stats.kills), p.stats.death.is_some()));
+
+                Ok(Ranking {
+                    accounts: accounts(&players),
+                    justification: json!({
+                        "kills": players
+                            .iter()
+                            .map(|p| json!({ "account_id": p.account_id, "kills": p.stats.kills }))
+                            .collect::<Vec<Value>>(),
+                    }),
+                })
+            }
+
+            ResultStrategy::HighestXp => {
+                let mut players: Vec<&PlayerResult> = outcome.players.iter().collect();
+
+                players.sort_by_key(|p| (Reverse(p.stats.xp_accrual), p.stats.death.is_some()));
+
+                Ok(Ranking {
+                    accounts: accounts(&players),
+                    justification: json!({
+                        "xp_accrual": players
+                            .iter()
+                            .map(|p| json!({ "account_id": p.account_id, "xp_accrual": p.stats.xp_accrual.to_string() }))
+                            .collect::<Vec<Value>>(),
+                    }),
+                })
+            }
+
+            ResultStrategy::TeamVictory => {
+                //(survivors, kills) per team
+                let mut teams: BTreeMap<i32, (usize, i32)> = BTreeMap::new();
+
+                for p in outcome.players.iter() {
+                    if let Some(team) = p.team {
+                        let score = teams.entry(team).or_insert((0, 0));
+
+                        if p.stats.death.is_none() {
+                            score.0 += 1;
+                        }
+
+                        score.1 += p.stats.kills;
+                    }
+                }
+
+                let winner = match teams
+                    .iter()
+                    .max_by_key(|(team, score)| (**score, Reverse(**team)))
+                {
+                    Some((team, _)) => team.to_owned(),
+
+                    None => return Err("No player was assigned a team".to_string()),
+                };
+
+                let mut players: Vec<&PlayerResult> = outcome
+                    .players
+                    .iter()
+                    .filter(|p| p.team == Some(winner))
+                    .collect();
+
+                players.sort_by_key(|p| (p.stats.death.is_some(), Reverse(p.stats.kills)));
+
+                Ok(Ranking {
+                    accounts: accounts(&players),
+                    justification: json!({
+                        "winner": winner,
+                        "teams": teams
+                            .iter()
+                            .map(|(team, (survivors, kills))| json!({ "team": team, "survivors": survivors, "kills": kills }))
+                            .collect::<Vec<Value>>(),
+                    }),
+                })
+            }
+
+            ResultStrategy::Script { key } => {
+                let ranked: Vec<&str> = match outcome.data.0.get(key) {
+                    Some(Value::String(id)) => vec![id.as_str()],
+
+                    Some(Value::Array(ids)) => ids.iter().filter_map(|id| id.as_str()).collect(),
+
+                    Some(_) => {
+                        return Err(format!("{} is not an account id or a list of them", key))
+                    }
+
+                    None => return Err(format!("Game script did not set {}", key)),
+                };
+
+                let accounts = ranked
+                    .into_iter()
+                    .map(|id| AccountId::from_str(id).map_err(|e| format!("{}: {}", id, e)))
+                    .collect::<Result<Vec<AccountId>, String>>()?;
+
+                Ok(Ranking {
+                    justification: json!({ "key": key, "ranking": accounts }),
+                    accounts,
+                })
+            }
+        }
+    }
+}
+
+fn accounts(players: &[&PlayerResult]) -> Vec<AccountId> {
+    players.iter().map(|p| p.account_id.to_owned()).collect()
+}
+
+//the best ranked account that is a pool result or staked on one decides the result
+pub fn pool_result(ranking: &[AccountId], pool: &Pool) -> Result<Option<AccountId>, ServerError> {
+    for aid in ranking.iter() {
+        for (res, stakes) in pool.required_stakes.0.iter() {
+            let pool_result = AccountId::from_str(res.as_str())
+                .map_err(|e| ServerError::Query(format!("{}: {}", res, e)))?;
+
+            if aid == &pool_result
+                || stakes
+                    .iter()
+                    .any(|(account_id, _)| account_id.as_str() == aid.as_str())
+            {
+                return Ok(Some(pool_result));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+//accounts that staked on the result, the contract decides what each of them is paid
+pub fn stakers(pool: &Pool, result: &Option<AccountId>) -> Result<Vec<AccountId>, ServerError> {
+    let result = match result {
+        Some(result) => result,
+
+        None => return Ok(vec![]),
+    };
+
+    let mut stakers = Vec::new();
+
+    for (res, stakes) in pool.required_stakes.0.iter() {
+        if res.as_str() != result.as_str() {
+            continue;
+        }
+
+        for (account_id, _) in stakes.iter() {
+            stakers.push(
+                AccountId::from_str(account_id.as_str())
+                    .map_err(|e| ServerError::Query(format!("{}: {}", account_id, e)))?,
+            );
+        }
+    }
+
+    //an account may have placed several stakes
+    stakers.sort();
+    stakers.dedup();
+
+    Ok(stakers)
+}
+
+//recorded in pools.result once the result is asserted
+pub fn record(
+    strategy: &ResultStrategy,
+    ranking: &Ranking,
+    result: &Option<AccountId>,
+    stakers: &[AccountId],
+) -> Content {
+    let mut content = Content::new();
+
+    content
+        .insert("result", result)
+        .insert("strategy", strategy)
+        .insert("ranking", &ranking.accounts)
+        .insert("justification", &ranking.justification)
+        .insert("stakers", stakers);
+
+    content
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use chrono::{Local, NaiveDateTime};
+    use near_primitives::types::AccountId;
+    use serde_json::json;
+
+    use crate::types::{Content, PlayerResult, PlayerStats, ResultStrategy, SessionOutcome};
+
+    use super::Strategy;
+
+    fn player(
+        id: &str,
+        team: Option<i32>,
+        kills: i32,
+        death: Option<NaiveDateTime>,
+    ) -> PlayerResult {
+        PlayerResult {
+            account_id: AccountId::from_str(id).unwrap(),
+            team,
+            stats: PlayerStats {
+                kills,
+                death,
+                ..Default::default()
+            },
+            ended_at: Local::now().naive_local(),
+        }
+    }
+
+    fn outcome(players: Vec<PlayerResult>) -> SessionOutcome {
+        SessionOutcome {
+            players,
+            data: Content::new(),
+        }
+    }
+
+    fn ranked(strategy: &ResultStrategy, outcome: &SessionOutcome) -> Vec<String> {
+        strategy
+            .rank(outcome)
+            .unwrap()
+            .accounts
+            .iter()
+            .map(|account_id| account_id.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn most_kills_ties_go_to_survivors_then_keep_their_order() {
+        let dead = Some(Local::now().naive_local());
+
+        let outcome = outcome(vec![
+            player("dead.testnet", None, 3, dead),
+            player("first.testnet", None, 3, None),
+            player("second.testnet", None, 3, None),
+            player("top.testnet", None, 5, dead),
+        ]);
+
+        assert_eq!(
+            ranked(&ResultStrategy::MostKills, &outcome),
+            vec![
+                "top.testnet",
+                "first.testnet",
+                "second.testnet",
+                "dead.testnet"
+            ]
+        );
+    }
+
+    #[test]
+    fn team_victory_ties_go_to_kills_then_the_lowest_team() {
+        let dead = Some(Local::now().naive_local());
+
+        //one survivor each, team 2 has more kills
+        let by_kills = outcome(vec![
+            player("a1.testnet", Some(1), 1, None),
+            player("a2.testnet", Some(1), 0, dead),
+            player("b1.testnet", Some(2), 2, dead),
+            player("b2.testnet", Some(2), 0, None),
+        ]);
+
+        assert_eq!(
+            ranked(&ResultStrategy::TeamVictory, &by_kills),
+            vec!["b2.testnet", "b1.testnet"]
+        );
+
+        //same survivors and kills
+        let by_team = outcome(vec![
+            player("b1.testnet", Some(2), 1, None),
+            player("a1.testnet", Some(1), 0, None),
+            player("a2.testnet", Some(1), 1, dead),
+        ]);
+
+        assert_eq!(
+            ranked(&ResultStrategy::TeamVictory, &by_team),
+            vec!["a1.testnet", "a2.testnet"]
+        );
+
+        assert!(ResultStrategy::TeamVictory
+            .rank(&outcome(vec![player("solo.testnet", None, 0, None)]))
+            .is_err());
+    }
+
+    #[test]
+    fn script_rankings_keep_the_script_order() {
+        let strategy = ResultStrategy::Script {
+            key: "winners".to_string(),
+        };
+
+        let mut ranked_by_script = outcome(vec![]);
+
+        ranked_by_script
+            .data
+            .insert("winners", &json!(["b.testnet", "a.testnet"]));
+
+        assert_eq!(
+            ranked(&strategy, &ranked_by_script),
+            vec!["b.testnet", "a.testnet"]
+        );
+
+        let mut single = outcome(vec![]);
+
+        single.data.insert("winners", "a.testnet");
+
+        assert_eq!(ranked(&strategy, &single), vec!["a.testnet"]);
+
+        let mut invalid = outcome(vec![]);
+
+        invalid.data.insert("winners", &json!(["Not An Account"]));
+
+        assert!(strategy.rank(&invalid).is_err());
+
+        assert!(strategy.rank(&outcome(vec![])).is_err());
+    }
+}
@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+
+use crate::types::{Entity, EntityRule, SessionState, UserId};
+
+pub trait Rule: Send {
+    fn check_spawn(
+        &self,
+        _updater: &UserId,
+        entity: Entity,
+        _state: &SessionState,
+    ) -> Result<Entity, String> {
+        Ok(entity)
+    }
+
+    //ticks is the time since the entity's last accepted update, in ticks
+    fn check_update(
+        &self,
+        _updater: &UserId,
+        _current: &Entity,
+        next: Entity,
+        _ticks: f64,
+    ) -> Result<Entity, String> {
+        Ok(next)
+    }
+}
+
+impl Rule for EntityRule {
+    fn check_spawn(
+        &self,
+        updater: &UserId,
+        entity: Entity,
+        state: &SessionState,
+    ) -> Result<Entity, String> {
+        match self {
+            EntityRule::SpawnManaged if &entity.manager != updater => Err(format!(
+                "{} cannot spawn entities managed by {}",
+                updater, entity.manager
+            )),
+
+            EntityRule::SpawnLimit(limit) => {
+                let spawned = state
+                    .entities
+                    .0
+                    .values()
+                    .filter(|e| &e.manager == updater && e.entity_type == entity.entity_type)
+                    .count();
+
+                if spawned >= *limit {
+                    Err(format!(
+                        "spawn limit of {} reached for {}",
+                        limit, entity.entity_type
+                    ))
+                } else {
+                    Ok(entity)
+                }
+            }
+
+            _ => Ok(entity),
+        }
+    }
+
+    fn check_update(
+        &self,
+        _updater: &UserId,
+        current: &Entity,
+        mut next: Entity,
+        ticks: f64,
+    ) -> Result<Entity, String> {
+        match self {
+            EntityRule::FixedManager if next.manager != current.manager => Err(format!(
+                "manager of {} cannot be reassigned",
+                current.entity_type
+            )),
+
+            EntityRule::MaxMovement(max) => {
+                next.position = current.position.towards(&next.position, max * ticks);
+
+                Ok(next)
+            }
+
+            _ => Ok(next),
+        }
+    }
+}
+
+//rules are checked in order, a rule may clamp the entity before passing it on
+pub struct EntityValidator {
+    rules: HashMap<String, Vec<Box<dyn Rule>>>,
+}
+
+impl EntityValidator {
+    pub fn new() -> Self {
+        Self {
+            rules: HashMap::new(),
+        }
+    }
+
+    pub fn from_config(entity_rules: &HashMap<String, Vec<EntityRule>>) -> Self {
+        let mut validator = Self::new();
+
+        for (entity_type, rules) in entity_rules.iter() {
+            for rule in rules {
+                validator.add_rule(entity_type, Box::new(rule.to_owned()));
+            }
+        }
+
+        validator
+    }
+
+    pub fn add_rule(&mut self, entity_type: &str, rule: Box<dyn Rule>) -> &mut Self {
+        self.rules
+            .entry(entity_type.to_string())
+            .or_insert(Vec::new())
+            .push(rule);
+
+        self
+    }
+
+    fn rules_for<'a>(&'a self, entity_type: &'a str) -> impl Iterator<Item = &'a Box<dyn Rule>> {
+        self.rules
+            .get("*")
+            .into_iter()
+            .chain(self.rules.get(entity_type).into_iter())
+            .flatten()
+    }
+
+    pub fn check_spawn(
+        &self,
+        updater: &UserId,
+        entity: Entity,
+        state: &SessionState,
+    ) -> Result<Entity, String> {
+        let entity_type = entity.entity_type.to_owned();
+
+        self.rules_for(&entity_type)
+            .try_fold(entity, |entity, rule| {
+                rule.check_spawn(updater, entity, state)
+            })
+    }
+
+    pub fn check_update(
+        &self,
+        updater: &UserId,
+        current: &Entity,
+        next: Entity,
+        ticks: f64,
+    ) -> Result<Entity, String> {
+        if next.entity_type != current.entity_type {
+            return Err(format!(
+                "entity type cannot change from {} to {}",
+                current.entity_type, next.entity_type
+            ));
+        }
+
+        self.rules_for(&current.entity_type)
+            .try_fold(next, |next, rule| {
+                rule.check_update(updater, current, next, ticks)
+            })
+    }
+}
+
+impl Default for EntityValidator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,233 @@
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use diesel::prelude::*;
+use near_primitives::types::{AccountId, Balance};
+use serde_json::to_value;
+
+use crate::{
+    db::{models::RewardRow, schema},
+    types::{Lvl, RewardEntry, UserId},
+};
+
+use super::{
+    chain::chain,
+    messages::{ServerError, WalletResponse},
+};
+
+//long enough to absorb lobby screens polling, short enough to show rewards soon after a session
+const CACHE_TTL: Duration = Duration::from_secs(10);
+
+pub const REWARDS_PAGE: usize = 50;
+const MAX_REWARDS_PAGE: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WalletQuery {
+    Character,
+    Balance,
+    Stakes,
+    Lvl,
+}
+
+lazy_static::lazy_static! {
+    static ref CACHE: Mutex<HashMap<(AccountId, WalletQuery), (Instant, WalletResponse)>> =
+        Mutex::new(HashMap::new());
+}
+
+fn cached(account_id: &AccountId, query: WalletQuery) -> Option<WalletResponse> {
+    match CACHE.lock().unwrap().get(&(account_id.to_owned(), query)) {
+        Some((fetched_at, res)) if fetched_at.elapsed() < CACHE_TTL => Some(res.to_owned()),
+
+        _ => None,
+    }
+}
+
+fn store(account_id: &AccountId, query: WalletQuery, res: &WalletResponse) {
+    let mut cache = CACHE.lock().unwrap();
+
+    cache.retain(|_, (fetched_at, _)| fetched_at.elapsed() < CACHE_TTL);
+
+    cache.insert(
+        (account_id.to_owned(), query),
+        (Instant::now(), res.to_owned()),
+    );
+}
+
+//the requested account if the user owns it, otherwise their most recently active one
+pub fn owned_account(
+    uid: &UserId,
+    requested: Option<AccountId>,
+    conn: &mut PgConnection,
+) -> Result<AccountId, ServerError> {
+    use schema::accounts::dsl::{account_id, accounts, last_active, user_id};
+
+    let mut query = accounts
+        .filter(user_id.eq(uid))
+        .select(account_id)
+        .order(last_active.desc().nulls_last())
+        .into_boxed();
+
+    if let Some(requested) = &requested {
+        query = query.filter(account_id.eq(requested.as_str()));
+    }
+
+    match query.first::<String>(conn) {
+        Ok(id) => AccountId::from_str(&id).map_err(|e| ServerError::Query(e.to_string())),
+
+        Err(diesel::result::Error::NotFound) => Err(ServerError::new(
+            std::io::ErrorKind::NotFound,
+            &match requested {
+                Some(requested) => format!("{} is not linked to {}", requested, uid),
+
+                None => format!("{} has no linked account", uid),
+            },
+        )),
+
+        Err(e) => Err(ServerError::Database(e)),
+    }
+}
+
+//newest first, across every account the user has linked unless one is requested
+pub fn reward_history(
+    uid: &UserId,
+    requested: Option<AccountId>,
+    offset: usize,
+    limit: usize,
+    conn: &mut PgConnection,
+) -> Result<Vec<RewardEntry>, ServerError> {
+    use schema::accounts::dsl::{account_id, accounts, user_id};
+    use schema::rewards::dsl::{account_id as rid, id, rewards};
+
+    let mut linked = accounts
+        .filter(user_id.eq(uid))
+        .select(account_id)
+        .into_boxed();
+
+    if let Some(requested) = &requested {
+        linked = linked.filter(account_id.eq(requested.as_str()));
+    }
+
+    let linked = linked.load::<String>(conn).map_err(ServerError::Database)?;
+
+    match &requested {
+        Some(requested) if linked.is_empty() => {
+            return Err(ServerError::new(
+                std::io::ErrorKind::NotFound,
+                &format!("{} is not linked to {}", requested, uid),
+            ))
+        }
+
+        _ => {}
+    }
+
+    //ids follow insertion order, timestamps can collide
+    rewards
+        .filter(rid.eq_any(linked))
+        .order(id.desc())
+        .offset(offset as i64)
+        .limit(limit.min(MAX_REWARDS_PAGE) as i64)
+        .get_results::<RewardRow>(conn)
+        .map(|rows| {
+            rows.into_iter()
+                .map(|row| RewardEntry {
+                    account_id: row.account_id,
+                    at: row.created_at,
+                    reward: row.reward,
+                })
+                .collect()
+        })
+        .map_err(ServerError::Database)
+}
+
+async fn balance(account_id: &AccountId) -> Result<Balance, ServerError> {
+    if let Some(WalletResponse::Balance(balance)) = cached(account_id, WalletQuery::Balance) {
+        if let Ok(balance) = balance.parse::<Balance>() {
+            return Ok(balance);
+        }
+    }
+
+    let balance = chain().get_ft_balance(account_id.to_owned()).await?;
+
+    store(
+        account_id,
+        WalletQuery::Balance,
+        &WalletResponse::Balance(balance.to_string()),
+    );
+
+    Ok(balance)
+}
+
+pub async fn query(
+    account_id: AccountId,
+    query: WalletQuery,
+) -> Result<WalletResponse, ServerError> {
+    if let Some(res) = cached(&account_id, query) {
+        return Ok(res);
+    }
+
+    let res = match query {
+        WalletQuery::Character => WalletResponse::Character(
+            to_value(chain().get_character(account_id.to_owned()).await?)
+                .map_err(ServerError::Serde)?,
+        ),
+
+        WalletQuery::Balance => WalletResponse::Balance(balance(&account_id).await?.to_string()),
+
+        WalletQuery::Stakes => WalletResponse::Stakes(
+            to_value(chain().get_stakes(account_id.to_owned()).await?)
+                .map_err(ServerError::Serde)?,
+        ),
+
+        WalletQuery::Lvl => WalletResponse::Lvl(Lvl::from_xp(balance(&account_id).await?)),
+    };
+
+    store(&account_id, query, &res);
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use near_primitives::types::AccountId;
+
+    use crate::handlers::{
+        memory_chain::{install, MemoryChain},
+        messages::WalletResponse,
+    };
+
+    use super::{query, WalletQuery};
+
+    #[actix::test]
+    async fn balances_are_cached() {
+        let account_id = AccountId::from_str("cached.testnet").unwrap();
+
+        let chain = MemoryChain::new().with_balance(account_id.to_owned(), 42);
+
+        let _installed = install(&chain).await;
+
+        assert_eq!(
+            query(account_id.to_owned(), WalletQuery::Balance)
+                .await
+                .unwrap(),
+            WalletResponse::Balance("42".to_string())
+        );
+
+        chain
+            .state
+            .lock()
+            .unwrap()
+            .balances
+            .insert(account_id.to_owned(), 7);
+
+        assert_eq!(
+            query(account_id, WalletQuery::Balance).await.unwrap(),
+            WalletResponse::Balance("42".to_string())
+        );
+    }
+}
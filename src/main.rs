@@ -3,16 +3,24 @@ use actix_web::{middleware, web, App, Error, HttpRequest, HttpResponse, HttpServ
 use actix_web_actors::ws;
 use actix_web_httpauth::middleware::HttpAuthentication;
 use dotenv::from_path;
-use handlers::client::ClientActor;
+use handlers::{client::ClientActor, messages::Encoding, CLIENTS};
 use serde_json::json;
 
-use std::{env, net::Ipv4Addr, path};
+use std::{
+    env,
+    io::{self, ErrorKind},
+    net::Ipv4Addr,
+    path,
+};
 
 use crate::{
-    db::{run_migrations, validator},
+    db::{run_migrations, validator, Identity},
     handlers::contract_methods::set_default_attributes,
 };
 
+mod api;
+mod auth;
+mod config;
 mod db;
 mod handlers;
 mod types;
@@ -40,10 +48,20 @@ lazy_static::lazy_static! {
 }
 
 async fn index(req: HttpRequest, stream: web::Payload) -> Result<HttpResponse, Error> {
-    let ext = req.extensions();
-    let act: ClientActor = ext.get::<ClientActor>().unwrap().to_owned();
+    let Identity { user_id, roles, .. } = req.extensions().get::<Identity>().unwrap().to_owned();
+
+    if CLIENTS.lock().unwrap().get(&user_id).is_some() {
+        return Err(Error::from(io::Error::new(
+            ErrorKind::AddrInUse,
+            format!("{} already connected", &user_id),
+        )));
+    }
+
+    let mut act = ClientActor::new(user_id, roles);
+
+    act.encoding = Encoding::negotiate(&req);
 
-    match ws::start(act, &req, stream) {
+    match ws::start_with_protocols(act, &Encoding::PROTOCOLS, &req, stream) {
         Ok(res) => Ok(res),
 
         Err(e) => Err(e),
@@ -55,6 +73,18 @@ async fn main() -> std::io::Result<()> {
     println!("Server Started");
 
     from_path(&*ENV_PATH).expect("Error fetching env variables");
+
+    match config::init() {
+        Ok(near) => println!(
+            "[Server] Using {:?} rpc {} as {}",
+            near.network, near.rpc_url, near.signer.account_id
+        ),
+
+        Err(e) => return Err(io::Error::new(ErrorKind::InvalidInput, e.to_string())),
+    }
+
+    //picks up chain operations left pending by the last run
+    lazy_static::initialize(&handlers::OUTBOX);
     // run_migrations();
 
     // let attributes = json!({//must match PlayerAttributes type at game\entities\player.ts
@@ -76,8 +106,13 @@ async fn main() -> std::io::Result<()> {
     HttpServer::new(move || {
         App::new()
             .wrap(middleware::Logger::default())
-            .wrap(HttpAuthentication::basic(validator))
-            .route("/", web::get().to(index))
+            .configure(api::public)
+            .service(
+                web::scope("")
+                    .wrap(HttpAuthentication::with_fn(validator))
+                    .route("/", web::get().to(index))
+                    .configure(api::config),
+            )
     })
     .bind(*SERVER_URL)?
     .run()
@@ -6,9 +6,10 @@ use diesel::{
     serialize::{self, Output, ToSql},
     sql_types::Jsonb,
 };
+use near_primitives::{hash::CryptoHash, types::AccountId};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
-use serde_json::{from_slice, to_string, to_value, Map, Value};
+use serde_json::{from_slice, from_value, to_string, to_value, Map, Value};
 use std::{
     collections::{
         hash_map::{DefaultHasher, Entry},
@@ -24,7 +25,13 @@ pub type UserId = String;
 
 use std::hash::{Hash, Hasher};
 
-use crate::handlers::{ClientInfo, ClientStatus};
+use crate::{
+    db::models::Session,
+    handlers::{
+        messages::{ServerError, Update},
+        ClientInfo, ClientStatus,
+    },
+};
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone, AsExpression, FromSqlRow)]
 #[diesel(sql_type = Jsonb)]
@@ -56,10 +63,367 @@ impl SessionState {
                 .unwrap_or(&PlayerStats::default())
                 .to_owned(),
             status: client.status.to_owned(),
+            team: client.team,
+        }
+    }
+
+    pub fn diff(&self, base: &SessionState) -> StateDelta {
+        let pending_spawns: HashMap<EntityId, EntityId> = self
+            .pending_spawns
+            .iter()
+            .filter(|(id, new_id)| base.pending_spawns.get(*id) != Some(*new_id))
+            .map(|(id, new_id)| (id.to_owned(), new_id.to_owned()))
+            .collect();
+
+        let resolved_spawns: HashSet<EntityId> = base
+            .pending_spawns
+            .keys()
+            .filter(|id| !self.pending_spawns.contains_key(*id))
+            .copied()
+            .collect();
+
+        let stats: HashMap<UserId, PlayerStats> = self
+            .stats
+            .iter()
+            .filter(|(id, stats)| base.stats.get(*id) != Some(*stats))
+            .map(|(id, stats)| (id.to_owned(), stats.to_owned()))
+            .collect();
+
+        StateDelta {
+            spawn: if self.spawn != base.spawn {
+                Some(self.spawn.to_owned())
+            } else {
+                None
+            },
+            entities: self.entities.diff(&base.entities),
+            destroyed_entities: self.destroyed_entities.diff(&base.destroyed_entities).changed,
+            pending_spawns,
+            resolved_spawns,
+            stats,
+            elapsed: self.elapsed,
+            data: if self.data != base.data {
+                Some(self.data.to_owned())
+            } else {
+                None
+            },
+        }
+    }
+
+    //the changes since base as events, ordered so applying them to base gives self
+    pub fn events(&self, base: &SessionState) -> Vec<SessionEvent> {
+        let StateDelta {
+            spawn,
+            entities: EntitiesDelta { changed, removed },
+            destroyed_entities,
+            stats,
+            data,
+            ..
+        } = self.diff(base);
+
+        let (spawned, updated): (HashMap<EntityId, Entity>, HashMap<EntityId, Entity>) = changed
+            .0
+            .into_iter()
+            .partition(|(id, _)| !base.entities.0.contains_key(id));
+
+        let mut events = Vec::new();
+
+        if spawn.is_some() || data.is_some() {
+            events.push(SessionEvent::Scene { spawn, data });
+        }
+
+        if !spawned.is_empty() {
+            events.push(SessionEvent::Spawned {
+                entities: Entities(spawned),
+            });
+        }
+
+        if !updated.is_empty() {
+            events.push(SessionEvent::Updated {
+                entities: Entities(updated),
+            });
+        }
+
+        if !removed.is_empty() || !destroyed_entities.0.is_empty() {
+            events.push(SessionEvent::Killed {
+                entities: removed,
+                destroyed: destroyed_entities,
+            });
+        }
+
+        for (user_id, stats) in stats {
+            events.push(SessionEvent::Stats { user_id, stats });
+        }
+
+        events
+    }
+
+    //events that only record what happened leave the state untouched
+    pub fn apply(&mut self, event: &SessionEvent) {
+        match event {
+            SessionEvent::Spawned { entities } | SessionEvent::Updated { entities } => {
+                for (id, entity) in entities.0.iter() {
+                    self.entities.update(id.to_owned(), entity.to_owned());
+                }
+            }
+
+            SessionEvent::Killed {
+                entities,
+                destroyed,
+            } => {
+                for id in entities.iter() {
+                    self.entities.remove(id);
+                }
+
+                for (id, entity) in destroyed.0.iter() {
+                    self.destroyed_entities.update(id.to_owned(), entity.to_owned());
+                }
+            }
+
+            SessionEvent::Stats { user_id, stats } => {
+                self.stats.insert(user_id.to_owned(), stats.to_owned());
+            }
+
+            SessionEvent::Scene { spawn, data } => {
+                if let Some(spawn) = spawn {
+                    self.spawn = spawn.to_owned();
+                }
+
+                if let Some(data) = data {
+                    self.data = data.to_owned();
+                }
+            }
+
+            _ => {}
+        }
+    }
+}
+
+//an incremental change appended to session_events, replayed on top of the last snapshot
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Jsonb)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SessionEvent {
+    Spawned {
+        entities: Entities,
+    },
+    Updated {
+        entities: Entities,
+    },
+    Killed {
+        entities: HashSet<EntityId>,
+        #[serde(default = "Entities::default")]
+        destroyed: Entities,
+    },
+    Stats {
+        user_id: UserId,
+        stats: PlayerStats,
+    },
+    Scene {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        spawn: Option<Spawn>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        data: Option<Content>,
+    },
+    Status {
+        status: SessionStatus,
+    },
+    Chat {
+        sender: UserId,
+        msg: String,
+    },
+    Joined {
+        user_id: UserId,
+    },
+    Left {
+        user_id: UserId,
+    },
+    Kicked {
+        user_id: UserId,
+        by: UserId,
+    },
+    Whitelisted {
+        by: UserId,
+        added: Vec<UserId>,
+        removed: Vec<UserId>,
+    },
+    Paused {
+        by: UserId,
+        #[serde(default)]
+        for_duration: Option<Duration>,
+    },
+    Resumed {
+        by: UserId,
+    },
+    //ended by a player, or by the server when no one is named
+    Ended {
+        #[serde(default)]
+        by: Option<UserId>,
+    },
+    Resolved {
+        #[serde(default)]
+        pool_id: Option<String>,
+    },
+    Error {
+        #[serde(default)]
+        user_id: Option<UserId>,
+        message: String,
+    },
+    //an entry of the old logs column that did not match any event
+    Legacy {
+        entry: Value,
+    },
+}
+
+//kinds folded into the snapshot, the history leaves them out unless they are asked for
+pub const STATE_KINDS: [&str; 5] = ["spawned", "updated", "killed", "stats", "scene"];
+
+impl SessionEvent {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SessionEvent::Spawned { .. } => "spawned",
+            SessionEvent::Updated { .. } => "updated",
+            SessionEvent::Killed { .. } => "killed",
+            SessionEvent::Stats { .. } => "stats",
+            SessionEvent::Scene { .. } => "scene",
+            SessionEvent::Status { .. } => "status",
+            SessionEvent::Chat { .. } => "chat",
+            SessionEvent::Joined { .. } => "joined",
+            SessionEvent::Left { .. } => "left",
+            SessionEvent::Kicked { .. } => "kicked",
+            SessionEvent::Whitelisted { .. } => "whitelisted",
+            SessionEvent::Paused { .. } => "paused",
+            SessionEvent::Resumed { .. } => "resumed",
+            SessionEvent::Ended { .. } => "ended",
+            SessionEvent::Resolved { .. } => "resolved",
+            SessionEvent::Error { .. } => "error",
+            SessionEvent::Legacy { .. } => "legacy",
+        }
+    }
+
+    //the player an event is about, indexed so history can be filtered by it
+    pub fn player(&self) -> Option<&UserId> {
+        match self {
+            SessionEvent::Stats { user_id, .. }
+            | SessionEvent::Joined { user_id }
+            | SessionEvent::Left { user_id }
+            | SessionEvent::Kicked { user_id, .. } => Some(user_id),
+
+            SessionEvent::Chat { sender, .. } => Some(sender),
+
+            SessionEvent::Whitelisted { by, .. }
+            | SessionEvent::Paused { by, .. }
+            | SessionEvent::Resumed { by } => Some(by),
+
+            SessionEvent::Ended { by } => by.as_ref(),
+
+            SessionEvent::Error { user_id, .. } => user_id.as_ref(),
+
+            _ => None,
+        }
+    }
+}
+
+//events waiting to be appended to session_events, stamped when they happened
+#[derive(Debug, Clone, Default)]
+pub struct EventLog(pub Vec<(NaiveDateTime, SessionEvent)>);
+
+impl EventLog {
+    #[inline]
+    pub fn log(&mut self, event: SessionEvent) {
+        self.0.push((Local::now().naive_local(), event));
+    }
+
+    pub fn error(&mut self, user_id: &UserId, e: &ServerError) {
+        self.log(SessionEvent::Error {
+            user_id: Some(user_id.to_owned()),
+            message: e.to_string(),
+        });
+    }
+
+    pub fn take(&mut self) -> Vec<(NaiveDateTime, SessionEvent)> {
+        std::mem::take(&mut self.0)
+    }
+}
+
+impl ToSql<Jsonb, Pg> for SessionEvent {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        let event = to_value(&self).unwrap();
+
+        <Value as ToSql<Jsonb, Pg>>::to_sql(&event, &mut out.reborrow())
+    }
+}
+
+impl FromSql<Jsonb, Pg> for SessionEvent {
+    fn from_sql(bytes: PgValue) -> deserialize::Result<Self> {
+        let event = <Value as FromSql<Jsonb, Pg>>::from_sql(bytes)?;
+
+        match from_value::<SessionEvent>(event) {
+            Ok(event) => Ok(event),
+
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+}
+
+//what a session sent or accepted, in the order it happened, kept for replays
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Jsonb)]
+#[serde(tag = "frame", rename_all = "snake_case")]
+pub enum ReplayFrame {
+    //the full state, playback can start or seek from any of these
+    Keyframe {
+        state: SessionState,
+        players: HashMap<UserId, PlayerInfo>,
+        status: SessionStatus,
+    },
+    Update {
+        updater: UserId,
+        update: Update,
+    },
+    Status {
+        status: SessionStatus,
+    },
+}
+
+impl ToSql<Jsonb, Pg> for ReplayFrame {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        let frame = to_value(&self).unwrap();
+
+        <Value as ToSql<Jsonb, Pg>>::to_sql(&frame, &mut out.reborrow())
+    }
+}
+
+impl FromSql<Jsonb, Pg> for ReplayFrame {
+    fn from_sql(bytes: PgValue) -> deserialize::Result<Self> {
+        let frame = <Value as FromSql<Jsonb, Pg>>::from_sql(bytes)?;
+
+        match from_value::<ReplayFrame>(frame) {
+            Ok(frame) => Ok(frame),
+
+            Err(e) => Err(Box::new(e)),
         }
     }
 }
 
+//changes since an acknowledged tick, applied by clients on top of their copy of that tick
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct StateDelta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spawn: Option<Spawn>,
+    pub entities: EntitiesDelta,
+    #[serde(default = "Entities::default")]
+    pub destroyed_entities: Entities,
+    #[serde(default = "HashMap::new")]
+    pub pending_spawns: HashMap<EntityId, EntityId>,
+    #[serde(default = "HashSet::new")]
+    pub resolved_spawns: HashSet<EntityId>,
+    #[serde(default = "HashMap::new")]
+    pub stats: HashMap<UserId, PlayerStats>,
+    pub elapsed: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Content>,
+}
+
 impl ToSql<Jsonb, Pg> for SessionState
 where
     Value: ToSql<Jsonb, Pg>,
@@ -119,6 +483,8 @@ pub struct PlayerInfo {
     pub managed_entities: HashSet<EntityId>,
     pub stats: PlayerStats,
     pub status: ClientStatus,
+    #[serde(default)]
+    pub team: Option<i32>,
 }
 
 impl ToSql<Jsonb, Pg> for PlayerInfo
@@ -148,6 +514,7 @@ impl Default for PlayerInfo {
             managed_entities: HashSet::new(),
             stats: PlayerStats::default(),
             status: ClientStatus::Loading(Local::now().naive_local()),
+            team: None,
         }
     }
 }
@@ -197,6 +564,73 @@ pub struct GameConfig {
     pub session_attempts: Option<i64>,
     pub player_attempts: Option<i64>,
     pub duration: f32,
+    //rules for entity updates keyed by entity type, "*" applies to every type
+    #[serde(default = "GameConfig::default_entity_rules")]
+    pub entity_rules: HashMap<String, Vec<EntityRule>>,
+    #[serde(default)]
+    pub settlement: Settlement,
+    #[serde(default)]
+    pub pool_result: ResultStrategy,
+    //seconds spectators lag behind play, so what they see cannot be relayed to players
+    #[serde(default)]
+    pub spectator_delay: Option<f32>,
+}
+
+//how player outcomes are written to the chain when a session ends
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Settlement {
+    //one transaction per player
+    #[default]
+    PerPlayer,
+    //one transaction per session, with a function call action per player
+    Batched,
+}
+
+//xp earned by a surviving player, None for a death
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct PlayerOutcome {
+    pub account_id: AccountId,
+    pub xp: Option<u128>,
+}
+
+//how the winner of a session pool is picked from the session outcome
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+#[serde(tag = "strategy", rename_all = "snake_case")]
+pub enum ResultStrategy {
+    //survivors ranked by how long they stayed in the session
+    #[default]
+    LastSurvivor,
+    MostKills,
+    HighestXp,
+    //the team with the most survivors, ties broken by kills
+    TeamVictory,
+    //account ids ranked by the game script in SessionState.data under key
+    Script { key: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct PlayerResult {
+    pub account_id: AccountId,
+    pub team: Option<i32>,
+    pub stats: PlayerStats,
+    pub ended_at: NaiveDateTime,
+}
+
+//what a pool result strategy is computed from, players without an account are left out
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct SessionOutcome {
+    pub players: Vec<PlayerResult>,
+    pub data: Content,
+}
+
+impl GameConfig {
+    fn default_entity_rules() -> HashMap<String, Vec<EntityRule>> {
+        HashMap::from([(
+            "*".to_string(),
+            vec![EntityRule::FixedManager, EntityRule::SpawnManaged],
+        )])
+    }
 }
 
 impl ToSql<Jsonb, Pg> for GameConfig
@@ -229,6 +663,42 @@ impl Default for GameConfig {
             session_attempts: None,
             player_attempts: None,
             duration: 30.0,
+            entity_rules: GameConfig::default_entity_rules(),
+            settlement: Settlement::default(),
+            pool_result: ResultStrategy::default(),
+            spectator_delay: None,
+        }
+    }
+}
+
+//public view of a session, never includes the password or state
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct SessionInfo {
+    pub id: Uuid,
+    pub game_id: GameId,
+    pub pool_id: Option<String>,
+    pub creator: UserId,
+    pub private: bool,
+    pub protected: bool,
+    pub players: i64,
+    pub player_limit: i32,
+    pub created_at: NaiveDateTime,
+    pub started_at: Option<NaiveDateTime>,
+}
+
+impl SessionInfo {
+    pub fn new(session: &Session, config: &GameConfig, players: i64) -> Self {
+        Self {
+            id: session.id.to_owned(),
+            game_id: session.game_id.to_owned(),
+            pool_id: session.pool_id.to_owned(),
+            creator: session.creator.to_owned(),
+            private: session.private,
+            protected: session.password.is_some(),
+            players,
+            player_limit: config.player_limit,
+            created_at: session.created_at.to_owned(),
+            started_at: session.started_at.to_owned(),
         }
     }
 }
@@ -290,6 +760,34 @@ impl Entities {
     pub fn remove(&mut self, id: &EntityId) -> Option<Entity> {
         self.0.remove(id)
     }
+
+    pub fn diff(&self, base: &Entities) -> EntitiesDelta {
+        let changed: HashMap<EntityId, Entity> = self
+            .0
+            .iter()
+            .filter(|(id, entity)| base.0.get(*id) != Some(*entity))
+            .map(|(id, entity)| (id.to_owned(), entity.to_owned()))
+            .collect();
+
+        let removed: HashSet<EntityId> = base
+            .0
+            .keys()
+            .filter(|id| !self.0.contains_key(*id))
+            .copied()
+            .collect();
+
+        EntitiesDelta {
+            changed: Entities(changed),
+            removed,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct EntitiesDelta {
+    pub changed: Entities,
+    #[serde(default = "HashSet::new")]
+    pub removed: HashSet<EntityId>,
 }
 
 impl Default for Entities {
@@ -353,6 +851,28 @@ impl Hash for Position {
     }
 }
 
+impl Position {
+    pub fn distance(&self, other: &Position) -> f64 {
+        ((other.x - self.x).powi(2) + (other.y - self.y).powi(2)).sqrt()
+    }
+
+    //moves towards the target by at most max_distance
+    pub fn towards(&self, target: &Position, max_distance: f64) -> Position {
+        let distance = self.distance(target);
+
+        if distance <= max_distance {
+            return target.to_owned();
+        }
+
+        let scale = max_distance / distance;
+
+        Position {
+            x: self.x + (target.x - self.x) * scale,
+            y: self.y + (target.y - self.y) * scale,
+        }
+    }
+}
+
 impl Default for Position {
     fn default() -> Self {
         Self { x: 0.0, y: 0.0 }
@@ -375,40 +895,156 @@ impl Default for Spawn {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum EntityRule {
+    //clamps the distance an entity can move per tick since its last accepted update
+    MaxMovement(f64),
+    //max live entities of the type managed by one player
+    SpawnLimit(usize),
+    //updates cannot reassign the entity manager
+    FixedManager,
+    //spawns must be managed by the player spawning them
+    SpawnManaged,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum RewardKind {
+    Xp,
+    Death,
+    Stakes,
+}
+
+//a contract interaction made for an account, one rewards row each
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Reward {
+    pub kind: RewardKind,
+    pub session_id: Uuid,
+    pub tx_hash: Option<String>,
+    //xp earned, none for deaths and for stakes, whose amounts the contract decides
+    #[serde(default)]
+    pub amount: Option<u128>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pool_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct RewardEntry {
+    pub account_id: String,
+    pub at: NaiveDateTime,
+    pub reward: Value,
+}
+
+//a pending on-chain side effect, persisted in chain_outbox until it succeeds
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone, AsExpression, FromSqlRow)]
 #[diesel(sql_type = Jsonb)]
-pub struct Logs(pub HashMap<NaiveDateTime, Value>);
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ChainOperation {
+    GiveXp {
+        session_id: Uuid,
+        account_id: AccountId,
+        xp: u128,
+    },
+    KillCharacter {
+        session_id: Uuid,
+        account_id: AccountId,
+    },
+    AssertPoolResult {
+        session_id: Uuid,
+        pool_id: String,
+        strategy: ResultStrategy,
+        outcome: SessionOutcome,
+    },
+    DistributeStakes {
+        session_id: Uuid,
+        pool_id: String,
+    },
+    SettlePlayers {
+        session_id: Uuid,
+        outcomes: Vec<PlayerOutcome>,
+    },
+    //None for a pool whose session was never created
+    DeactivatePool {
+        session_id: Option<Uuid>,
+        pool_id: String,
+    },
+}
 
-impl ToSql<Jsonb, Pg> for Logs {
-    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
-        let logs = to_value(&self).unwrap();
+impl ChainOperation {
+    //one entry per effect, re-enqueueing the same effect is a no-op
+    pub fn idempotency_key(&self) -> String {
+        match self {
+            ChainOperation::GiveXp {
+                session_id,
+                account_id,
+                ..
+            } => format!("xp:{}:{}", session_id, account_id),
+
+            ChainOperation::KillCharacter {
+                session_id,
+                account_id,
+            } => format!("death:{}:{}", session_id, account_id),
+
+            ChainOperation::AssertPoolResult { pool_id, .. } => format!("pool_result:{}", pool_id),
+
+            ChainOperation::DistributeStakes { pool_id, .. } => {
+                format!("distribute:{}", pool_id)
+            }
+
+            //the same players settle under the same key whichever batch they ended up in
+            ChainOperation::SettlePlayers {
+                session_id,
+                outcomes,
+            } => {
+                let mut accounts: Vec<&str> = outcomes
+                    .iter()
+                    .map(|outcome| outcome.account_id.as_str())
+                    .collect();
+
+                accounts.sort();
+
+                format!(
+                    "settle:{}:{}",
+                    session_id,
+                    CryptoHash::hash_bytes(accounts.join(",").as_bytes())
+                )
+            }
 
-        <Value as ToSql<Jsonb, Pg>>::to_sql(&logs, &mut out.reborrow())
+            ChainOperation::DeactivatePool { pool_id, .. } => format!("deactivate:{}", pool_id),
+        }
     }
-}
 
-impl FromSql<Jsonb, Pg> for Logs {
-    fn from_sql(bytes: PgValue) -> deserialize::Result<Self> {
-        match from_slice::<Logs>(bytes.as_bytes()) {
-            Ok(logs) => Ok(logs),
+    pub fn session_id(&self) -> Option<Uuid> {
+        match self {
+            ChainOperation::GiveXp { session_id, .. }
+            | ChainOperation::KillCharacter { session_id, .. }
+            | ChainOperation::AssertPoolResult { session_id, .. }
+            | ChainOperation::DistributeStakes { session_id, .. }
+            | ChainOperation::SettlePlayers { session_id, .. } => Some(session_id.to_owned()),
 
-            Err(_) => Ok(Logs::new()),
+            ChainOperation::DeactivatePool { session_id, .. } => session_id.to_owned(),
         }
     }
 }
 
-impl Logs {
-    pub fn new() -> Self {
-        Self(HashMap::new())
+impl ToSql<Jsonb, Pg> for ChainOperation {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        let operation = to_value(&self).unwrap();
+
+        <Value as ToSql<Jsonb, Pg>>::to_sql(&operation, &mut out.reborrow())
     }
+}
 
-    #[inline]
-    pub fn log<V>(&mut self, v: &V)
-    where
-        V: ?Sized + Serialize,
-    {
-        self.0
-            .insert(Local::now().naive_local(), to_value(&v).unwrap());
+impl FromSql<Jsonb, Pg> for ChainOperation {
+    fn from_sql(bytes: PgValue) -> deserialize::Result<Self> {
+        let operation = <Value as FromSql<Jsonb, Pg>>::from_sql(bytes)?;
+
+        match from_value::<ChainOperation>(operation) {
+            Ok(operation) => Ok(operation),
+
+            Err(e) => Err(Box::new(e)),
+        }
     }
 }
 